@@ -0,0 +1,71 @@
+//! Stepping ambient light averaging in response to saturation, for
+//! `--ambient-autorange`.
+//!
+//! The ambient parameter register doesn't expose a gain as such, so the
+//! averaging is the setting that's stepped: each saturated reading (at most
+//! once per `STEP_INTERVAL`, so the new setting has time to take effect)
+//! halves it, down to a single conversion. Once readings have stayed below
+//! `RESTORE_BELOW` for `RESTORE_AFTER`, it is doubled again, one step at a
+//! time, back up to the averaging the sensor started with. The gap between
+//! full scale and `RESTORE_BELOW` keeps the two from flapping.
+
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// Readings at or above this are saturated.
+const SATURATED: u32 = u16::MAX as u32;
+
+/// Readings must stay below this before averaging is stepped back up.
+const RESTORE_BELOW: u32 = SATURATED / 2;
+
+/// How long readings must stay below `RESTORE_BELOW` before each step up.
+const RESTORE_AFTER: Duration = Duration::from_secs(60);
+
+/// The minimum time between steps down.
+const STEP_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub struct AmbientAutorange {
+    /// The averaging exponent configured at startup, which is never exceeded.
+    max: u8,
+
+    /// The current averaging exponent, i.e. 2^n conversions.
+    averaging: u8,
+
+    last_step: Option<Instant>,
+    unsaturated_since: Option<Instant>,
+}
+
+impl AmbientAutorange {
+    pub fn new(averaging: u8) -> AmbientAutorange {
+        AmbientAutorange { max: averaging, averaging, last_step: None, unsaturated_since: None }
+    }
+
+    /// Records an ambient reading, returning the new averaging exponent if it
+    /// should change.
+    pub fn observe(&mut self, ambient: u32, clock: &impl Clock) -> Option<u8> {
+        let now = clock.now();
+        if ambient < RESTORE_BELOW {
+            let since = *self.unsaturated_since.get_or_insert(now);
+            if self.averaging < self.max && clock.since(since) >= RESTORE_AFTER {
+                self.averaging += 1;
+                self.unsaturated_since = Some(now);
+                self.last_step = Some(now);
+                return Some(self.averaging);
+            }
+
+            return None;
+        }
+
+        self.unsaturated_since = None;
+        let settled = self.last_step.is_none_or(|t| clock.since(t) >= STEP_INTERVAL);
+        if ambient >= SATURATED && self.averaging > 0 && settled {
+            self.averaging -= 1;
+            self.last_step = Some(now);
+            return Some(self.averaging);
+        }
+
+        None
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+use color_eyre::Result;
+use serde_derive::Serialize;
+use vcnl4010::ProximitySensor;
+
+use crate::conversion::ConversionTiming;
+
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    pub reads: usize,
+    pub duration_secs: f64,
+    pub reads_per_sec: f64,
+    pub p50_latency_us: u128,
+    pub p99_latency_us: u128,
+    pub max_latency_us: u128,
+
+    /// Measured conversion periods, if self-timed measurements are running.
+    pub conversion: Option<ConversionTiming>,
+}
+
+/// Issues back-to-back combined proximity and ambient reads for the given
+/// duration, measuring the latency of each. The sensor's configuration is not
+/// modified.
+pub fn run(sensor: &mut ProximitySensor, duration: Duration) -> Result<BenchResult> {
+    let mut latencies = Vec::new();
+
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let t = Instant::now();
+        sensor.read_proximity()?;
+        sensor.read_ambient_light()?;
+        latencies.push(t.elapsed());
+    }
+    let elapsed = start.elapsed();
+
+    latencies.sort_unstable();
+    let percentile = |p: f64| {
+        let i = ((latencies.len() as f64 * p).ceil() as usize).clamp(1, latencies.len()) - 1;
+        latencies[i].as_micros()
+    };
+
+    Ok(BenchResult {
+        reads: latencies.len(),
+        duration_secs: elapsed.as_secs_f64(),
+        reads_per_sec: latencies.len() as f64 / elapsed.as_secs_f64(),
+        p50_latency_us: percentile(0.50),
+        p99_latency_us: percentile(0.99),
+        max_latency_us: latencies.last().map(|d| d.as_micros()).unwrap_or_default(),
+        conversion: None,
+    })
+}
+
+impl BenchResult {
+    pub fn print_table(&self) {
+        println!("{:<16} {:>12}", "reads", self.reads);
+        println!("{:<16} {:>12.2}", "duration (s)", self.duration_secs);
+        println!("{:<16} {:>12.1}", "reads/sec", self.reads_per_sec);
+        println!("{:<16} {:>12}", "p50 latency (us)", self.p50_latency_us);
+        println!("{:<16} {:>12}", "p99 latency (us)", self.p99_latency_us);
+        println!("{:<16} {:>12}", "max latency (us)", self.max_latency_us);
+        if let Some(conversion) = &self.conversion {
+            println!("{}", conversion.summary());
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::str::FromStr;
+
+/// A brightness level given either as an absolute sysfs value or as a
+/// percentage of the display's `max_brightness`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrightnessLevel {
+    Absolute(u32),
+    Percent(f32),
+}
+
+impl BrightnessLevel {
+    /// Resolves this level to an absolute brightness value for a display with
+    /// the given maximum brightness.
+    pub fn resolve(self, max_brightness: u32) -> u32 {
+        match self {
+            BrightnessLevel::Absolute(v) => v.min(max_brightness),
+            BrightnessLevel::Percent(p) => {
+                ((max_brightness as f32 * p / 100.0).round() as u32).min(max_brightness)
+            },
+        }
+    }
+}
+
+impl FromStr for BrightnessLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(percent) = s.strip_suffix('%') {
+            let p = f32::from_str(percent.trim())
+                .map_err(|_| format!("Invalid percentage: {s}"))?;
+            if !(0.0..=100.0).contains(&p) {
+                return Err(format!("Percentage out of range (0-100%): {s}"));
+            }
+
+            Ok(BrightnessLevel::Percent(p))
+        } else {
+            let v = u32::from_str(s.trim())
+                .map_err(|_| format!("Invalid brightness value: {s}"))?;
+
+            Ok(BrightnessLevel::Absolute(v))
+        }
+    }
+}
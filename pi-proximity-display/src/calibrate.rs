@@ -0,0 +1,174 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use serde_derive::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use vcnl4010::ProximitySensor;
+
+/// Full scale proximity reading.
+const PROXIMITY_FULL_SCALE: u32 = u16::MAX as u32;
+
+/// Step by which `--auto-led` reduces the LED current, in mA.
+const AUTO_LED_STEP_MA: u16 = 20;
+
+/// Lowest LED current `--auto-led` will reduce to, in mA.
+const AUTO_LED_MIN_MA: u16 = 10;
+
+/// Interval between samples while measuring the baseline.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Measures the mean proximity reading over the given duration. This should
+/// be done with nothing in front of the sensor.
+pub fn measure_baseline(sensor: &mut ProximitySensor, duration: Duration) -> Result<u32> {
+    let samples = (duration.as_millis() / SAMPLE_INTERVAL.as_millis()).max(1) as u64;
+
+    let mut sum = 0u64;
+    for _ in 0..samples {
+        sum += sensor.read_proximity()? as u64;
+        thread::sleep(SAMPLE_INTERVAL);
+    }
+
+    Ok((sum / samples) as u32)
+}
+
+/// Measures the empty-scene proximity baseline and warns if it is above the
+/// given fraction of full scale, which usually means IR from the LED is being
+/// reflected back by the enclosure. If `auto_led` is set, the LED current is
+/// stepped down until the baseline is acceptable. Returns the final LED
+/// current in mA and the baseline measured at that current.
+pub fn check_baseline(
+    sensor: &mut ProximitySensor,
+    led_current_ma: u16,
+    duration: Duration,
+    max_fraction: f32,
+    auto_led: bool,
+) -> Result<(u16, u32)> {
+    let limit = (PROXIMITY_FULL_SCALE as f32 * max_fraction) as u32;
+
+    let baseline = measure_baseline(sensor, duration)?;
+    info!("proximity baseline: {baseline} at {led_current_ma}mA (limit: {limit})");
+
+    if baseline <= limit {
+        return Ok((led_current_ma, baseline));
+    }
+
+    if !auto_led {
+        warn!(
+            "proximity baseline {baseline} at {led_current_ma}mA is near full scale, presence \
+            detection may not work; this usually indicates IR reflecting off the enclosure. \
+            Try a lower --proximity-led-current, or pass --auto-led to reduce it automatically."
+        );
+        return Ok((led_current_ma, baseline));
+    }
+
+    let mut current = led_current_ma;
+    let mut baseline = baseline;
+    while baseline > limit && current > AUTO_LED_MIN_MA {
+        current = current.saturating_sub(AUTO_LED_STEP_MA).max(AUTO_LED_MIN_MA);
+        sensor.set_led_current_ma(current)?;
+
+        baseline = measure_baseline(sensor, duration)?;
+        info!("proximity baseline: {baseline} at {current}mA");
+    }
+
+    if baseline > limit {
+        warn!("proximity baseline {baseline} still near full scale at minimum LED current {current}mA");
+    } else {
+        info!("reduced LED current from {led_current_ma}mA to {current}mA, baseline now {baseline}");
+    }
+
+    Ok((current, baseline))
+}
+
+/// A proximity baseline persisted across restarts.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StoredBaseline {
+    pub proximity: u32,
+    pub led_current_ma: u16,
+}
+
+impl StoredBaseline {
+    /// Loads a baseline from the given file, returning `None` (with a warning
+    /// for anything other than a missing file) if it can't be used.
+    pub fn load(path: &Path) -> Option<StoredBaseline> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                info!("no stored baseline at {} ({e})", path.display());
+                return None;
+            },
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(baseline) => {
+                info!("loaded baseline from {}: {baseline:?}", path.display());
+                Some(baseline)
+            },
+            Err(e) => {
+                warn!("stored baseline at {} is invalid, recalibrating: {e}", path.display());
+                None
+            },
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        info!("saved baseline to {}: {self:?}", path.display());
+
+        Ok(())
+    }
+}
+
+/// Weight of each new idle sample in the baseline's moving average. Small so
+/// the baseline tracks slow drift (e.g. temperature) but not brief events.
+const BASELINE_ALPHA: f32 = 0.001;
+
+/// How often an updated baseline is written back to disk.
+const BASELINE_SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Slowly refines the proximity baseline from readings taken while idle, and
+/// periodically persists it.
+#[derive(Debug)]
+pub struct BaselineTracker {
+    stored: StoredBaseline,
+    average: f32,
+    path: Option<PathBuf>,
+    last_save: Instant,
+}
+
+impl BaselineTracker {
+    pub fn new(stored: StoredBaseline, path: Option<PathBuf>) -> BaselineTracker {
+        BaselineTracker {
+            stored,
+            average: stored.proximity as f32,
+            path,
+            last_save: Instant::now(),
+        }
+    }
+
+    /// The current baseline proximity value.
+    pub fn baseline(&self) -> u32 {
+        self.stored.proximity
+    }
+
+    /// Feeds a proximity reading taken while nothing should be present.
+    pub fn observe_idle(&mut self, proximity: u32) {
+        self.average += (proximity as f32 - self.average) * BASELINE_ALPHA;
+        self.stored.proximity = self.average.round() as u32;
+
+        if self.last_save.elapsed() >= BASELINE_SAVE_INTERVAL {
+            self.last_save = Instant::now();
+            if let Some(path) = &self.path {
+                debug!("refreshing stored baseline");
+                if let Err(e) = self.stored.save(path) {
+                    warn!("could not save baseline to {}: {e}", path.display());
+                }
+            }
+        }
+    }
+}
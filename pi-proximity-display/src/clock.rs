@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+/// A source of the current time, so time-dependent logic can be driven by
+/// something other than the system clock.
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// Time elapsed since `earlier`, saturating at zero.
+    fn since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// The real monotonic system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when advanced, for tests.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock {
+    now: std::cell::Cell<Instant>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock { now: std::cell::Cell::new(Instant::now()) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
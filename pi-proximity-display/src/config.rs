@@ -0,0 +1,290 @@
+//! Support for loading settings from a JSON config file.
+//!
+//! The config file is an object whose keys are the long names of command line
+//! flags, with values given as they would be on the command line, e.g.:
+//!
+//! ```json
+//! {
+//!   "proximity-range": "2930..2975",
+//!   "proximity-hold": "30s",
+//!   "brightness-range": "1..31",
+//!   "wlopm-extra-arg": ["--json"],
+//!   "displays": {
+//!     "DSI-1": { "brightness-range": "1..255", "brightness-cap": "80%" }
+//!   }
+//! }
+//! ```
+//!
+//! Boolean flags are set with `true`, and repeatable flags may be given as an
+//! array. The optional `displays` object contains per-display overrides keyed
+//! by display name; see `DISPLAY_KEYS` for the settings it may contain.
+//!
+//! Values are passed through the same parsers as the command line so they
+//! behave identically, and anything given explicitly on the command line takes
+//! precedence over the config file.
+//!
+//! Every flag may also be set with an environment variable named after its
+//! long name in upper case with a `PPD_` prefix, e.g. `PPD_PROXIMITY_RANGE`
+//! for `--proximity-range` (see `env_var`). Environment variables take
+//! precedence over the config file, but not over the command line. Boolean
+//! flags accept values like `true` or `false`.
+
+use std::{collections::BTreeMap, ffi::OsString, fs, path::{Path, PathBuf}};
+
+use clap::{parser::ValueSource, ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches};
+use color_eyre::{eyre::eyre, Result};
+use serde_json::{Map, Value};
+use tracing::warn;
+
+use crate::profile::Profile;
+
+/// Settings that may be overridden per display in the `displays` section.
+pub const DISPLAY_KEYS: &[&str] = &[
+    "ambient-light-range",
+    "brightness-range",
+    "brightness-cap",
+];
+
+/// Flags that control the program itself rather than being settings, and so
+/// are left out of `render_effective`.
+const NOT_SETTINGS: &[&str] = &["config", "print-config", "help", "version"];
+
+/// Flags with no environment variable.
+const NO_ENV: &[&str] = &["help", "version"];
+
+/// Returns the environment variable for a flag's long name.
+pub fn env_var(long: &str) -> String {
+    format!("PPD_{}", long.to_uppercase().replace('-', "_"))
+}
+
+/// Returns the command for `A`, with an environment variable added to each
+/// flag (including in subcommands).
+fn command<A: CommandFactory>() -> Command {
+    with_env(A::command())
+}
+
+fn with_env(command: Command) -> Command {
+    let subcommands: Vec<String> = command.get_subcommands().map(|s| s.get_name().to_string()).collect();
+    let command = command.mut_args(|arg| match arg.get_long() {
+        Some(long) if !NO_ENV.contains(&long) => {
+            let env = env_var(long);
+            arg.env(env)
+        },
+        _ => arg,
+    });
+
+    subcommands
+        .iter()
+        .fold(command, |command, name| command.mut_subcommand(name, with_env))
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    /// Global settings, keyed by long flag name.
+    pub settings: Map<String, Value>,
+
+    /// Per-display overrides, keyed by display name.
+    pub displays: BTreeMap<String, Map<String, Value>>,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Config> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| eyre!("could not read config file {}: {e}", path.display()))?;
+
+        let mut settings: Map<String, Value> = serde_json::from_str(&contents)
+            .map_err(|e| eyre!("could not parse config file {}: {e}", path.display()))?;
+
+        let mut displays = BTreeMap::new();
+        if let Some(value) = settings.remove("displays") {
+            let Value::Object(sections) = value else {
+                return Err(eyre!("config 'displays' must be an object"));
+            };
+
+            for (name, section) in sections {
+                let Value::Object(section) = section else {
+                    return Err(eyre!("config section for display {name} must be an object"));
+                };
+
+                if let Some(key) = section.keys().find(|k| !DISPLAY_KEYS.contains(&k.as_str())) {
+                    return Err(eyre!(
+                        "'{key}' can't be set per display {name}, expected one of: {}",
+                        DISPLAY_KEYS.join(", ")
+                    ));
+                }
+
+                displays.insert(name, section);
+            }
+        }
+
+        Ok(Config { settings, displays })
+    }
+
+    /// Returns the per-display section matching the given name, if any.
+    pub fn display_section(&self, name: &str) -> Option<&Map<String, Value>> {
+        self.displays
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, section)| section)
+    }
+
+    /// Applies a profile's settings beneath this config's own global settings.
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        for (key, value) in profile.settings {
+            let value = match *value {
+                "true" => Value::Bool(true),
+                value => Value::String(value.to_string()),
+            };
+
+            self.settings.entry(key.to_string()).or_insert(value);
+        }
+    }
+
+    /// Warns about any per-display sections that don't match a known display.
+    pub fn warn_unknown_displays(&self, known: &[String]) {
+        for name in self.displays.keys() {
+            if !known.iter().any(|k| k.eq_ignore_ascii_case(name)) {
+                warn!("config has a section for unknown display {name}, ignoring (known: {known:?})");
+            }
+        }
+    }
+
+    /// Returns the global settings overlaid with the section for the named
+    /// display, if any.
+    fn settings_for(&self, display: Option<&str>) -> Map<String, Value> {
+        let mut settings = self.settings.clone();
+        if let Some(section) = display.and_then(|d| self.display_section(d)) {
+            settings.extend(section.clone());
+        }
+
+        settings
+    }
+
+    /// Loads the config file named by the `--config` argument in `argv`, if
+    /// any, returning an empty config otherwise.
+    pub fn from_args<A: CommandFactory>(argv: &[OsString]) -> Result<Config> {
+        let path = command::<A>()
+            .ignore_errors(true)
+            .try_get_matches_from(argv)
+            .ok()
+            .and_then(|m| m.get_one::<PathBuf>("config").cloned());
+
+        match path {
+            Some(path) => Config::load(path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// Parses arguments from `argv`, using settings from this config (and the
+    /// section for `display`, if given) for any arguments that weren't
+    /// explicitly provided on the command line.
+    pub fn parse_args<A>(&self, argv: &[OsString], display: Option<&str>) -> Result<A>
+    where
+        A: CommandFactory + FromArgMatches,
+    {
+        let matches = self.merged_matches::<A>(argv, display)?;
+        Ok(A::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()))
+    }
+
+    /// Renders the effective settings (this config merged with `argv`) in the
+    /// config file format, so they can be loaded again with `--config`.
+    /// Per-display sections are carried over as-is.
+    pub fn render_effective<A: CommandFactory>(&self, argv: &[OsString]) -> Result<String> {
+        let matches = self.merged_matches::<A>(argv, None)?;
+
+        let mut settings = Map::new();
+        for arg in command::<A>().get_arguments() {
+            let Some(long) = arg.get_long() else {
+                continue;
+            };
+
+            let id = arg.get_id().as_str();
+            if NOT_SETTINGS.contains(&long) {
+                continue;
+            }
+
+            let value = match arg.get_action() {
+                ArgAction::SetTrue => Value::Bool(matches.get_flag(id)),
+                ArgAction::Append => match matches.get_raw(id) {
+                    Some(values) => values.map(|v| Value::String(v.to_string_lossy().into_owned())).collect(),
+                    None => continue,
+                },
+                _ => match matches.get_raw(id).and_then(|mut v| v.next()) {
+                    Some(value) => Value::String(value.to_string_lossy().into_owned()),
+                    None => continue,
+                },
+            };
+
+            settings.insert(long.to_string(), value);
+        }
+
+        if !self.displays.is_empty() {
+            let displays = self.displays
+                .iter()
+                .map(|(name, section)| (name.clone(), Value::Object(section.clone())))
+                .collect();
+            settings.insert("displays".to_string(), Value::Object(displays));
+        }
+
+        Ok(serde_json::to_string_pretty(&settings)?)
+    }
+
+    fn merged_matches<A: CommandFactory>(&self, argv: &[OsString], display: Option<&str>) -> Result<ArgMatches> {
+        let settings = self.settings_for(display);
+        let command = command::<A>();
+
+        // parse leniently first just to see what was given explicitly; any
+        // real errors are reported by the final parse
+        let explicit = command
+            .clone()
+            .ignore_errors(true)
+            .try_get_matches_from(argv)
+            .ok();
+
+        let mut merged: Vec<OsString> = argv.iter().take(1).cloned().collect();
+        for (key, value) in &settings {
+            let arg = command
+                .get_arguments()
+                .find(|a| a.get_long() == Some(key.as_str()))
+                .ok_or_else(|| eyre!("unknown config setting '{key}'"))?;
+
+            let id = arg.get_id().as_str();
+            let given = explicit
+                .as_ref()
+                .and_then(|m| m.value_source(id))
+                .is_some_and(|s| s != ValueSource::DefaultValue);
+            if given {
+                continue;
+            }
+
+            let flag = format!("--{key}");
+            match (arg.get_action(), value) {
+                (ArgAction::SetTrue, Value::Bool(true)) => merged.push(flag.into()),
+                (ArgAction::SetTrue, Value::Bool(false)) => (),
+                (ArgAction::SetTrue, _) => {
+                    return Err(eyre!("config setting '{key}' must be true or false"));
+                },
+                (_, Value::Array(values)) => {
+                    for value in values {
+                        merged.push(format!("{flag}={}", scalar(key, value)?).into());
+                    }
+                },
+                (_, value) => merged.push(format!("{flag}={}", scalar(key, value)?).into()),
+            }
+        }
+        merged.extend(argv.iter().skip(1).cloned());
+
+        Ok(command.get_matches_from(merged))
+    }
+}
+
+/// Formats a scalar config value as it would be given on the command line.
+fn scalar(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(eyre!("config setting '{key}' must be a string, number, or boolean")),
+    }
+}
@@ -0,0 +1,202 @@
+//! A minimal HTTP API for overriding display power and brightness at runtime.
+//!
+//! Endpoints take a plain text body:
+//!
+//! * `POST /power` with `on`, `off`, or `auto`
+//! * `POST /brightness` with a brightness value or `auto`
+//!
+//! `auto` returns control to the proximity state machine or ambient mapping,
+//! respectively. `GET /overrides` returns the current overrides as JSON, and
+//! `GET /counters` the daemon's health counters.
+//!
+//! This only implements as much HTTP/1.1 as needed for tools like `curl`: each
+//! connection carries one request and is then closed. Connections are handled
+//! synchronously from the sensor loop, so a slow client can delay a poll by up
+//! to `CLIENT_TIMEOUT`. There is no authentication, so it should only be bound
+//! to localhost or a trusted network.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    time::Duration,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use tracing::{debug, info, warn};
+
+use crate::{display::DisplayPowerMode, overrides::Overrides, stats::Counters};
+
+/// How long to wait on a client before giving up on its request.
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Largest request body accepted.
+const MAX_BODY: usize = 64;
+
+/// Parses a `--control-addr`, either a full socket address or just a port to
+/// bind on localhost.
+pub fn parse_control_addr(s: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = u16::from_str(s) {
+        return Ok(SocketAddr::from(([127, 0, 0, 1], port)));
+    }
+
+    SocketAddr::from_str(s).map_err(|_| format!("Invalid address (expected host:port or port): {s}"))
+}
+
+pub struct ControlServer {
+    listener: TcpListener,
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn new(status: &'static str, body: impl Into<String>) -> Response {
+        Response { status, body: body.into() }
+    }
+}
+
+impl ControlServer {
+    pub fn bind(addr: SocketAddr) -> Result<ControlServer> {
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| eyre!("could not bind control api to {addr}: {e}"))?;
+        listener.set_nonblocking(true)?;
+        info!("serving control api on http://{addr}");
+
+        Ok(ControlServer { listener })
+    }
+
+    /// Handles any pending requests, applying them to `overrides`.
+    /// `max_brightness` bounds brightness overrides.
+    pub fn poll(&mut self, overrides: &mut Overrides, max_brightness: u32, counters: &Counters) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    if let Err(e) = handle(stream, overrides, max_brightness, counters) {
+                        debug!("control api client {peer} failed: {e}");
+                    }
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("error accepting control api client: {e}");
+                    break;
+                },
+            }
+        }
+    }
+}
+
+fn handle(stream: TcpStream, overrides: &mut Overrides, max_brightness: u32, counters: &Counters) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Ok((method, path, body)) => route(&method, &path, body.trim(), overrides, max_brightness, counters),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::new("400 Bad Request", e.to_string()),
+        Err(e) => return Err(e),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len() + 1,
+        response.body
+    )?;
+    stream.write_all(b"\n")
+}
+
+/// Reads a request line, headers, and body, returning (method, path, body).
+fn read_request(reader: &mut impl BufRead) -> io::Result<(String, String, String)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| invalid("invalid content-length"))?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Err(invalid("request body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|_| invalid("request body must be utf-8"))?;
+
+    Ok((method, path, body))
+}
+
+fn route(
+    method: &str,
+    path: &str,
+    body: &str,
+    overrides: &mut Overrides,
+    max_brightness: u32,
+    counters: &Counters,
+) -> Response {
+    match (method, path) {
+        ("POST", "/power") => {
+            let power = match body {
+                "on" => Some(DisplayPowerMode::On),
+                "off" => Some(DisplayPowerMode::Off),
+                "auto" => None,
+                _ => return Response::new("400 Bad Request", "expected on, off, or auto"),
+            };
+
+            info!("control api: power override set to {body}");
+            overrides.power = power;
+            Response::new("200 OK", body)
+        },
+        ("POST", "/brightness") => {
+            let brightness = match body {
+                "auto" => None,
+                value => match u32::from_str(value) {
+                    Ok(b) if b <= max_brightness => Some(b),
+                    Ok(_) => {
+                        return Response::new(
+                            "422 Unprocessable Entity",
+                            format!("brightness must be at most {max_brightness}"),
+                        );
+                    },
+                    Err(_) => return Response::new("400 Bad Request", "expected a brightness value or auto"),
+                },
+            };
+
+            info!("control api: brightness override set to {body}");
+            overrides.brightness = brightness;
+            Response::new("200 OK", body)
+        },
+        ("GET", "/overrides") => match serde_json::to_string(overrides) {
+            Ok(json) => Response::new("200 OK", json),
+            Err(e) => Response::new("500 Internal Server Error", e.to_string()),
+        },
+        ("GET", "/counters") => match serde_json::to_string(counters) {
+            Ok(json) => Response::new("200 OK", json),
+            Err(e) => Response::new("500 Internal Server Error", e.to_string()),
+        },
+        (_, "/power" | "/brightness" | "/overrides" | "/counters") => Response::new("405 Method Not Allowed", "method not allowed"),
+        _ => Response::new("404 Not Found", "not found"),
+    }
+}
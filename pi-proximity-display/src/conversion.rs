@@ -0,0 +1,129 @@
+//! Timing self-timed conversions against the configured measurement rates.
+//!
+//! With high ambient light averaging, conversions can take much longer than
+//! the configured rates imply, and the proximity channel is starved while the
+//! ambient conversion runs. Reads then keep returning the same not-yet-updated
+//! results, which is hard to tell apart from a dead sensor, so the measured
+//! period of each channel is compared to its nominal one.
+
+use std::time::Duration;
+
+use color_eyre::Result;
+use serde_derive::Serialize;
+use tracing::{info, warn};
+use vcnl4010::{Channel, ProximitySensor};
+
+/// Number of conversions timed per channel; the median is reported.
+const SAMPLES: usize = 3;
+
+/// Conversions taking longer than this multiple of the nominal period are
+/// reported as slow.
+const SLOW_FACTOR: f64 = 1.5;
+
+/// How many nominal periods to wait for a conversion before giving up.
+const TIMEOUT_PERIODS: u32 = 4;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ChannelTiming {
+    /// The nominal period for the configured rate.
+    pub expected_ms: f64,
+
+    /// The median measured period, or `None` if conversions timed out.
+    pub measured_ms: Option<f64>,
+}
+
+impl ChannelTiming {
+    fn is_slow(&self) -> bool {
+        self.measured_ms.is_none_or(|m| m > self.expected_ms * SLOW_FACTOR)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ConversionTiming {
+    /// Proximity timing, unless proximity measurements are disabled.
+    pub proximity: Option<ChannelTiming>,
+    pub ambient: ChannelTiming,
+
+    /// Number of conversions averaged into each ambient light result.
+    pub ambient_averaging: u32,
+}
+
+fn time_channel(sensor: &mut ProximitySensor, channel: Channel, expected: Duration) -> Result<ChannelTiming> {
+    let mut periods = Vec::with_capacity(SAMPLES);
+    for _ in 0..SAMPLES {
+        match sensor.time_conversion(channel, expected * TIMEOUT_PERIODS)? {
+            Some(period) => periods.push(period),
+            None => break,
+        }
+    }
+
+    let measured = if periods.len() == SAMPLES {
+        periods.sort_unstable();
+        Some(periods[SAMPLES / 2])
+    } else {
+        None
+    };
+
+    Ok(ChannelTiming {
+        expected_ms: expected.as_secs_f64() * 1000.0,
+        measured_ms: measured.map(|d| d.as_secs_f64() * 1000.0),
+    })
+}
+
+/// Times a few conversions on each channel, skipping proximity unless
+/// `proximity` is set. Self-timed measurements must be running; this takes a
+/// few measurement periods of each channel.
+pub fn measure(sensor: &mut ProximitySensor, proximity: bool) -> Result<ConversionTiming> {
+    let proximity = match proximity {
+        true => {
+            let period = sensor.read_proximity_rate()?.period();
+            Some(time_channel(sensor, Channel::Proximity, period)?)
+        },
+        false => None,
+    };
+    let ambient_params = sensor.read_ambient_light_parameters()?;
+
+    Ok(ConversionTiming {
+        proximity,
+        ambient: time_channel(sensor, Channel::Ambient, ambient_params.frequency().period())?,
+        ambient_averaging: 1 << ambient_params.averaging(),
+    })
+}
+
+fn format_ms(ms: Option<f64>) -> String {
+    ms.map_or_else(|| "timed out".to_string(), |ms| format!("{ms:.1}ms"))
+}
+
+impl ConversionTiming {
+    /// A one-line summary of the measured and nominal periods.
+    pub fn summary(&self) -> String {
+        let proximity = match &self.proximity {
+            Some(p) => format!("{} (nominal {:.1}ms)", format_ms(p.measured_ms), p.expected_ms),
+            None => "disabled".to_string(),
+        };
+        format!(
+            "conversion period: proximity {proximity}, ambient {} (nominal {:.1}ms, {}x averaging)",
+            format_ms(self.ambient.measured_ms),
+            self.ambient.expected_ms,
+            self.ambient_averaging,
+        )
+    }
+
+    /// Logs the timing, warning if either channel is slower than its rate.
+    pub fn log(&self) {
+        info!(
+            proximity_ms = self.proximity.and_then(|p| p.measured_ms),
+            ambient_ms = self.ambient.measured_ms,
+            "{}",
+            self.summary()
+        );
+
+        if self.proximity.is_some_and(|p| p.is_slow()) || self.ambient.is_slow() {
+            warn!(
+                "sensor conversions are slower than the configured rates, so readings may be stale or \
+                look stuck; consider lowering ambient light averaging (currently {}x) or the measurement rates",
+                self.ambient_averaging,
+            );
+        }
+    }
+}
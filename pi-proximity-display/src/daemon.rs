@@ -0,0 +1,942 @@
+//! The control loop: each `Daemon::step` takes one reading, updates the
+//! presence state, and drives the display's power and brightness from it.
+
+use std::{thread, time::{Duration, Instant, SystemTime}};
+
+use color_eyre::{eyre::eyre, Result};
+use tracing::{debug, info, warn};
+use vcnl4010::{ProximitySensor, SensorDevice, AMBIENT_LUX_PER_COUNT};
+
+use crate::autorange::AmbientAutorange;
+use crate::calibrate::BaselineTracker;
+use crate::clock::Clock;
+#[cfg(feature = "http")]
+use crate::control::ControlServer;
+use crate::datalog::DataLog;
+use crate::daynight::{self, DayNight, TimeOfDay};
+#[cfg(feature = "dbus")]
+use crate::dbus::{self, DbusService};
+use crate::display::{AuxBacklight, BrightnessBackend, Display, DisplayPowerMode};
+use crate::distance::DistanceCalibration;
+use crate::gpio::EdgeListener;
+use crate::hooks::Hook;
+use crate::ipc::IpcServer;
+use crate::localtime::LocalTime;
+use crate::luminance::LuminanceTable;
+use crate::overrides::Overrides;
+use crate::poll::{self, PollScheduler};
+use crate::presence::PresenceTracker;
+use crate::reading::{self, ChangeFilter, Reading};
+use crate::stats::{RunStats, RunSummary};
+use crate::status_led::StatusLed;
+use crate::wake::AmbientWake;
+use crate::{
+    ambient_read_due, brightness_write_due, check_command_register, detected_brightness, log_transition,
+    map_ambient, map_ambient_to_display_brightness, night_fixed_brightness, presence_strength, program_interrupts,
+    proximity_boost, read_samples, recover_sensor, refresh_display, reinitialize_sensor, round_to, set_brightness,
+    signal, Args, BrightnessWhileOff, LogFormat, Reopen, State, SummaryField, ZeroAmbient, HEARTBEAT_INTERVAL,
+    STATUS_TARGET,
+};
+
+/// What to do between two `Daemon::step`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wait {
+    /// Wait for a sensor interrupt, up to `--interrupt-timeout`.
+    Interrupt,
+    Sleep(Duration),
+}
+
+/// The daemon's state between readings, along with the sensor, display, and
+/// everything else the loop drives.
+pub struct Daemon<'a, D: SensorDevice, C: Clock> {
+    args: &'a Args,
+    clock: &'a C,
+    sensor: ProximitySensor<D>,
+    display: Display,
+    aux_backlights: Vec<AuxBacklight>,
+    baseline: Option<BaselineTracker>,
+    interrupt: Option<EdgeListener>,
+    led_current_ma: u16,
+
+    state: State,
+    cleared_at: Option<Instant>,
+
+    /// The brightness last written, or `None` if the next mapped value should
+    /// be written regardless.
+    brightness: Option<u32>,
+    disabled: bool,
+    settle_until: Option<Instant>,
+    detected_since: Option<Instant>,
+    max_on_tripped: bool,
+    night_fixed: bool,
+    invalid_readings: u32,
+    read_errors: u32,
+
+    started: Instant,
+    last_heartbeat: Instant,
+    last_counters_log: Instant,
+    last_display_refresh: Instant,
+    last_command_check: Instant,
+    last_brightness_write: Option<Instant>,
+    poll: PollScheduler,
+
+    last_ambient: Option<(u32, Instant)>,
+    last_nonzero_ambient: Option<u32>,
+    zero_ambient: bool,
+    ambient_saturated: bool,
+    ambient_level: Option<usize>,
+    autorange: Option<AmbientAutorange>,
+    ambient_wake: Option<AmbientWake>,
+
+    stats: RunStats,
+    presence: PresenceTracker,
+    status_led: Option<StatusLed>,
+    display_was_off: bool,
+    last_power: Option<DisplayPowerMode>,
+    on_detect: Option<Hook>,
+    on_clear: Option<Hook>,
+    power_on: Option<Hook>,
+    power_off: Option<Hook>,
+
+    day_night: DayNight,
+    luminance: Option<LuminanceTable>,
+    distance: Option<DistanceCalibration>,
+    #[cfg(feature = "http")]
+    control: Option<ControlServer>,
+    overrides: Overrides,
+    #[cfg(feature = "dbus")]
+    dbus: Option<DbusService>,
+    data_log: Option<DataLog>,
+    ipc: Option<IpcServer>,
+    ipc_filter: ChangeFilter,
+}
+
+impl<'a, D: SensorDevice, C: Clock> Daemon<'a, D, C>
+where
+    ProximitySensor<D>: Reopen,
+{
+    /// Sets up everything the loop needs from `args`, for a sensor that has
+    /// already been configured for measurements.
+    pub fn new(
+        args: &'a Args,
+        clock: &'a C,
+        mut sensor: ProximitySensor<D>,
+        display: Display,
+        aux_backlights: Vec<AuxBacklight>,
+        baseline: Option<BaselineTracker>,
+    ) -> Result<Self> {
+        let led_current_ma = sensor.read_led_current()?.to_milliamps();
+        let state = State::Cleared;
+
+        // start from the brightness the display already has, so a first target
+        // matching it isn't rewritten and it's reported accurately until then
+        let brightness = match (display.brightness_backend, display.has_backlight()) {
+            (BrightnessBackend::Sysfs, true) => Some(display.brightness),
+            _ => None,
+        };
+
+        let mut status_led = args.status_led.as_ref().and_then(StatusLed::open);
+        if let Some(led) = &mut status_led {
+            if !led.set(&state) {
+                status_led = None;
+            }
+        }
+
+        let interrupt = match args.interrupt_gpio {
+            Some(line) => match EdgeListener::open(&args.gpio_chip, line) {
+                Ok(listener) => {
+                    let offset = baseline.as_ref().filter(|_| args.proximity_auto_zero).map_or(0, |b| b.baseline());
+                    let (low, high) = program_interrupts(&mut sensor, args, offset)?;
+                    info!(
+                        "waiting for interrupts on gpio line {line} (thresholds: {low}..{high}, count: {})",
+                        args.interrupt_count.count()
+                    );
+
+                    Some(listener)
+                },
+                Err(e) => {
+                    warn!("could not open interrupt gpio, falling back to polling: {e:#}");
+                    None
+                },
+            },
+            None => None,
+        };
+
+        #[cfg(feature = "dbus")]
+        let dbus = args.dbus.and_then(|bus| match DbusService::connect(bus, display.max_brightness) {
+            Ok(service) => Some(service),
+            Err(e) => {
+                warn!("could not start D-Bus service, continuing without it: {e:#}");
+                None
+            },
+        });
+        let data_log = args
+            .data_log
+            .as_ref()
+            .map(|path| DataLog::open(path, args.data_log_format, args.data_log_interval, args.data_log_max_size))
+            .transpose()?;
+        let distance = if args.distance_calibration.is_empty() {
+            None
+        } else {
+            let calibration = DistanceCalibration::fit(&args.distance_calibration).map_err(|e| eyre!(e))?;
+            info!("distance calibration: {calibration:?}");
+            Some(calibration)
+        };
+
+        let autorange = match args.ambient_autorange {
+            true => {
+                let averaging = sensor.read_ambient_light_parameters()?.averaging();
+                info!("ambient autorange enabled, starting from {}x averaging", 1u32 << averaging);
+                Some(AmbientAutorange::new(averaging))
+            },
+            false => None,
+        };
+
+        Ok(Daemon {
+            args,
+            clock,
+            led_current_ma,
+            state,
+            cleared_at: None,
+            brightness,
+            disabled: false,
+            settle_until: None,
+            detected_since: None,
+            max_on_tripped: false,
+            night_fixed: false,
+            invalid_readings: 0,
+            read_errors: 0,
+            started: clock.now(),
+            last_heartbeat: clock.now(),
+            last_counters_log: clock.now(),
+            last_display_refresh: clock.now(),
+            last_command_check: clock.now(),
+            last_brightness_write: None,
+            poll: PollScheduler::new(
+                args.poll_interval,
+                args.idle_poll_after,
+                args.idle_poll_max,
+                args.idle_poll_backoff,
+                args.idle_wake_threshold.unwrap_or(args.proximity_range().start),
+            ),
+            last_ambient: None,
+            last_nonzero_ambient: None,
+            zero_ambient: false,
+            ambient_saturated: false,
+            ambient_level: None,
+            autorange,
+            ambient_wake: args.ambient_wake_delta.map(|delta| AmbientWake::new(delta, args.ambient_wake_window)),
+            stats: RunStats::new(),
+            presence: PresenceTracker::new(args.presence_include_hold),
+            status_led,
+            display_was_off: display.is_off(),
+            last_power: display.power,
+            on_detect: args.on_detect_cmd.as_ref().map(|c| Hook::new("on-detect", c)),
+            on_clear: args.on_clear_cmd.as_ref().map(|c| Hook::new("on-clear", c)),
+            power_on: args.power_on_cmd.as_ref().map(|c| Hook::new("power-on", c)),
+            power_off: args.power_off_cmd.as_ref().map(|c| Hook::new("power-off", c)),
+            day_night: args.day_night(),
+            luminance: args.luminance_table.as_ref().map(LuminanceTable::load).transpose()?,
+            distance,
+            #[cfg(feature = "http")]
+            control: args.control_addr.map(ControlServer::bind).transpose()?,
+            overrides: Overrides::default(),
+            #[cfg(feature = "dbus")]
+            dbus,
+            data_log,
+            ipc: args.ipc_socket.as_ref().map(IpcServer::bind).transpose()?,
+            ipc_filter: ChangeFilter::new(args.ipc_proximity_delta, args.ipc_ambient_delta, args.ipc_heartbeat),
+            sensor,
+            display,
+            aux_backlights,
+            baseline,
+            interrupt,
+        })
+    }
+
+    /// Steps the loop until a shutdown is requested.
+    pub fn run(&mut self) -> Result<()> {
+        while !signal::shutdown_requested() {
+            match self.step()? {
+                Wait::Interrupt => {
+                    if let Some(listener) = &mut self.interrupt {
+                        listener.wait(self.args.interrupt_timeout)?;
+                    }
+                },
+                Wait::Sleep(duration) => thread::sleep(duration),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The run statistics so far.
+    pub fn summary(&self) -> RunSummary {
+        self.stats.summary()
+    }
+
+    /// The sensor's auto-zero offset, or 0 without `--proximity-auto-zero`.
+    fn baseline_offset(&self) -> u32 {
+        self.baseline.as_ref().filter(|_| self.args.proximity_auto_zero).map_or(0, |b| b.baseline())
+    }
+
+    /// Takes one reading and acts on it, returning how to wait for the next.
+    pub fn step(&mut self) -> Result<Wait> {
+        let args = self.args;
+        if !args.command_check_interval.is_zero()
+            && self.clock.since(self.last_command_check) >= args.command_check_interval
+        {
+            self.last_command_check = self.clock.now();
+            let interrupt_offset = self.interrupt.as_ref().map(|_| self.baseline_offset());
+            check_command_register(&mut self.sensor, args, self.led_current_ma, interrupt_offset)?;
+        }
+
+        let Some((raw_proximity_val, fresh_ambient)) = self.read()? else {
+            return Ok(Wait::Sleep(args.poll_interval));
+        };
+        let sampled_at = (self.clock.now(), SystemTime::now());
+        let ambient_light_val = self.filter_ambient(fresh_ambient)?;
+        let proximity_val = match (&self.baseline, args.proximity_auto_zero) {
+            (Some(b), true) => raw_proximity_val.saturating_sub(b.baseline()),
+            _ => raw_proximity_val,
+        };
+
+        self.update_control()?;
+
+        if self.max_on_tripped && args.proximity_cleared(proximity_val) {
+            info!("proximity cleared, display may turn on again");
+            self.max_on_tripped = false;
+        }
+
+        let previous_state = self.state.name();
+        let state_reason = self.update_state(proximity_val, ambient_light_val)?;
+
+        self.presence.tick();
+        self.stats.observe(proximity_val, ambient_light_val);
+        self.stats.set_power_verification(self.display.power_verified, self.display.power_unverified);
+        if self.display.is_off() != self.display_was_off {
+            self.display_was_off = self.display.is_off();
+            self.stats.power_transition();
+        }
+        if self.display.power != self.last_power {
+            self.last_power = self.display.power;
+            let hook = match self.last_power {
+                Some(DisplayPowerMode::On) => self.power_on.as_ref(),
+                Some(DisplayPowerMode::Off) => self.power_off.as_ref(),
+                None => None,
+            };
+            if let Some(hook) = hook {
+                let name = &self.display.name;
+                hook.fire(std::slice::from_ref(name), &[("DISPLAY_NAME", name.clone())]);
+            }
+        }
+
+        if let Some(baseline) = &mut self.baseline {
+            if self.state == State::Cleared && proximity_val < args.proximity_range().start {
+                baseline.observe_idle(raw_proximity_val);
+            }
+        }
+
+        if self.clock.since(self.last_display_refresh) >= args.display_refresh_interval {
+            self.last_display_refresh = self.clock.now();
+            if let Err(e) = refresh_display(&mut self.display, args) {
+                // a transient sysfs error shouldn't stop the loop; the next
+                // refresh will try again
+                warn!("could not refresh display max_brightness, keeping {}: {e}", self.display.max_brightness);
+            }
+        }
+
+        if self.settle_until.is_some_and(|t| self.clock.now() >= t) {
+            self.settle_until = None;
+        }
+
+        let mapped = self.map_brightness(proximity_val, ambient_light_val);
+        if args.explain {
+            let paused_reason = match () {
+                _ if self.disabled => Some("disable file present"),
+                _ if args.brightness_while_off.pauses(self.display.is_off()) => Some("display off"),
+                _ if self.settle_until.is_some() => Some("ambient settling"),
+                _ => None,
+            };
+            let brightness_cap = args.brightness_cap.map(|c| c.resolve(self.display.max_brightness));
+            info!(
+                raw_proximity = raw_proximity_val,
+                proximity = proximity_val,
+                range = ?args.proximity_range(),
+                previous_state,
+                state = self.state.name(),
+                ambient = ambient_light_val,
+                mapped_brightness = ?mapped,
+                brightness_cap = ?brightness_cap,
+                paused = ?paused_reason,
+                "explain: {state_reason}"
+            );
+        }
+
+        if let Some(mapped) = mapped {
+            let brightness_cap = args.brightness_cap.map(|c| c.resolve(self.display.max_brightness));
+            let new_brightness = match brightness_cap {
+                Some(cap) => mapped.min(cap),
+                None => mapped,
+            };
+
+            let interval = args.brightness_write_interval;
+            let write_due = brightness_write_due(self.last_brightness_write, interval, self.clock);
+            if self.brightness != Some(new_brightness) && write_due {
+                let written = set_brightness(&mut self.display, args, new_brightness)?;
+                self.last_brightness_write = Some(self.clock.now());
+                self.stats.brightness_write();
+                self.brightness = Some(written);
+                if mapped > written {
+                    info!("set brightness to {written} (ambient: {ambient_light_val}, capped from {mapped})")
+                } else {
+                    info!("set brightness to {written} (ambient: {ambient_light_val})")
+                }
+            }
+        }
+
+        if !self.disabled {
+            for aux in &mut self.aux_backlights {
+                let target = match (&args.ambient_light_range, self.display.is_off()) {
+                    (_, true) => 0,
+                    (Some(ambient), false) => {
+                        map_ambient_to_display_brightness(ambient_light_val, ambient, &aux.spec.range)
+                    },
+                    (None, false) => aux.spec.range.end,
+                };
+
+                if aux.set_brightness(target)? {
+                    info!("set aux backlight {} to {target}", aux.spec.sysfs_path.display());
+                }
+            }
+        }
+
+        if !args.counters_log_interval.is_zero()
+            && self.clock.since(self.last_counters_log) >= args.counters_log_interval
+        {
+            self.last_counters_log = self.clock.now();
+            self.stats.counters().log();
+        }
+
+        if self.clock.since(self.last_heartbeat) >= HEARTBEAT_INTERVAL {
+            self.last_heartbeat = self.clock.now();
+            self.log_heartbeat(proximity_val, ambient_light_val);
+        }
+
+        self.publish(sampled_at, proximity_val, ambient_light_val);
+
+        Ok(match &self.interrupt {
+            Some(_) => Wait::Interrupt,
+            None if args.align_poll => {
+                Wait::Sleep(poll::until_aligned(self.poll.next_interval(&self.state, proximity_val)))
+            },
+            None => Wait::Sleep(self.poll.next_interval(&self.state, proximity_val)),
+        })
+    }
+
+    /// Reads the (proximity, fresh ambient) samples, recovering the sensor
+    /// after repeated read errors or invalid readings. Returns `None` if the
+    /// reading should be retried after `--poll-interval`.
+    fn read(&mut self) -> Result<Option<(u32, Option<u32>)>> {
+        let args = self.args;
+        let read_ambient = ambient_read_due(self.last_ambient.map(|(_, at)| at), args.ambient_interval, self.clock);
+        let samples = match read_samples(&mut self.sensor, args, read_ambient) {
+            Ok(samples) => {
+                self.read_errors = 0;
+                self.stats.read();
+                samples
+            },
+            Err(e) => {
+                self.read_errors += 1;
+                self.stats.read_error();
+                warn!("sensor read failed ({} in a row): {e:#}", self.read_errors);
+                if self.read_errors >= args.sensor_error_limit {
+                    let interrupt_offset = self.interrupt.as_ref().map(|_| self.baseline_offset());
+                    recover_sensor(&mut self.sensor, args, self.led_current_ma, interrupt_offset)?;
+                    self.stats.sensor_recovery();
+                    self.read_errors = 0;
+                }
+
+                return Ok(None);
+            },
+        };
+        if self.interrupt.is_some() {
+            let status = self.sensor.read_interrupt_status()?;
+            if status.into_bits() != 0 {
+                self.sensor.clear_interrupt_status(status)?;
+            }
+        }
+
+        let Some(samples) = samples else {
+            self.invalid_readings += 1;
+            self.stats.invalid_reading();
+            if self.invalid_readings.is_multiple_of(args.invalid_reading_limit) {
+                warn!("{} consecutive invalid proximity readings, reinitializing sensor", self.invalid_readings);
+                reinitialize_sensor(&mut self.sensor, args, self.led_current_ma)?;
+            }
+
+            return Ok(None);
+        };
+        if self.invalid_readings > 0 {
+            info!("proximity readings valid again after {} invalid", self.invalid_readings);
+            self.invalid_readings = 0;
+        }
+
+        Ok(Some(samples))
+    }
+
+    /// Returns the ambient light value to act on, given a fresh reading if one
+    /// was taken, per `--ambient-interval` and `--zero-ambient`. Also steps
+    /// `--ambient-autorange`.
+    fn filter_ambient(&mut self, fresh_ambient: Option<u32>) -> Result<u32> {
+        let args = self.args;
+        let ambient_light_val = match (fresh_ambient, self.last_ambient) {
+            (Some(ambient), _) => {
+                self.last_ambient = Some((ambient, self.clock.now()));
+                ambient
+            },
+            (None, Some((ambient, _))) => ambient,
+            (None, None) => unreachable!("ambient light is always read until a value is cached"),
+        };
+        let ambient_light_val = match (ambient_light_val, args.zero_ambient) {
+            (0, ZeroAmbient::Accept) => 0,
+            (0, mode) => {
+                if !self.zero_ambient {
+                    self.zero_ambient = true;
+                    warn!(
+                        "ambient light reads 0 although the ambient channel is enabled, which usually indicates \
+                        a fault rather than darkness{}",
+                        if mode == ZeroAmbient::Hold { "; holding the last nonzero reading" } else { "" }
+                    );
+                }
+
+                match (mode, self.last_nonzero_ambient) {
+                    (ZeroAmbient::Hold, Some(last)) => last,
+                    _ => 0,
+                }
+            },
+            (ambient, _) => {
+                if self.zero_ambient {
+                    info!("ambient light readings nonzero again");
+                    self.zero_ambient = false;
+                }
+                self.last_nonzero_ambient = Some(ambient);
+                ambient
+            },
+        };
+
+        if (ambient_light_val == u16::MAX as u32) != self.ambient_saturated {
+            self.ambient_saturated = !self.ambient_saturated;
+            match self.ambient_saturated {
+                true => warn!(
+                    "ambient light is saturated at full scale (~{:.0} lx), so brighter light can't be told \
+                    apart; consider shading the sensor from direct sunlight or --ambient-autorange",
+                    u16::MAX as f32 * AMBIENT_LUX_PER_COUNT
+                ),
+                false => info!("ambient light no longer saturated"),
+            }
+        }
+        if let (Some(autorange), Some(ambient)) = (self.autorange.as_mut(), fresh_ambient) {
+            if let Some(averaging) = autorange.observe(ambient, self.clock) {
+                let params = self.sensor.read_ambient_light_parameters()?;
+                let reason = match averaging < params.averaging() {
+                    true => "ambient light saturated",
+                    false => "ambient light back within range",
+                };
+                info!(
+                    averaging = 1u32 << averaging,
+                    "{reason}, ambient light averaging {}x -> {}x",
+                    1u32 << params.averaging(),
+                    1u32 << averaging,
+                );
+                self.sensor.set_ambient_light_parameters(params.with_averaging(averaging))?;
+
+                // readings from before the change don't compare with those
+                // after it, so the mapping starts over with the next one
+                self.last_ambient = None;
+                self.ambient_level = None;
+                self.ambient_wake =
+                    args.ambient_wake_delta.map(|delta| AmbientWake::new(delta, args.ambient_wake_window));
+            }
+        }
+
+        Ok(ambient_light_val)
+    }
+
+    /// Applies the disable file, `--night-fixed-brightness`, and overrides from
+    /// the control server and D-Bus.
+    fn update_control(&mut self) -> Result<()> {
+        let args = self.args;
+        let now_disabled = args.disable_file.as_ref().is_some_and(|p| p.exists());
+        if now_disabled != self.disabled {
+            self.disabled = now_disabled;
+            if self.disabled {
+                warn!("disable file present, pausing display control");
+            } else {
+                if self.overrides.power.is_none() {
+                    self.state.transition(&mut self.display, args)?;
+                }
+                info!(
+                    state = self.state.name(),
+                    "disable file removed, resuming display control in state {} with display power {:?}",
+                    self.state.name(),
+                    self.display.power,
+                );
+                self.brightness = None;
+            }
+        }
+
+        let previous = self.overrides;
+        if args.night_fixed_active() != self.night_fixed {
+            self.night_fixed = !self.night_fixed;
+            match self.night_fixed {
+                true => info!("night started, holding the display on at --night-fixed-brightness"),
+                false => info!("night ended, resuming normal display control in state {:?}", self.state),
+            }
+            if !self.disabled && self.overrides.power.is_none() {
+                self.state.transition(&mut self.display, args)?;
+            }
+        }
+
+        #[cfg(feature = "http")]
+        if let Some(control) = &mut self.control {
+            control.poll(&mut self.overrides, self.display.max_brightness, &self.stats.counters());
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(service) = &mut self.dbus {
+            service.poll(&mut self.overrides);
+        }
+        if previous.power.is_some() && self.overrides.power.is_none() && !self.disabled {
+            info!("power override cleared, resuming display control in state {:?}", self.state);
+            self.state.transition(&mut self.display, args)?;
+        }
+        if self.overrides.brightness != previous.brightness {
+            self.brightness = None;
+        }
+        self.display.flush_power()?;
+        if let (Some(mode), false, false) = (self.overrides.power, self.disabled, args.presence_log_only) {
+            if self.display.power != Some(mode) {
+                self.display.set_power(mode)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Steps the presence state machine, powering the display and firing hooks
+    /// on transitions. Returns the reason for the outcome, for `--explain`.
+    fn update_state(&mut self, proximity_val: u32, ambient_light_val: u32) -> Result<&'static str> {
+        let args = self.args;
+        let ambient_wake = self.ambient_wake.as_mut().is_some_and(|w| w.observe(ambient_light_val, self.clock));
+        if ambient_wake {
+            debug!(ambient = ambient_light_val, "ambient light wake");
+        }
+        if self.state.extend_hold(args, proximity_val, self.clock) {
+            debug!(proximity = proximity_val, "near presence, hold timer restarted");
+        }
+        let update = self.state.update(args, proximity_val, ambient_wake, self.clock, self.cleared_at);
+        let state_reason = match update {
+            Some(State::Detected) if self.max_on_tripped => "detection ignored after --max-on-time tripped",
+            Some(State::Cleared) => "hold expired: cleared",
+            None if self.state == State::Cleared && (args.proximity_detected(proximity_val) || ambient_wake) => {
+                "detection suppressed by --rearm-cooldown"
+            },
+            _ => self.state.explain_update(args, proximity_val, ambient_wake),
+        };
+
+        // after the max on time trips, detection is ignored until it clears
+        let update = update.filter(|new| !(self.max_on_tripped && *new == State::Detected));
+        if let Some(new) = update {
+            let session = self.presence.observe(&new);
+            log_transition(&self.state, &new, proximity_val, session);
+            self.state = new;
+            if self.state == State::Cleared {
+                self.cleared_at = Some(self.clock.now());
+            }
+            if !self.disabled && self.overrides.power.is_none() {
+                let was_off = self.display.is_off();
+                self.state.transition(&mut self.display, args)?;
+
+                if let (true, false, Some(b)) = (was_off, self.display.is_off(), self.brightness) {
+                    if !args.ambient_settle.is_zero() && args.brightness_while_off != BrightnessWhileOff::Zero {
+                        info!(
+                            "holding brightness at {b} for {} while ambient light settles",
+                            humantime::format_duration(args.ambient_settle)
+                        );
+                        self.settle_until = Some(self.clock.now() + args.ambient_settle);
+                    }
+                }
+            }
+            if self.status_led.as_mut().is_some_and(|led| !led.set(&self.state)) {
+                self.status_led = None;
+            }
+
+            let hook = match self.state {
+                State::Detected => self.on_detect.as_ref(),
+                State::Cleared => self.on_clear.as_ref(),
+                State::ClearedTransitioning(_) | State::Approaching => None,
+            };
+            if let Some(hook) = hook {
+                hook.fire(&[proximity_val.to_string()], &[("PROXIMITY", proximity_val.to_string())]);
+            }
+        }
+
+        if self.state == State::Detected {
+            let since = *self.detected_since.get_or_insert_with(|| self.clock.now());
+            if let Some(max) = args.max_on_time.filter(|max| self.clock.since(since) >= *max) {
+                warn!(
+                    "proximity detected continuously for over {}, forcing display off until it clears (--max-on-time)",
+                    humantime::format_duration(max)
+                );
+                self.max_on_tripped = true;
+                let session = self.presence.observe(&State::Cleared);
+                log_transition(&self.state, &State::Cleared, proximity_val, session);
+                self.state = State::Cleared;
+                self.cleared_at = Some(self.clock.now());
+                if !self.disabled && self.overrides.power.is_none() {
+                    self.state.transition(&mut self.display, args)?;
+                }
+                if self.status_led.as_mut().is_some_and(|led| !led.set(&self.state)) {
+                    self.status_led = None;
+                }
+
+                if let Some(hook) = &self.on_clear {
+                    hook.fire(&[proximity_val.to_string()], &[("PROXIMITY", proximity_val.to_string())]);
+                }
+            }
+        }
+        if self.state != State::Detected {
+            self.detected_since = None;
+        }
+
+        Ok(state_reason)
+    }
+
+    /// The brightness to write for this reading, if any.
+    fn map_brightness(&mut self, proximity_val: u32, ambient_light_val: u32) -> Option<u32> {
+        let args = self.args;
+        let max_brightness = self.display.max_brightness;
+
+        // by default brightness isn't written while the display is off, since
+        // on some panels this can briefly light the backlight; the latest
+        // value is applied once it powers back on
+        let off = self.display.is_off();
+        let paused = self.disabled || args.brightness_while_off.pauses(off) || self.settle_until.is_some();
+        let mapped = match (&args.ambient_levels, &args.ambient_light_range, &args.brightness_range) {
+            _ if paused => None,
+            _ if self.overrides.brightness.is_some() => self.overrides.brightness,
+            _ if self.night_fixed => night_fixed_brightness(args, proximity_val, max_brightness),
+            _ if args.detected_brightness.is_some() => detected_brightness(args, &self.state, max_brightness),
+            (Some(levels), _, _) => {
+                let band = levels.select(self.ambient_level, ambient_light_val, args.ambient_level_hysteresis);
+                self.ambient_level = Some(band);
+                let boost = proximity_boost(args, proximity_val, max_brightness);
+                Some((levels.brightness(band) + boost).min(max_brightness))
+            },
+            (None, Some(ambient), Some(day_range)) => {
+                let display = &match &args.night_brightness_range {
+                    Some(night_range) => {
+                        let factor = self.day_night.night_factor(TimeOfDay::of(&LocalTime::now()));
+                        daynight::blend(day_range, night_range, factor)
+                    },
+                    None => day_range.clone(),
+                };
+                let ambient_mapped =
+                    map_ambient(args, self.luminance.as_ref(), ambient_light_val, ambient, display);
+                let boost = proximity_boost(args, proximity_val, max_brightness);
+                Some((ambient_mapped + boost).min(display.end))
+            },
+            _ => None,
+        };
+
+        args.brightness_while_off.apply(off, mapped)
+    }
+
+    fn log_heartbeat(&self, proximity_val: u32, ambient_light_val: u32) {
+        let args = self.args;
+        let lux = ambient_light_val as f32 * AMBIENT_LUX_PER_COUNT;
+        let cm = self.distance.as_ref().and_then(|d| d.estimate_distance_cm(proximity_val));
+
+        // rounding is for readability only, and doesn't affect control
+        let proximity = round_to(proximity_val, args.log_granularity);
+        let ambient = round_to(ambient_light_val, args.log_granularity);
+        let presence = presence_strength(args, proximity_val, self.baseline.as_ref());
+        let mut fields = Vec::new();
+        if args.summary_includes(SummaryField::Proximity) {
+            fields.push(match cm {
+                Some(cm) => format!("proximity: {proximity} (~{cm:.0}cm, {presence}%)"),
+                None => format!("proximity: {proximity} ({presence}%)"),
+            });
+        }
+        if args.summary_includes(SummaryField::Ambient) {
+            fields.push(format!("ambient: {ambient} ({lux:.1} lx)"));
+        }
+        if !fields.is_empty() {
+            info!("{}", fields.join(" | "));
+        }
+
+        if args.log_format == LogFormat::Compact {
+            let power = if matches!(self.state, State::Cleared) { "OFF" } else { "ON" };
+            let brightness = self.brightness.map_or_else(|| "-".to_string(), |b| b.to_string());
+            info!(target: STATUS_TARGET, "P={proximity:>5} A={ambient:>5} B={brightness:>4} S={power}");
+        }
+    }
+
+    /// Publishes the reading over IPC and D-Bus, and to the data log.
+    fn publish(&mut self, sampled_at: (Instant, SystemTime), proximity_val: u32, ambient_light_val: u32) {
+        let (monotonic_ms, timestamp_ms) = reading::timestamps(self.started, sampled_at.0, sampled_at.1);
+        let reading = Reading {
+            monotonic_ms,
+            timestamp_ms,
+            proximity: proximity_val,
+            presence: presence_strength(self.args, proximity_val, self.baseline.as_ref()),
+            ambient: ambient_light_val,
+            lux: ambient_light_val as f32 * AMBIENT_LUX_PER_COUNT,
+            state: self.state.name(),
+            brightness: self.brightness,
+            counters: self.stats.counters(),
+        };
+        if let Some(ipc) = self.ipc.as_mut().filter(|_| self.ipc_filter.changed(&reading, self.clock)) {
+            ipc.publish(&reading);
+        }
+        if let Some(data_log) = &mut self.data_log {
+            data_log.record(&reading);
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(service) = &mut self.dbus {
+            if let Err(e) = service.update(&reading, self.display.power, self.overrides) {
+                warn!("D-Bus connection lost, no longer serving {}: {e:#}", dbus::BUS_NAME);
+                self.dbus = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use clap::Parser;
+    use vcnl4010::{mock::MockDevice, I2CFunctionality};
+
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::display::tests::{logging_display, temp_path};
+
+    /// Maps ambient light 0..1000 onto brightness 0..100.
+    fn args(extra: &[&str]) -> Args {
+        let base = [
+            "pi-proximity-display",
+            "--proximity-range",
+            "100..200",
+            "--proximity-hold",
+            "10s",
+            "--ambient-light-range",
+            "0..1000",
+            "--brightness-range",
+            "0..100",
+        ];
+        Args::try_parse_from(base.iter().chain(extra)).unwrap()
+    }
+
+    /// A daemon reading from a mock sensor, fed through the returned device,
+    /// and driving a display that starts on and logs its power and brightness
+    /// commands to `dir/log`.
+    fn daemon<'a>(args: &'a Args, clock: &'a MockClock, dir: &Path) -> (Daemon<'a, MockDevice, MockClock>, MockDevice) {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let mut display = logging_display(dir);
+        display.power = Some(DisplayPowerMode::On);
+
+        let device = MockDevice::new();
+        let functionality = I2CFunctionality(
+            I2CFunctionality::SMBUS_READ_BYTE_DATA | I2CFunctionality::SMBUS_WRITE_BYTE_DATA,
+        );
+        let sensor = ProximitySensor::from_device(device.clone(), functionality);
+        let daemon = Daemon::new(args, clock, sensor, display, Vec::new(), None).unwrap();
+
+        (daemon, device)
+    }
+
+    /// Steps `daemon` with a reading, returning the display commands it sent.
+    fn step(
+        daemon: &mut Daemon<MockDevice, MockClock>,
+        device: &MockDevice,
+        dir: &Path,
+        proximity: u16,
+        ambient: u16,
+    ) -> Vec<String> {
+        device.set_proximity(proximity);
+        device.set_ambient(ambient);
+        daemon.step().unwrap();
+
+        let log = dir.join("log");
+        let commands = fs::read_to_string(&log).unwrap_or_default().lines().map(String::from).collect();
+        let _ = fs::remove_file(&log);
+
+        commands
+    }
+
+    #[test]
+    fn brightness_while_off_modes() {
+        // the brightness written once the display has turned off, while ambient
+        // light changes, and once it turns back on
+        let cases = [
+            ("keep", vec!["power --off"], vec!["80"], vec!["power --on"]),
+            ("zero", vec!["power --off", "0"], vec![], vec!["power --on", "80"]),
+            ("skip", vec!["power --off"], vec![], vec!["power --on", "80"]),
+        ];
+
+        for (mode, clearing, while_off, detecting) in cases {
+            let args = args(&["--brightness-while-off", mode]);
+            let clock = MockClock::new();
+            let dir = temp_path(&format!("daemon-while-off-{mode}"));
+            let (mut daemon, device) = daemon(&args, &clock, &dir);
+
+            assert_eq!(step(&mut daemon, &device, &dir, 500, 500), ["power --on", "50"], "{mode}");
+            assert!(step(&mut daemon, &device, &dir, 50, 500).is_empty(), "{mode}");
+            clock.advance(Duration::from_secs(11));
+            assert_eq!(step(&mut daemon, &device, &dir, 50, 500), clearing, "{mode}");
+
+            assert_eq!(step(&mut daemon, &device, &dir, 50, 800), while_off, "{mode}");
+            assert_eq!(step(&mut daemon, &device, &dir, 500, 800), detecting, "{mode}");
+        }
+    }
+
+    #[test]
+    fn brightness_is_not_written_while_off() {
+        let args = args(&[]);
+        let clock = MockClock::new();
+        let dir = temp_path("daemon-off");
+        let (mut daemon, device) = daemon(&args, &clock, &dir);
+
+        assert_eq!(step(&mut daemon, &device, &dir, 500, 500), ["power --on", "50"]);
+        step(&mut daemon, &device, &dir, 50, 500);
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(step(&mut daemon, &device, &dir, 50, 500), ["power --off"]);
+
+        for ambient in [200, 900, 700] {
+            assert!(step(&mut daemon, &device, &dir, 50, ambient).is_empty(), "ambient {ambient}");
+        }
+
+        // only the latest value is written, once the display is back on
+        assert_eq!(step(&mut daemon, &device, &dir, 500, 700), ["power --on", "70"]);
+    }
+
+    #[test]
+    fn detected_brightness_is_applied_on_detection() {
+        let args = args(&["--detected-brightness", "70%", "--hold-brightness", "20"]);
+        let clock = MockClock::new();
+        let dir = temp_path("daemon-detected");
+        let (mut daemon, device) = daemon(&args, &clock, &dir);
+
+        // the fixed level ignores ambient light, before and during the hold
+        assert_eq!(step(&mut daemon, &device, &dir, 500, 900), ["power --on", "70"]);
+        assert!(step(&mut daemon, &device, &dir, 500, 100).is_empty());
+        assert_eq!(step(&mut daemon, &device, &dir, 50, 100), ["20"]);
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(step(&mut daemon, &device, &dir, 50, 100), ["power --off"]);
+
+        assert_eq!(step(&mut daemon, &device, &dir, 500, 300), ["power --on", "70"]);
+    }
+}
@@ -0,0 +1,335 @@
+//! Periodic logging of readings for long-term analysis, as CSV or, with the
+//! `sqlite` feature, an SQLite database.
+//!
+//! Rows are buffered in memory and flushed at most once per `FLUSH_INTERVAL`
+//! to limit writes to SD cards. Once the file exceeds its size cap, it is
+//! renamed with a `.1` suffix (replacing any previous one) and a new file is
+//! started, so at most about twice the cap is used.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use color_eyre::{eyre::eyre, Result};
+use tracing::{info, warn};
+
+use crate::reading::Reading;
+
+/// How often buffered rows are flushed to disk.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `timestamp` is the wall clock time of the sample, and `monotonic_ms` the
+/// monotonic time since the daemon started; see `Reading`.
+const HEADER: &str = "timestamp,proximity,ambient,lux,state,brightness,monotonic_ms\n";
+
+/// The same columns as `HEADER`, with `timestamp_ms` in milliseconds since
+/// the Unix epoch.
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS readings (
+    timestamp_ms INTEGER NOT NULL,
+    proximity INTEGER NOT NULL,
+    ambient INTEGER NOT NULL,
+    lux REAL NOT NULL,
+    state TEXT NOT NULL,
+    brightness INTEGER,
+    monotonic_ms REAL NOT NULL
+)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DataLogFormat {
+    Csv,
+
+    /// A `readings` table in an SQLite database.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+/// Parses a size in bytes with an optional `K`, `M`, or `G` suffix (powers of
+/// 1024), e.g. `10M`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size: {s}"))
+}
+
+pub struct DataLog {
+    path: PathBuf,
+    format: DataLogFormat,
+    sink: Sink,
+    max_size: u64,
+    interval: Duration,
+    last_row: Option<Instant>,
+    last_flush: Instant,
+}
+
+enum Sink {
+    Csv {
+        writer: BufWriter<File>,
+        size: u64,
+    },
+
+    /// Rows are held until the next flush, then inserted in one transaction.
+    #[cfg(feature = "sqlite")]
+    Sqlite {
+        db: rusqlite::Connection,
+        pending: Vec<Reading>,
+    },
+}
+
+impl DataLog {
+    pub fn open(path: impl AsRef<Path>, format: DataLogFormat, interval: Duration, max_size: u64) -> Result<DataLog> {
+        let path = path.as_ref().to_path_buf();
+        let sink = Sink::open(&path, format)?;
+        info!("logging readings to {} every {}", path.display(), humantime::format_duration(interval));
+
+        Ok(DataLog {
+            path,
+            format,
+            sink,
+            max_size,
+            interval,
+            last_row: None,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Records a row for the reading if the row interval has elapsed. Errors
+    /// are logged rather than returned so a full or failing disk doesn't stop
+    /// display control.
+    pub fn record(&mut self, reading: &Reading) {
+        if self.last_row.is_some_and(|t| t.elapsed() < self.interval) {
+            return;
+        }
+        self.last_row = Some(Instant::now());
+
+        if let Err(e) = self.write_row(reading) {
+            warn!("could not write data log {}: {e:#}", self.path.display());
+        }
+    }
+
+    fn write_row(&mut self, reading: &Reading) -> Result<()> {
+        if self.sink.size()? >= self.max_size {
+            self.rotate()?;
+        }
+
+        self.sink.write(reading)?;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.last_flush = Instant::now();
+            self.sink.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.sink.flush()?;
+
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, &rotated)?;
+        self.sink = Sink::open(&self.path, self.format)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for DataLog {
+    fn drop(&mut self) {
+        if let Err(e) = self.sink.flush() {
+            warn!("could not flush data log {}: {e}", self.path.display());
+        }
+    }
+}
+
+impl Sink {
+    fn open(path: &Path, format: DataLogFormat) -> Result<Sink> {
+        match format {
+            DataLogFormat::Csv => {
+                let (writer, size) = open_csv(path)?;
+                Ok(Sink::Csv { writer, size })
+            },
+            #[cfg(feature = "sqlite")]
+            DataLogFormat::Sqlite => {
+                let db = rusqlite::Connection::open(path)
+                    .and_then(|db| db.execute(SCHEMA, ()).map(|_| db))
+                    .map_err(|e| eyre!("could not open data log {}: {e}", path.display()))?;
+                Ok(Sink::Sqlite { db, pending: Vec::new() })
+            },
+        }
+    }
+
+    /// The size of the log, including CSV rows not yet flushed. Pending
+    /// SQLite rows aren't counted until they're inserted.
+    fn size(&self) -> Result<u64> {
+        match self {
+            Sink::Csv { size, .. } => Ok(*size),
+            #[cfg(feature = "sqlite")]
+            Sink::Sqlite { db, .. } => {
+                let size: i64 = db.query_row(
+                    "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                    (),
+                    |row| row.get(0),
+                )?;
+                Ok(size as u64)
+            },
+        }
+    }
+
+    fn write(&mut self, reading: &Reading) -> Result<()> {
+        match self {
+            Sink::Csv { writer, size } => {
+                let row = format!(
+                    "{},{},{},{:.2},{},{},{:.1}\n",
+                    humantime::format_rfc3339_millis(UNIX_EPOCH + Duration::from_millis(reading.timestamp_ms)),
+                    reading.proximity,
+                    reading.ambient,
+                    reading.lux,
+                    reading.state,
+                    reading.brightness.map(|b| b.to_string()).unwrap_or_default(),
+                    reading.monotonic_ms,
+                );
+                writer.write_all(row.as_bytes())?;
+                *size += row.len() as u64;
+            },
+            #[cfg(feature = "sqlite")]
+            Sink::Sqlite { pending, .. } => pending.push(reading.clone()),
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Sink::Csv { writer, .. } => writer.flush()?,
+            #[cfg(feature = "sqlite")]
+            Sink::Sqlite { db, pending } => {
+                let tx = db.transaction()?;
+                {
+                    let mut insert = tx.prepare_cached(
+                        "INSERT INTO readings (timestamp_ms, proximity, ambient, lux, state, brightness, monotonic_ms) \
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    )?;
+                    for reading in pending.iter() {
+                        insert.execute(rusqlite::params![
+                            reading.timestamp_ms as i64,
+                            reading.proximity,
+                            reading.ambient,
+                            reading.lux,
+                            reading.state,
+                            reading.brightness,
+                            reading.monotonic_ms,
+                        ])?;
+                    }
+                }
+                tx.commit()?;
+                pending.clear();
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens a CSV log for appending, writing the header if it's new or empty,
+/// and returns it along with its current size.
+fn open_csv(path: &Path) -> Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| eyre!("could not open data log {}: {e}", path.display()))?;
+
+    let mut size = file.metadata()?.len();
+    let mut writer = BufWriter::new(file);
+    if size == 0 {
+        writer.write_all(HEADER.as_bytes())?;
+        size = HEADER.len() as u64;
+    }
+
+    Ok((writer, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pi-proximity-display-{}-{name}", process::id()))
+    }
+
+    fn reading(proximity: u32, brightness: Option<u32>) -> Reading {
+        Reading {
+            monotonic_ms: 1500.0,
+            timestamp_ms: 1_700_000_000_000,
+            proximity,
+            presence: 0,
+            ambient: 450,
+            lux: 112.5,
+            state: "detected",
+            brightness,
+            counters: Default::default(),
+        }
+    }
+
+    #[test]
+    fn writes_csv_rows_on_flush() {
+        let path = temp_path("datalog.csv");
+        let _ = fs::remove_file(&path);
+
+        let mut log = DataLog::open(&path, DataLogFormat::Csv, Duration::ZERO, u64::MAX).unwrap();
+        log.record(&reading(2100, Some(60)));
+        log.record(&reading(300, None));
+        drop(log);
+
+        let rows = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            rows.lines().collect::<Vec<_>>(),
+            [
+                HEADER.trim_end(),
+                "2023-11-14T22:13:20.000Z,2100,450,112.50,detected,60,1500.0",
+                "2023-11-14T22:13:20.000Z,300,450,112.50,detected,,1500.0",
+            ]
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn inserts_sqlite_rows_on_flush() {
+        let path = temp_path("datalog.db");
+        let _ = fs::remove_file(&path);
+
+        let mut log = DataLog::open(&path, DataLogFormat::Sqlite, Duration::ZERO, u64::MAX).unwrap();
+        log.record(&reading(2100, Some(60)));
+        log.record(&reading(300, None));
+
+        let db = rusqlite::Connection::open(&path).unwrap();
+        let count = |db: &rusqlite::Connection| -> i64 {
+            db.query_row("SELECT COUNT(*) FROM readings", (), |row| row.get(0)).unwrap()
+        };
+        assert_eq!(count(&db), 0);
+
+        drop(log);
+        assert_eq!(count(&db), 2);
+        let (proximity, brightness): (u32, Option<u32>) = db
+            .query_row("SELECT proximity, brightness FROM readings ORDER BY rowid DESC", (), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((proximity, brightness), (300, None));
+    }
+}
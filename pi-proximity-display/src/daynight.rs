@@ -0,0 +1,163 @@
+//! Blending between daytime and nighttime brightness ranges by time of day.
+//!
+//! Times are interpreted in the system's local timezone (per `TZ` or
+//! `/etc/localtime`), and are fixed clock times rather than being derived from
+//! the actual position of the sun, so they may need adjusting seasonally. On
+//! DST changes the blend simply follows the new wall-clock time.
+//!
+//! Each transition is centered on its time: with a 1h twilight and a 07:00
+//! sunrise, the night range is used until 06:30, blends linearly to the day
+//! range by 07:30, and vice versa around sunset.
+
+use std::{ops::Range, str::FromStr, time::Duration};
+
+use crate::localtime::LocalTime;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// A wall-clock time of day, parsed from `HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeOfDay {
+    /// Seconds since midnight.
+    seconds: u32,
+}
+
+impl TimeOfDay {
+    pub fn of(t: &LocalTime) -> TimeOfDay {
+        TimeOfDay { seconds: t.hour * 3600 + t.minute * 60 + t.second }
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hour, minute) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid time (expected HH:MM): {s}"))?;
+
+        let hour = u32::from_str(hour).map_err(|_| format!("Invalid hour: {hour}"))?;
+        let minute = u32::from_str(minute).map_err(|_| format!("Invalid minute: {minute}"))?;
+        if hour > 23 || minute > 59 {
+            return Err(format!("Invalid time: {s}"));
+        }
+
+        Ok(TimeOfDay { seconds: hour * 3600 + minute * 60 })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DayNight {
+    pub sunrise: TimeOfDay,
+    pub sunset: TimeOfDay,
+
+    /// Total length of each transition, centered on sunrise and sunset.
+    pub twilight: Duration,
+}
+
+impl DayNight {
+    /// Returns how far into night it is at the given time, from 0 (full day)
+    /// to 1 (full night).
+    pub fn night_factor(&self, now: TimeOfDay) -> f32 {
+        let half = (self.twilight.as_secs() as u32 / 2).min(SECONDS_PER_DAY / 4);
+
+        // signed distance from `edge` to `now`, wrapped to within half a day
+        let offset = |edge: TimeOfDay| {
+            let d = (now.seconds + SECONDS_PER_DAY - edge.seconds) % SECONDS_PER_DAY;
+            if d > SECONDS_PER_DAY / 2 {
+                d as i64 - SECONDS_PER_DAY as i64
+            } else {
+                d as i64
+            }
+        };
+
+        // progress through a transition from 0 to 1, if within one
+        let progress = |edge: TimeOfDay| {
+            let d = offset(edge);
+            if half == 0 || d.unsigned_abs() > half as u64 {
+                None
+            } else {
+                Some((d + half as i64) as f32 / (2 * half) as f32)
+            }
+        };
+
+        if let Some(p) = progress(self.sunrise) {
+            return 1.0 - p;
+        }
+        if let Some(p) = progress(self.sunset) {
+            return p;
+        }
+
+        if self.is_day(now) { 0.0 } else { 1.0 }
+    }
+
+    /// Whether `now` is between sunset and sunrise, ignoring twilight.
+    pub fn is_night(&self, now: TimeOfDay) -> bool {
+        !self.is_day(now)
+    }
+
+    fn is_day(&self, now: TimeOfDay) -> bool {
+        let (rise, set, t) = (self.sunrise.seconds, self.sunset.seconds, now.seconds);
+        if rise <= set {
+            t >= rise && t < set
+        } else {
+            t >= rise || t < set
+        }
+    }
+}
+
+/// Linearly interpolates between two ranges.
+pub fn blend(day: &Range<u32>, night: &Range<u32>, night_factor: f32) -> Range<u32> {
+    let lerp = |a: u32, b: u32| (a as f32 + (b as f32 - a as f32) * night_factor).round() as u32;
+
+    lerp(day.start, night.start)..lerp(day.end, night.end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> TimeOfDay {
+        TimeOfDay::from_str(s).unwrap()
+    }
+
+    fn day_night(sunrise: &str, sunset: &str) -> DayNight {
+        DayNight { sunrise: at(sunrise), sunset: at(sunset), twilight: Duration::from_secs(3600) }
+    }
+
+    #[test]
+    fn blends_across_twilight() {
+        let dn = day_night("07:00", "19:00");
+        assert_eq!(dn.night_factor(at("03:00")), 1.0);
+        assert_eq!(dn.night_factor(at("06:30")), 1.0);
+        assert_eq!(dn.night_factor(at("07:00")), 0.5);
+        assert_eq!(dn.night_factor(at("07:30")), 0.0);
+        assert_eq!(dn.night_factor(at("12:00")), 0.0);
+        assert_eq!(dn.night_factor(at("18:45")), 0.25);
+        assert_eq!(dn.night_factor(at("19:30")), 1.0);
+    }
+
+    #[test]
+    fn handles_sunset_after_midnight() {
+        let dn = day_night("07:00", "00:30");
+        assert_eq!(dn.night_factor(at("23:00")), 0.0);
+        assert_eq!(dn.night_factor(at("00:30")), 0.5);
+        assert_eq!(dn.night_factor(at("03:00")), 1.0);
+        assert!(dn.is_night(at("03:00")));
+        assert!(!dn.is_night(at("23:59")));
+    }
+
+    #[test]
+    fn blends_ranges() {
+        assert_eq!(blend(&(20..200), &(0..40), 0.0), 20..200);
+        assert_eq!(blend(&(20..200), &(0..40), 0.5), 10..120);
+        assert_eq!(blend(&(20..200), &(0..40), 1.0), 0..40);
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        assert!(TimeOfDay::from_str("24:00").is_err());
+        assert!(TimeOfDay::from_str("07:60").is_err());
+        assert!(TimeOfDay::from_str("0700").is_err());
+    }
+}
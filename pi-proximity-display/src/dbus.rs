@@ -0,0 +1,280 @@
+//! A D-Bus service exposing sensor and display state, for `--dbus`.
+//!
+//! The service owns the bus name `io.github.timothyb89.ProximityDisplay` and
+//! serves a single object at `/io/github/timothyb89/ProximityDisplay`, whose
+//! interface of the same name has these read-only properties:
+//!
+//! * `State` (`s`): the presence state, e.g. `detected` or `cleared`
+//! * `Proximity` (`u`), `Ambient` (`u`) and `Lux` (`d`): the latest readings
+//! * `Brightness` (`i`): the last brightness written, or -1 if unknown
+//! * `Power` (`s`): `on`, `off`, or `unknown`
+//! * `PowerOverride` (`s`): `on`, `off`, or `auto`
+//! * `BrightnessOverride` (`i`): the overridden brightness, or -1 for `auto`
+//!
+//! and two methods, which set the same overrides as the HTTP control API:
+//!
+//! * `SetPower(s mode)` with `on`, `off`, or `auto`
+//! * `SetBrightness(i brightness)` with a brightness value, or -1 for `auto`
+//!
+//! `PropertiesChanged` is emitted whenever any property other than the
+//! readings changes; those change on nearly every poll, so are only
+//! available through `Get`/`GetAll`.
+//!
+//! zbus serves calls from its own thread; overrides they set are queued and
+//! applied from the sensor loop by [`DbusService::poll`]. On the system bus,
+//! a policy file allowing the process to own the name must be installed
+//! under `/usr/share/dbus-1/system.d`.
+
+use std::mem;
+
+use clap::ValueEnum;
+use color_eyre::Result;
+use tracing::info;
+use zbus::{
+    blocking::{connection, object_server::InterfaceRef, Connection},
+    fdo, interface,
+};
+
+use crate::{display::DisplayPowerMode, overrides::Overrides, reading::Reading};
+
+pub const BUS_NAME: &str = "io.github.timothyb89.ProximityDisplay";
+pub const OBJECT_PATH: &str = "/io/github/timothyb89/ProximityDisplay";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+/// The values of the service's properties.
+#[derive(Debug, Clone, PartialEq)]
+struct Properties {
+    state: &'static str,
+    proximity: u32,
+    ambient: u32,
+    lux: f64,
+    brightness: i32,
+    power: &'static str,
+    power_override: &'static str,
+    brightness_override: i32,
+}
+
+impl Default for Properties {
+    fn default() -> Properties {
+        Properties {
+            state: "unknown",
+            proximity: 0,
+            ambient: 0,
+            lux: 0.0,
+            brightness: -1,
+            power: "unknown",
+            power_override: "auto",
+            brightness_override: -1,
+        }
+    }
+}
+
+fn power_name(power: Option<DisplayPowerMode>, none: &'static str) -> &'static str {
+    match power {
+        Some(DisplayPowerMode::On) => "on",
+        Some(DisplayPowerMode::Off) => "off",
+        None => none,
+    }
+}
+
+impl Properties {
+    fn new(reading: &Reading, power: Option<DisplayPowerMode>, overrides: Overrides) -> Properties {
+        Properties {
+            state: reading.state,
+            proximity: reading.proximity,
+            ambient: reading.ambient,
+            lux: reading.lux as f64,
+            brightness: reading.brightness.map_or(-1, |b| b as i32),
+            power: power_name(power, "unknown"),
+            power_override: power_name(overrides.power, "auto"),
+            brightness_override: overrides.brightness.map_or(-1, |b| b as i32),
+        }
+    }
+}
+
+/// An override set by a method call, waiting for the next poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    Power(Option<DisplayPowerMode>),
+    Brightness(Option<u32>),
+}
+
+/// The object served at `OBJECT_PATH`.
+struct Service {
+    properties: Properties,
+    max_brightness: u32,
+    requests: Vec<Request>,
+}
+
+#[interface(name = "io.github.timothyb89.ProximityDisplay")]
+impl Service {
+    #[zbus(property)]
+    fn state(&self) -> String {
+        self.properties.state.to_string()
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn proximity(&self) -> u32 {
+        self.properties.proximity
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn ambient(&self) -> u32 {
+        self.properties.ambient
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn lux(&self) -> f64 {
+        self.properties.lux
+    }
+
+    #[zbus(property)]
+    fn brightness(&self) -> i32 {
+        self.properties.brightness
+    }
+
+    #[zbus(property)]
+    fn power(&self) -> String {
+        self.properties.power.to_string()
+    }
+
+    #[zbus(property)]
+    fn power_override(&self) -> String {
+        self.properties.power_override.to_string()
+    }
+
+    #[zbus(property)]
+    fn brightness_override(&self) -> i32 {
+        self.properties.brightness_override
+    }
+
+    fn set_power(&mut self, mode: &str) -> fdo::Result<()> {
+        let power = match mode {
+            "on" => Some(DisplayPowerMode::On),
+            "off" => Some(DisplayPowerMode::Off),
+            "auto" => None,
+            _ => return Err(fdo::Error::InvalidArgs("expected on, off, or auto".to_string())),
+        };
+
+        info!("dbus: power override set to {mode}");
+        self.requests.push(Request::Power(power));
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, brightness: i32) -> fdo::Result<()> {
+        let brightness = match brightness {
+            -1 => None,
+            b if (0..=self.max_brightness as i64).contains(&(b as i64)) => Some(b as u32),
+            _ => {
+                let detail = format!("brightness must be -1 (auto) or 0 to {}", self.max_brightness);
+                return Err(fdo::Error::InvalidArgs(detail));
+            },
+        };
+
+        info!("dbus: brightness override set to {}", brightness.map_or("auto".to_string(), |b| b.to_string()));
+        self.requests.push(Request::Brightness(brightness));
+        Ok(())
+    }
+}
+
+pub struct DbusService {
+    // kept to hold the connection open
+    _connection: Connection,
+    service: InterfaceRef<Service>,
+}
+
+impl DbusService {
+    /// Connects to the bus and claims `BUS_NAME`. `max_brightness` bounds
+    /// brightness overrides.
+    pub fn connect(bus: Bus, max_brightness: u32) -> Result<DbusService> {
+        let builder = match bus {
+            Bus::Session => connection::Builder::session()?,
+            Bus::System => connection::Builder::system()?,
+        };
+        let service = Service { properties: Properties::default(), max_brightness, requests: Vec::new() };
+        let connection = builder.name(BUS_NAME)?.serve_at(OBJECT_PATH, service)?.build()?;
+        let service = connection.object_server().interface(OBJECT_PATH)?;
+        info!("serving D-Bus name {BUS_NAME} on the {bus:?} bus at {OBJECT_PATH}");
+
+        Ok(DbusService { _connection: connection, service })
+    }
+
+    /// Applies overrides set by method calls since the last poll to
+    /// `overrides`.
+    pub fn poll(&mut self, overrides: &mut Overrides) {
+        for request in self.service.get_mut().requests.drain(..) {
+            match request {
+                Request::Power(power) => overrides.power = power,
+                Request::Brightness(brightness) => overrides.brightness = brightness,
+            }
+        }
+    }
+
+    /// Updates the properties from the latest reading, emitting
+    /// `PropertiesChanged` for any that changed. An error means the
+    /// connection is no longer usable.
+    pub fn update(&mut self, reading: &Reading, power: Option<DisplayPowerMode>, overrides: Overrides) -> Result<()> {
+        let mut service = self.service.get_mut();
+        let old = mem::replace(&mut service.properties, Properties::new(reading, power, overrides));
+        let new = &service.properties;
+        let emitter = self.service.signal_emitter();
+
+        async_io::block_on(async {
+            if new.state != old.state {
+                service.state_changed(emitter).await?;
+            }
+            if new.brightness != old.brightness {
+                service.brightness_changed(emitter).await?;
+            }
+            if new.power != old.power {
+                service.power_changed(emitter).await?;
+            }
+            if new.power_override != old.power_override {
+                service.power_override_changed(emitter).await?;
+            }
+            if new.brightness_override != old.brightness_override {
+                service.brightness_override_changed(emitter).await?;
+            }
+            Ok::<_, zbus::Error>(())
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service {
+        Service { properties: Properties::default(), max_brightness: 255, requests: Vec::new() }
+    }
+
+    #[test]
+    fn queues_valid_overrides() {
+        let mut service = service();
+        service.set_power("off").unwrap();
+        service.set_brightness(255).unwrap();
+        service.set_brightness(-1).unwrap();
+        service.set_power("auto").unwrap();
+        assert_eq!(
+            service.requests,
+            [
+                Request::Power(Some(DisplayPowerMode::Off)),
+                Request::Brightness(Some(255)),
+                Request::Brightness(None),
+                Request::Power(None),
+            ]
+        );
+
+        assert!(service.set_power("dim").is_err());
+        assert!(service.set_brightness(256).is_err());
+        assert!(service.set_brightness(-2).is_err());
+        assert_eq!(service.requests.len(), 4);
+    }
+}
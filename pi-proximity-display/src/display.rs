@@ -1,17 +1,242 @@
 use std::{
-    ffi::OsStr, fs, path::{Path, PathBuf}, process::{Command, Output}
+    env, ffi::OsStr, fs, ops::Range, os::unix::fs::PermissionsExt, path::{Path, PathBuf}, process::{Command, Output},
+    str::FromStr, thread, time::{Duration, Instant},
 };
 
-use color_eyre::{eyre::eyre, Result};
+use clap::ValueEnum;
 use serde_derive::Deserialize;
 use tracing::{info, warn};
 
+use crate::sway;
+
+/// How long to wait after a power command before querying whether it took.
+const VERIFY_POWER_DELAY: Duration = Duration::from_millis(200);
+
+/// Number of brightness writes used for `--fade-in` and `--fade-out`.
+const FADE_STEPS: u32 = 20;
+
+mod error;
+
+pub use error::{DisplayError, Result};
+
+/// The mechanism used to turn displays on and off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum PowerBackend {
+    /// Runs `wlopm` for each power change.
+    #[default]
+    Wlopm,
+
+    /// Talks to sway directly via the IPC socket named by `SWAYSOCK`.
+    Swayipc,
+}
+
+/// The mechanism used to set display brightness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum BrightnessBackend {
+    /// Writes the sysfs backlight `brightness` attribute.
+    #[default]
+    Sysfs,
+
+    /// Runs a user-provided shell command for each change.
+    Command,
+}
+
+/// An additional backlight node written in tandem with a display's primary
+/// backlight.
+#[derive(Debug)]
+pub struct LinkedBacklight {
+    pub sysfs_path: PathBuf,
+    pub max_brightness: u32,
+}
+
+/// An auxiliary brightness node (e.g. a keyboard backlight) that follows the
+/// display's power state, with its own brightness range, parsed from
+/// `path:min..max[:deadband]`.
+#[derive(Debug, Clone)]
+pub struct AuxBacklightSpec {
+    pub sysfs_path: PathBuf,
+    pub range: Range<u32>,
+
+    /// Minimum change in target brightness before the node is rewritten.
+    pub deadband: u32,
+}
+
+impl FromStr for AuxBacklightSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Invalid aux backlight (expected path:min..max[:deadband]): {s}");
+
+        let (rest, last) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let (path, range, deadband) = if last.contains("..") {
+            (rest, last, "1")
+        } else {
+            let (path, range) = rest.rsplit_once(':').ok_or_else(invalid)?;
+            (path, range, last)
+        };
+
+        let (min, max) = range.split_once("..").ok_or_else(invalid)?;
+        let min = u32::from_str(min).map_err(|_| format!("Invalid aux backlight minimum: {min}"))?;
+        let max = u32::from_str(max).map_err(|_| format!("Invalid aux backlight maximum: {max}"))?;
+        if min > max {
+            return Err(format!("Aux backlight range {min}..{max} is inverted"));
+        }
+
+        let deadband = u32::from_str(deadband)
+            .map_err(|_| format!("Invalid aux backlight deadband: {deadband}"))?;
+
+        Ok(AuxBacklightSpec {
+            sysfs_path: PathBuf::from(path),
+            range: min..max,
+            deadband: deadband.max(1),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct AuxBacklight {
+    pub spec: AuxBacklightSpec,
+    pub max_brightness: u32,
+
+    /// The last brightness written, if any.
+    brightness: Option<u32>,
+}
+
+impl AuxBacklight {
+    /// Opens an auxiliary node, verifying that its range fits within its
+    /// `max_brightness`.
+    pub fn open(spec: AuxBacklightSpec) -> Result<AuxBacklight> {
+        let max_brightness = read_sysfs_u32(spec.sysfs_path.join("max_brightness"))?;
+        if spec.range.end > max_brightness {
+            return Err(DisplayError::BrightnessOutOfRange {
+                brightness: spec.range.end,
+                max: max_brightness,
+            });
+        }
+
+        Ok(AuxBacklight { spec, max_brightness, brightness: None })
+    }
+
+    /// Writes a brightness if it differs from the last written value by at
+    /// least the deadband, or if it turns the node fully on or off. Returns
+    /// true if it was written.
+    pub fn set_brightness(&mut self, brightness: u32) -> Result<bool> {
+        let brightness = brightness.min(self.max_brightness);
+        let changed = match self.brightness {
+            None => true,
+            Some(last) => {
+                (last == 0) != (brightness == 0) || last.abs_diff(brightness) >= self.spec.deadband
+            },
+        };
+        if !changed {
+            return Ok(false);
+        }
+
+        write_sysfs(self.spec.sysfs_path.join("brightness"), brightness)?;
+        self.brightness = Some(brightness);
+
+        Ok(true)
+    }
+}
+
+/// Options for invoking `wlopm`.
+#[derive(Debug, Clone)]
+pub struct WlopmConfig {
+    /// The `wlopm` executable, either a bare name looked up on `PATH` or a
+    /// path to the binary.
+    pub path: PathBuf,
+
+    /// Extra arguments passed to every `wlopm` invocation.
+    pub extra_args: Vec<String>,
+
+    /// The minimum time between `wlopm` invocations. Power changes requested
+    /// sooner are coalesced, and the latest is sent by `flush_power` once the
+    /// interval elapses.
+    pub min_interval: Duration,
+}
+
+impl Default for WlopmConfig {
+    fn default() -> Self {
+        WlopmConfig {
+            path: PathBuf::from("wlopm"),
+            extra_args: Vec::new(),
+            min_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+impl WlopmConfig {
+    /// Checks that the configured path refers to an executable file. Bare
+    /// names are looked up on `PATH`, as they would be when run.
+    pub fn validate(&self) -> Result<()> {
+        let error = |message: String| DisplayError::PowerCommand {
+            backend: PowerBackend::Wlopm,
+            message,
+        };
+
+        let bare = self.path.parent().is_some_and(|p| p.as_os_str().is_empty());
+        if bare {
+            let found = env::var_os("PATH")
+                .map(|paths| env::split_paths(&paths).any(|dir| is_executable(&dir.join(&self.path))))
+                .unwrap_or(false);
+            if !found {
+                return Err(error(format!("{} not found on PATH", self.path.display())));
+            }
+
+            return Ok(());
+        }
+
+        let metadata = fs::metadata(&self.path)
+            .map_err(|e| error(format!("{}: {e}", self.path.display())))?;
+
+        if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+            return Err(error(format!("{} is not an executable file", self.path.display())));
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Display {
     pub sysfs_path: PathBuf,
     pub name: String,
     pub brightness: u32,
     pub max_brightness: u32,
+
+    /// The last power mode successfully set, if any.
+    pub power: Option<DisplayPowerMode>,
+
+    /// The brightness before `fade_off`, restored once the display powers on.
+    faded_from: Option<u32>,
+
+    /// A power mode waiting for `WlopmConfig::min_interval` to elapse.
+    pending_power: Option<DisplayPowerMode>,
+
+    /// When a power command was last sent.
+    last_power_command: Option<Instant>,
+
+    /// If set, the power state is queried after each power command, and the
+    /// command resent up to this many times if it didn't take.
+    pub verify_power: Option<u32>,
+
+    /// Power changes confirmed by a query, and those still not reported
+    /// after all retries (or that couldn't be queried).
+    pub power_verified: u64,
+    pub power_unverified: u64,
+
+    pub power_backend: PowerBackend,
+    pub wlopm: WlopmConfig,
+
+    pub brightness_backend: BrightnessBackend,
+
+    /// The shell command used by `BrightnessBackend::Command`, with `{value}`
+    /// replaced by the brightness to set.
+    pub brightness_command: Option<String>,
+
+    /// Additional backlight nodes that are set whenever this display's
+    /// brightness changes, scaled to their own `max_brightness`.
+    pub linked: Vec<LinkedBacklight>,
 }
 
 impl Display {
@@ -19,51 +244,418 @@ impl Display {
         info!("trying display: {}", p.as_ref().display());
         let p = p.as_ref();
 
-        let name = fs::read_to_string(p.join("display_name"))?.trim().to_string();
-        let brightness: u32 = fs::read_to_string(p.join("brightness"))?.trim().parse()?;
-        let max_brightness: u32 = fs::read_to_string(p.join("max_brightness"))?.trim().parse()?;
+        let name = read_display_name(p);
+        let brightness = read_sysfs_u32(p.join("brightness"))?;
+        let max_brightness = read_sysfs_u32(p.join("max_brightness"))?;
 
         Ok(Display {
             name,
             brightness,
             max_brightness,
             sysfs_path: p.to_path_buf(),
+            power: None,
+            faded_from: None,
+            pending_power: None,
+            last_power_command: None,
+            verify_power: None,
+            power_verified: 0,
+            power_unverified: 0,
+            power_backend: PowerBackend::default(),
+            wlopm: WlopmConfig::default(),
+            brightness_backend: BrightnessBackend::default(),
+            brightness_command: None,
+            linked: Vec::new(),
         })
     }
 
-    /// Rereads the display from disk.
-    pub fn reload(&self) -> Result<Display> {
-        Display::try_from_path(&self.sysfs_path)
+    /// Creates a display with no sysfs backlight, whose power can still be
+    /// controlled by name (e.g. with wlopm). Brightness writes through the
+    /// sysfs backend are ignored.
+    pub fn without_backlight(name: impl Into<String>) -> Display {
+        Display {
+            name: name.into(),
+            brightness: 0,
+            max_brightness: 0,
+            sysfs_path: PathBuf::new(),
+            power: None,
+            faded_from: None,
+            pending_power: None,
+            last_power_command: None,
+            verify_power: None,
+            power_verified: 0,
+            power_unverified: 0,
+            power_backend: PowerBackend::default(),
+            wlopm: WlopmConfig::default(),
+            brightness_backend: BrightnessBackend::default(),
+            brightness_command: None,
+            linked: Vec::new(),
+        }
+    }
+
+    /// Returns false if this display was created with `without_backlight`.
+    pub fn has_backlight(&self) -> bool {
+        !self.sysfs_path.as_os_str().is_empty()
+    }
+
+    /// Links an additional backlight node to this display, such that it is
+    /// written whenever this display's brightness is set. If its
+    /// `max_brightness` differs from this display's, values are scaled
+    /// proportionally.
+    pub fn link_backlight(&mut self, p: impl AsRef<Path>) -> Result<()> {
+        let p = p.as_ref();
+        let max_brightness = read_sysfs_u32(p.join("max_brightness"))?;
+
+        if max_brightness != self.max_brightness {
+            warn!(
+                "backlight {} has max_brightness {max_brightness} (vs {}), values will be scaled",
+                p.display(),
+                self.max_brightness
+            );
+        }
+
+        self.linked.push(LinkedBacklight {
+            sysfs_path: p.to_path_buf(),
+            max_brightness,
+        });
+
+        Ok(())
+    }
+
+    /// Re-reads `max_brightness` for this display and any linked backlights,
+    /// returning true if any value changed.
+    pub fn refresh_max_brightness(&mut self) -> Result<bool> {
+        if !self.has_backlight() {
+            return Ok(false);
+        }
+
+        let mut changed = false;
+
+        let max_brightness = read_sysfs_u32(self.sysfs_path.join("max_brightness"))?;
+        if max_brightness != self.max_brightness {
+            self.max_brightness = max_brightness;
+            changed = true;
+        }
+
+        for linked in &mut self.linked {
+            let max_brightness = read_sysfs_u32(linked.sysfs_path.join("max_brightness"))?;
+            if max_brightness != linked.max_brightness {
+                linked.max_brightness = max_brightness;
+                changed = true;
+            }
+        }
+
+        Ok(changed)
     }
 
     pub fn set_brightness(&mut self, brightness: u32) -> Result<()> {
         let range = 0..=self.max_brightness;
         if !range.contains(&brightness) {
-            return Err(eyre!(
-                "brightness out of range: {brightness} not within 0 <= v <= {}",
-                self.max_brightness
-            ));
+            return Err(DisplayError::BrightnessOutOfRange {
+                brightness,
+                max: self.max_brightness,
+            });
+        }
+
+        match self.brightness_backend {
+            BrightnessBackend::Sysfs if !self.has_backlight() => (),
+            BrightnessBackend::Sysfs => {
+                write_sysfs(self.sysfs_path.join("brightness"), brightness)?;
+                for linked in &self.linked {
+                    let scaled = scale_brightness(brightness, self.max_brightness, linked.max_brightness);
+                    write_sysfs(linked.sysfs_path.join("brightness"), scaled)?;
+                }
+            },
+            BrightnessBackend::Command => {
+                let command = self.brightness_command.as_deref().unwrap_or_default();
+                run_brightness_command(command, brightness)?;
+            },
         }
 
-        fs::write(self.sysfs_path.join("brightness"), brightness.to_string())?;
         self.brightness = brightness;
+        self.faded_from = None;
+
+        Ok(())
+    }
+
+    /// Ramps brightness from its current value to `target` in even steps over
+    /// `duration`. This blocks for the whole duration.
+    pub fn fade_brightness(&mut self, target: u32, duration: Duration) -> Result<()> {
+        let from = self.brightness as i64;
+        let step_delay = duration / FADE_STEPS;
+        for step in 1..=FADE_STEPS {
+            let value = from + (target as i64 - from) * step as i64 / FADE_STEPS as i64;
+            if value as u32 != self.brightness {
+                self.set_brightness(value as u32)?;
+            }
+            if step < FADE_STEPS {
+                thread::sleep(step_delay);
+            }
+        }
 
         Ok(())
     }
 
+    /// Fades brightness to 0 over `duration`, then powers off. The previous
+    /// brightness is restored when the display next powers on, unless another
+    /// brightness is set first.
+    pub fn fade_off(&mut self, duration: Duration) -> Result<()> {
+        let from = self.brightness;
+        self.fade_brightness(0, duration)?;
+        self.faded_from = Some(from);
+
+        self.set_power(DisplayPowerMode::Off)
+    }
+
+    /// Restores the brightness from before `fade_off`, if it hasn't been
+    /// replaced since.
+    fn restore_faded(&mut self) -> Result<()> {
+        match self.faded_from {
+            Some(brightness) => self.set_brightness(brightness),
+            None => Ok(()),
+        }
+    }
+
+    /// Powers on, then fades brightness up from 0 over `duration` to the
+    /// current brightness, or the one from before `fade_off`.
+    pub fn fade_on(&mut self, duration: Duration) -> Result<()> {
+        let target = self.faded_from.take().unwrap_or(self.brightness);
+        if self.brightness != 0 {
+            self.set_brightness(0)?;
+        }
+        self.set_power(DisplayPowerMode::On)?;
+
+        if self.power != Some(DisplayPowerMode::On) {
+            // the power change was deferred, so restore once it's sent
+            self.faded_from = Some(target);
+            return Ok(());
+        }
+
+        self.fade_brightness(target, duration)
+    }
+
     pub fn set_power(&mut self, mode: DisplayPowerMode) -> Result<()> {
-        match mode {
-            DisplayPowerMode::On => wlopm_on(&self.name),
-            DisplayPowerMode::Off => wlopm_off(&self.name),
+        let rate_limited = self.power_backend == PowerBackend::Wlopm
+            && self.last_power_command.is_some_and(|t| t.elapsed() < self.wlopm.min_interval);
+        if rate_limited {
+            if self.power == Some(mode) {
+                if self.pending_power.take().is_some() {
+                    info!("power returned to {mode:?} before a pending change was sent, dropping it");
+                }
+                if mode == DisplayPowerMode::On {
+                    self.restore_faded()?;
+                }
+            } else if self.pending_power.replace(mode) != Some(mode) {
+                info!("power commands rate limited, coalescing change to {mode:?}");
+            }
+
+            return Ok(());
+        }
+
+        self.pending_power = None;
+        self.last_power_command = Some(Instant::now());
+        self.send_power(mode)?;
+        self.power = Some(mode);
+        if let Some(retries) = self.verify_power {
+            self.verify_sent_power(mode, retries)?;
+        }
+        if mode == DisplayPowerMode::On {
+            self.restore_faded()?;
+        }
+
+        Ok(())
+    }
+
+    /// Queries the power state after a command, resending it up to `retries`
+    /// times until the compositor reports `mode`.
+    fn verify_sent_power(&mut self, mode: DisplayPowerMode, retries: u32) -> Result<()> {
+        for attempt in 0..=retries {
+            thread::sleep(VERIFY_POWER_DELAY);
+            let queried = match self.query_power() {
+                Ok(queried) => queried,
+                Err(e) => {
+                    warn!("could not verify power for display {}: {e}", self.name);
+                    break;
+                },
+            };
+
+            match queried {
+                Some(actual) if actual == mode => {
+                    self.power_verified += 1;
+                    if attempt > 0 {
+                        info!("display {} power {mode:?} verified after {attempt} retries", self.name);
+                    }
+
+                    return Ok(());
+                },
+                Some(actual) if attempt < retries => {
+                    warn!("display {} still reports power {actual:?} after setting {mode:?}, retrying", self.name);
+                    self.send_power(mode)?;
+                },
+                Some(actual) => {
+                    warn!(
+                        "display {} still reports power {actual:?} after setting {mode:?} and {retries} retries",
+                        self.name
+                    );
+                },
+                None => {
+                    warn!("could not verify power for display {}: not reported by {:?}", self.name, self.power_backend);
+                    break;
+                },
+            }
+        }
+
+        self.power_unverified += 1;
+        Ok(())
+    }
+
+    /// Queries the display's current power state from the power backend, or
+    /// `None` if it isn't reported.
+    pub fn query_power(&self) -> Result<Option<DisplayPowerMode>> {
+        match self.power_backend {
+            PowerBackend::Wlopm => wlopm_query(&self.wlopm, &self.name),
+            PowerBackend::Swayipc => sway::output_power(&self.name)
+                .map(|power| power.map(|on| if on { DisplayPowerMode::On } else { DisplayPowerMode::Off }))
+                .map_err(|e| DisplayError::PowerCommand {
+                    backend: PowerBackend::Swayipc,
+                    message: format!("{e:#}"),
+                }),
+        }
+    }
+
+    /// Sends any power change coalesced by `set_power` once the rate limit
+    /// allows.
+    pub fn flush_power(&mut self) -> Result<()> {
+        match self.pending_power {
+            Some(mode) => self.set_power(mode),
+            None => Ok(()),
         }
     }
+
+    /// Returns true if this display is known to be powered off.
+    pub fn is_off(&self) -> bool {
+        self.power == Some(DisplayPowerMode::Off)
+    }
+
+    fn send_power(&self, mode: DisplayPowerMode) -> Result<()> {
+        match (self.power_backend, mode) {
+            (PowerBackend::Wlopm, DisplayPowerMode::On) => wlopm_on(&self.wlopm, &self.name),
+            (PowerBackend::Wlopm, DisplayPowerMode::Off) => wlopm_off(&self.wlopm, &self.name),
+            (PowerBackend::Swayipc, mode) => sway::set_output_power(&self.name, mode)
+                .map_err(|e| DisplayError::PowerCommand {
+                    backend: PowerBackend::Swayipc,
+                    message: format!("{e:#}"),
+                }),
+        }
+    }
+}
+
+/// Runs a brightness command, replacing `{value}` with the given brightness.
+/// Nonzero exit statuses are logged but not treated as errors.
+fn run_brightness_command(command: &str, brightness: u32) -> Result<()> {
+    let command = command.replace("{value}", &brightness.to_string());
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .output()
+        .map_err(|e| DisplayError::BrightnessCommand {
+            message: format!("could not run '{command}': {e}"),
+        })?;
+
+    if !output.status.success() {
+        warn!(
+            "brightness command '{command}' exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
 }
 
-pub fn list_displays() -> Result<Vec<Display>> {
+/// Scales a brightness value from one `max_brightness` to another.
+fn scale_brightness(brightness: u32, from_max: u32, to_max: u32) -> u32 {
+    if from_max == to_max || from_max == 0 {
+        return brightness.min(to_max);
+    }
+
+    ((brightness as f32 * to_max as f32 / from_max as f32).round() as u32).min(to_max)
+}
+
+/// Reads a sysfs attribute, trimming any surrounding whitespace.
+fn read_sysfs(path: PathBuf) -> Result<String> {
+    match fs::read_to_string(&path) {
+        Ok(s) => Ok(s.trim().to_string()),
+        Err(source) => Err(DisplayError::Io { path, source }),
+    }
+}
+
+/// Reads the backlight's `display_name`, falling back to the backlight's
+/// directory name if it is missing, unreadable, or empty. Not all backlight
+/// drivers provide this attribute.
+fn read_display_name(p: &Path) -> String {
+    let fallback = || p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+    let path = p.join("display_name");
+    match fs::read(&path) {
+        Ok(bytes) => {
+            let name = String::from_utf8_lossy(&bytes).trim().to_string();
+            if name.is_empty() {
+                let name = fallback();
+                warn!("{} is empty, using name {name}", path.display());
+                name
+            } else {
+                name
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let name = fallback();
+            info!("{} has no display_name, using name {name}", p.display());
+            name
+        },
+        Err(e) => {
+            let name = fallback();
+            warn!("could not read {}, using name {name}: {e}", path.display());
+            name
+        },
+    }
+}
+
+fn read_sysfs_u32(path: PathBuf) -> Result<u32> {
+    let s = read_sysfs(path.clone())?;
+
+    s.parse().map_err(|source| DisplayError::Parse { path, source })
+}
+
+fn write_sysfs(path: PathBuf, value: impl ToString) -> Result<()> {
+    fs::write(&path, value.to_string()).map_err(|source| DisplayError::Io { path, source })
+}
+
+/// The standard location of the sysfs backlight class.
+pub const BACKLIGHT_ROOT: &str = "/sys/class/backlight";
+
+/// Lists the displays with a backlight under `root`, normally
+/// `BACKLIGHT_ROOT`, sorted by name and then sysfs path so the first display
+/// is the same across boots. If the directory doesn't exist (e.g. in some
+/// containers), an empty list is returned.
+pub fn list_displays(root: impl AsRef<Path>) -> Result<Vec<Display>> {
     let mut displays = Vec::new();
 
-    for child in fs::read_dir("/sys/class/backlight")? {
-        let child = child?;
+    let root = root.as_ref();
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("backlight sysfs not present at {}, no backlights available", root.display());
+            return Ok(displays);
+        },
+        Err(source) => return Err(DisplayError::Io { path: root.to_path_buf(), source }),
+    };
+
+    for child in entries {
+        let child = child.map_err(|source| DisplayError::Io {
+            path: root.to_path_buf(),
+            source,
+        })?;
 
         match Display::try_from_path(child.path()) {
             Ok(display) => displays.push(display),
@@ -74,10 +666,13 @@ pub fn list_displays() -> Result<Vec<Display>> {
         }
     }
 
+    // read_dir order depends on the filesystem
+    displays.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.sysfs_path.cmp(&b.sysfs_path)));
+
     Ok(displays)
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum DisplayPowerMode {
     On,
     Off
@@ -89,40 +684,237 @@ pub enum DisplayPowerMode {
 //     power_mode: DisplayPowerMode,
 // }
 
-fn wlopm<I, S>(args: I) -> Result<Output>
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+fn wlopm<I, S>(config: &WlopmConfig, args: I) -> Result<Output>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    let output = Command::new("wlopm")
+    Command::new(&config.path)
+        .args(&config.extra_args)
         .args(args)
-        .output()?;
-
-    Ok(output)
+        .output()
+        .map_err(|e| DisplayError::PowerCommand {
+            backend: PowerBackend::Wlopm,
+            message: format!("could not run wlopm: {e}"),
+        })
 }
 
 fn wlopm_check_error(stderr: &[u8]) -> Result<()> {
     // annoyingly, wlopm doesn't return nonzero exit codes if an error occurs :/
     if !stderr.is_empty() {
-        let err = String::from_utf8_lossy(&stderr);
+        let err = String::from_utf8_lossy(stderr);
         if err.starts_with("ERROR") {
-            return Err(eyre!("wlopm returned an error: {err}"));
+            return Err(DisplayError::PowerCommand {
+                backend: PowerBackend::Wlopm,
+                message: err.trim().to_string(),
+            });
         }
     }
 
     Ok(())
 }
 
-fn wlopm_on(display_name: impl AsRef<str>) -> Result<()> {
-    let output = wlopm(&["--on", display_name.as_ref()])?;
+fn wlopm_on(config: &WlopmConfig, display_name: impl AsRef<str>) -> Result<()> {
+    let output = wlopm(config, ["--on", display_name.as_ref()])?;
     wlopm_check_error(&output.stderr)?;
 
     Ok(())
 }
 
-fn wlopm_off(display_name: impl AsRef<str>) -> Result<()> {
-    let output = wlopm(&["--off", display_name.as_ref()])?;
+fn wlopm_off(config: &WlopmConfig, display_name: impl AsRef<str>) -> Result<()> {
+    let output = wlopm(config, ["--off", display_name.as_ref()])?;
     wlopm_check_error(&output.stderr)?;
 
     Ok(())
 }
+
+/// Queries an output's power mode. Without arguments, wlopm prints one
+/// `<output> <on|off>` line per output.
+fn wlopm_query(config: &WlopmConfig, display_name: impl AsRef<str>) -> Result<Option<DisplayPowerMode>> {
+    let output = wlopm(config, [] as [&str; 0])?;
+    wlopm_check_error(&output.stderr)?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mode = stdout
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(name, _)| *name == display_name.as_ref())
+        .and_then(|(_, mode)| match mode.trim() {
+            "on" => Some(DisplayPowerMode::On),
+            "off" => Some(DisplayPowerMode::Off),
+            _ => None,
+        });
+
+    Ok(mode)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::process;
+
+    use super::*;
+
+    pub(crate) fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pi-proximity-display-{}-{name}", process::id()))
+    }
+
+    /// Creates a fake sysfs backlight directory under the temp dir.
+    fn backlight(name: &str, brightness: u32, max_brightness: u32) -> PathBuf {
+        let path = temp_path(name);
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("brightness"), format!("{brightness}\n")).unwrap();
+        fs::write(path.join("max_brightness"), format!("{max_brightness}\n")).unwrap();
+
+        path
+    }
+
+    /// A display whose brightness and power commands both append to `log`,
+    /// with `100` of `100` brightness.
+    pub(crate) fn logging_display(dir: &Path) -> Display {
+        let log = dir.join("log");
+        let wlopm = dir.join("wlopm");
+        fs::write(&wlopm, format!("#!/bin/sh\necho \"power $1\" >> {}\n", log.display())).unwrap();
+        fs::set_permissions(&wlopm, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut display = Display::without_backlight("DP-1");
+        display.max_brightness = 100;
+        display.brightness = 100;
+        display.brightness_backend = BrightnessBackend::Command;
+        display.brightness_command = Some(format!("echo {{value}} >> {}", log.display()));
+        display.wlopm = WlopmConfig { path: wlopm, extra_args: Vec::new(), min_interval: Duration::ZERO };
+
+        display
+    }
+
+    #[test]
+    fn fades_around_power_changes() {
+        let dir = temp_path("fade");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut display = logging_display(&dir);
+        let steps = |from: u32, to: u32| -> Vec<String> {
+            (1..=FADE_STEPS as i64)
+                .map(|step| (from as i64 + (to as i64 - from as i64) * step / FADE_STEPS as i64).to_string())
+                .collect()
+        };
+
+        display.fade_off(Duration::ZERO).unwrap();
+        let mut expected = steps(100, 0);
+        expected.push("power --off".to_string());
+        let log = fs::read_to_string(dir.join("log")).unwrap();
+        assert_eq!(log.lines().collect::<Vec<_>>(), expected);
+
+        // fading in powers on first, then ramps back to the brightness from
+        // before the fade out
+        fs::remove_file(dir.join("log")).unwrap();
+        display.fade_on(Duration::ZERO).unwrap();
+        let mut expected = vec!["power --on".to_string()];
+        expected.extend(steps(0, 100));
+        let log = fs::read_to_string(dir.join("log")).unwrap();
+        assert_eq!(log.lines().collect::<Vec<_>>(), expected);
+        assert_eq!(display.brightness, 100);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn validates_wlopm_path() {
+        let wlopm = |path: &Path| WlopmConfig { path: path.to_path_buf(), ..WlopmConfig::default() };
+        assert!(wlopm(Path::new("sh")).validate().is_ok());
+
+        let missing = wlopm(Path::new("pi-proximity-display-missing-wlopm")).validate().unwrap_err();
+        assert!(missing.to_string().contains("not found on PATH"), "{missing}");
+
+        let dir = temp_path("wlopm-validate");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("wlopm");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+        let not_executable = wlopm(&script).validate().unwrap_err();
+        assert!(not_executable.to_string().contains("not an executable file"), "{not_executable}");
+
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(wlopm(&script).validate().is_ok());
+        assert!(wlopm(&dir).validate().is_err());
+        assert!(wlopm(&dir.join("missing")).validate().is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn linked_backlights_are_written_scaled() {
+        let primary = backlight("linked-primary", 0, 100);
+        let secondary = backlight("linked-secondary", 0, 255);
+        fs::write(primary.join("display_name"), "DSI-1\n").unwrap();
+
+        let mut display = Display::try_from_path(&primary).unwrap();
+        display.link_backlight(&secondary).unwrap();
+        display.set_brightness(50).unwrap();
+
+        assert_eq!(read_sysfs_u32(primary.join("brightness")).unwrap(), 50);
+        assert_eq!(read_sysfs_u32(secondary.join("brightness")).unwrap(), 128);
+
+        display.set_brightness(100).unwrap();
+        assert_eq!(read_sysfs_u32(secondary.join("brightness")).unwrap(), 255);
+
+        fs::remove_dir_all(primary).unwrap();
+        fs::remove_dir_all(secondary).unwrap();
+    }
+
+    #[test]
+    fn display_name_falls_back_to_directory() {
+        let path = backlight("name-missing", 0, 100);
+        assert_eq!(read_display_name(&path), path.file_name().unwrap().to_string_lossy());
+
+        fs::write(path.join("display_name"), " \n").unwrap();
+        assert_eq!(read_display_name(&path), path.file_name().unwrap().to_string_lossy());
+
+        fs::write(path.join("display_name"), b"DSI-\xff1\n").unwrap();
+        assert_eq!(read_display_name(&path), "DSI-\u{fffd}1");
+
+        fs::write(path.join("display_name"), "HDMI-A-1\n").unwrap();
+        assert_eq!(Display::try_from_path(&path).unwrap().name, "HDMI-A-1");
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn lists_displays_under_root() {
+        let root = temp_path("list-root");
+        let _ = fs::remove_dir_all(&root);
+        assert!(list_displays(&root).unwrap().is_empty());
+
+        backlight("list-root/panel", 10, 100);
+        fs::create_dir_all(root.join("broken")).unwrap();
+
+        let displays = list_displays(&root).unwrap();
+        assert_eq!(displays.len(), 1);
+        assert_eq!(displays[0].name, "panel");
+        assert_eq!((displays[0].brightness, displays[0].max_brightness), (10, 100));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn lists_displays_in_name_order() {
+        let root = temp_path("order-root");
+        let _ = fs::remove_dir_all(&root);
+        for (dir, name) in [("a", Some("DSI-2")), ("b", Some("DSI-1")), ("c", None)] {
+            let path = backlight(&format!("order-root/{dir}"), 0, 100);
+            if let Some(name) = name {
+                fs::write(path.join("display_name"), name).unwrap();
+            }
+        }
+
+        let names: Vec<_> = list_displays(&root).unwrap().into_iter().map(|d| d.name).collect();
+        assert_eq!(names, ["DSI-1", "DSI-2", "c"]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+use std::{io, num::ParseIntError, path::PathBuf};
+
+use thiserror::Error;
+
+use super::PowerBackend;
+
+#[derive(Debug, Error)]
+pub enum DisplayError {
+  #[error("brightness out of range: {brightness} not within 0 <= v <= {max}")]
+  BrightnessOutOfRange {
+    brightness: u32,
+    max: u32,
+  },
+
+  #[error("sysfs i/o error at {}: {source}", path.display())]
+  Io {
+    path: PathBuf,
+    #[source]
+    source: io::Error,
+  },
+
+  #[error("could not parse {}: {source}", path.display())]
+  Parse {
+    path: PathBuf,
+    #[source]
+    source: ParseIntError,
+  },
+
+  #[error("brightness command failed: {message}")]
+  BrightnessCommand {
+    message: String,
+  },
+
+  #[error("{backend:?} power command failed: {message}")]
+  PowerCommand {
+    backend: PowerBackend,
+    message: String,
+  },
+}
+
+pub type Result<T> = std::result::Result<T, DisplayError>;
@@ -0,0 +1,109 @@
+//! Approximate distance estimation from raw proximity counts.
+//!
+//! The VCNL4010 is not a rangefinder: it measures the amount of reflected IR
+//! light, which depends heavily on the target's size, color, and reflectivity
+//! as well as the LED current and any reflections from the enclosure itself.
+//! Any distance derived from it is a rough approximation that is only
+//! meaningful for targets similar to those used during calibration.
+//!
+//! Calibration points pair a known distance with the proximity count measured
+//! at that distance. Reflected intensity falls off roughly with the square of
+//! distance, so we fit `count = a / d^2 + b` by least squares, where `b`
+//! approximates the baseline count with nothing present. At least two points at
+//! different distances are required, and more points spread across the range
+//! of interest will produce a better fit.
+
+use std::str::FromStr;
+
+/// A single calibration point: a proximity count measured with a target at a
+/// known distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationPoint {
+    pub distance_cm: f32,
+    pub count: u32,
+}
+
+impl FromStr for CalibrationPoint {
+    type Err = String;
+
+    /// Parses a point in the form `distance_cm:count`, e.g. `10:3200`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (distance, count) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid calibration point (expected cm:count): {s}"))?;
+
+        let distance_cm = f32::from_str(distance)
+            .map_err(|_| format!("Invalid calibration distance: {distance}"))?;
+        let count = u32::from_str(count)
+            .map_err(|_| format!("Invalid calibration count: {count}"))?;
+
+        if !distance_cm.is_finite() || distance_cm <= 0.0 {
+            return Err(format!("Calibration distance must be positive: {distance}"));
+        }
+
+        Ok(CalibrationPoint { distance_cm, count })
+    }
+}
+
+/// A fitted approximation of proximity count vs distance.
+#[derive(Debug, Clone, Copy)]
+pub struct DistanceCalibration {
+    /// Scale of the inverse-square term.
+    a: f32,
+
+    /// Approximate baseline count at infinite distance.
+    b: f32,
+}
+
+impl DistanceCalibration {
+    /// Fits a calibration to the given points. Returns an error if there are
+    /// fewer than two distinct distances, or if counts don't decrease with
+    /// distance as expected.
+    pub fn fit(points: &[CalibrationPoint]) -> Result<DistanceCalibration, String> {
+        if points.len() < 2 {
+            return Err("at least two distance calibration points are required".to_string());
+        }
+
+        // linear least squares on x = 1/d^2, y = count
+        let n = points.len() as f32;
+        let xs: Vec<f32> = points.iter().map(|p| 1.0 / (p.distance_cm * p.distance_cm)).collect();
+        let ys: Vec<f32> = points.iter().map(|p| p.count as f32).collect();
+
+        let mean_x = xs.iter().sum::<f32>() / n;
+        let mean_y = ys.iter().sum::<f32>() / n;
+
+        let mut sxx = 0.0;
+        let mut sxy = 0.0;
+        for (x, y) in xs.iter().zip(&ys) {
+            sxx += (x - mean_x) * (x - mean_x);
+            sxy += (x - mean_x) * (y - mean_y);
+        }
+
+        if sxx == 0.0 {
+            return Err("distance calibration points must use at least two distances".to_string());
+        }
+
+        let a = sxy / sxx;
+        let b = mean_y - a * mean_x;
+
+        if !(a.is_finite() && b.is_finite()) || a <= 0.0 {
+            return Err(
+                "distance calibration points must have higher counts at closer distances".to_string()
+            );
+        }
+
+        Ok(DistanceCalibration { a, b })
+    }
+
+    /// Estimates the distance to the target in centimeters for a proximity
+    /// count. Returns `None` for counts at or below the fitted baseline, where
+    /// no meaningful estimate is possible.
+    pub fn estimate_distance_cm(&self, count: u32) -> Option<f32> {
+        let above_baseline = count as f32 - self.b;
+        if above_baseline <= 0.0 {
+            return None;
+        }
+
+        Some((self.a / above_baseline).sqrt())
+    }
+}
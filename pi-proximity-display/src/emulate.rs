@@ -0,0 +1,55 @@
+//! Support for running against a VCNL4010 emulated by the Linux `i2c-stub`
+//! module, so the real I2C code path can be exercised without hardware.
+//!
+//! `i2c-stub` emulates chips as plain arrays of registers that read back
+//! whatever was last written, and supports the SMBus byte data transfers used
+//! by the `vcnl4010` crate. It doesn't emulate any chip behavior, so the
+//! product ID and initial measurement results must be written before the
+//! sensor is opened; `seed_i2c_stub` does this. Measurement results then stay
+//! fixed until changed externally, e.g. with `i2cset`. See
+//! `scripts/i2c-stub.sh` for an example.
+
+use std::path::Path;
+
+use color_eyre::{eyre::eyre, Result};
+use i2cdev::{core::I2CDevice, linux::LinuxI2CDevice};
+use vcnl4010::{
+    ADDR, REG_AMBIENT_LIGHT_RESULT_HIGH, REG_AMBIENT_LIGHT_RESULT_LOW, REG_LED_CURRENT,
+    REG_PRODUCT_ID, REG_PROXIMITY_RESULT_HIGH, REG_PROXIMITY_RESULT_LOW,
+};
+
+/// Product ID register value for a VCNL4010 (product 2, revision 1).
+const PRODUCT_ID: u8 = 0x21;
+
+/// Initial emulated proximity count, a typical idle baseline.
+pub const PROXIMITY: u16 = 2900;
+
+/// Initial emulated ambient light count (about 100 lux).
+pub const AMBIENT: u16 = 400;
+
+/// Writes the registers needed for an `i2c-stub` chip at the sensor's address
+/// to pass as a VCNL4010 with the given measurement results.
+pub fn seed_i2c_stub(i2c_device: impl AsRef<Path>, proximity: u16, ambient: u16) -> Result<()> {
+    let i2c_device = i2c_device.as_ref();
+    let mut device = LinuxI2CDevice::new(i2c_device, ADDR)
+        .map_err(|e| eyre!("could not open emulated sensor on {}: {e}", i2c_device.display()))?;
+
+    let [proximity_high, proximity_low] = proximity.to_be_bytes();
+    let [ambient_high, ambient_low] = ambient.to_be_bytes();
+    let registers = [
+        (REG_PRODUCT_ID, PRODUCT_ID),
+        (REG_LED_CURRENT, 20),
+        (REG_PROXIMITY_RESULT_HIGH, proximity_high),
+        (REG_PROXIMITY_RESULT_LOW, proximity_low),
+        (REG_AMBIENT_LIGHT_RESULT_HIGH, ambient_high),
+        (REG_AMBIENT_LIGHT_RESULT_LOW, ambient_low),
+    ];
+
+    for (register, value) in registers {
+        device
+            .smbus_write_byte_data(register, value)
+            .map_err(|e| eyre!("could not seed emulated register {register:#04x}: {e}"))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! User-defined ambient light to brightness transfer functions, for
+//! `--brightness-expr`, evaluated with `evalexpr`.
+//!
+//! Expressions use evalexpr's syntax over a few variables:
+//!
+//! * `t`: ambient light normalized to 0..1 over `--ambient-light-range`
+//! * `ambient`: the raw ambient light value
+//! * `min`, `max`: the current brightness range
+//!
+//! Along with evalexpr's builtins (`min`, `max`, `floor`, `ceil`, `round`,
+//! `if(cond, then, else)`, and the `math::` functions), the shorthands `pow`,
+//! `sqrt`, `exp`, `ln`, `log10`, `abs`, and `clamp(x, lo, hi)` are available.
+//! The variables are floats, but literals without a decimal point are
+//! integers, so e.g. `1/2` is 0; write `0.5` instead. The result is rounded
+//! to the nearest brightness value.
+
+use std::{fmt, str::FromStr};
+
+use evalexpr::{
+    build_operator_tree, ContextWithMutableFunctions, ContextWithMutableVariables, DefaultNumericTypes,
+    EvalexprResult, Function, HashMapContext, Node, Value,
+};
+
+const VARIABLES: [&str; 4] = ["t", "ambient", "min", "max"];
+
+pub struct Inputs {
+    pub t: f64,
+    pub ambient: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// A parsed `--brightness-expr`.
+#[derive(Debug, Clone)]
+pub struct BrightnessExpr {
+    source: String,
+    root: Node<DefaultNumericTypes>,
+}
+
+impl FromStr for BrightnessExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let root = build_operator_tree(s).map_err(|e| e.to_string())?;
+        if let Some(name) = root.iter_variable_identifiers().find(|v| !VARIABLES.contains(v)) {
+            return Err(format!("unknown variable '{name}'; expected one of {}", VARIABLES.join(", ")));
+        }
+
+        Ok(BrightnessExpr { source: s.to_string(), root })
+    }
+}
+
+impl fmt::Display for BrightnessExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Wraps a one-argument float function.
+fn unary(f: fn(f64) -> f64) -> Function<DefaultNumericTypes> {
+    Function::new(move |arg| Ok(Value::Float(f(arg.as_number()?))))
+}
+
+fn pow(arg: &Value) -> EvalexprResult<Value> {
+    let args = arg.as_fixed_len_tuple(2)?;
+    Ok(Value::Float(args[0].as_number()?.powf(args[1].as_number()?)))
+}
+
+fn clamp(arg: &Value) -> EvalexprResult<Value> {
+    let args = arg.as_fixed_len_tuple(3)?;
+    let (x, lo, hi) = (args[0].as_number()?, args[1].as_number()?, args[2].as_number()?);
+
+    // unlike f64::clamp this doesn't panic on an inverted range
+    Ok(Value::Float(x.max(lo).min(hi)))
+}
+
+fn context(inputs: &Inputs) -> EvalexprResult<HashMapContext> {
+    let mut context = HashMapContext::new();
+    for (name, value) in [("t", inputs.t), ("ambient", inputs.ambient), ("min", inputs.min), ("max", inputs.max)] {
+        context.set_value(name.to_string(), Value::Float(value))?;
+    }
+
+    context.set_function("pow".to_string(), Function::new(pow))?;
+    context.set_function("clamp".to_string(), Function::new(clamp))?;
+    for (name, f) in [
+        ("sqrt", f64::sqrt as fn(f64) -> f64),
+        ("exp", f64::exp),
+        ("ln", f64::ln),
+        ("log10", f64::log10),
+        ("abs", f64::abs),
+    ] {
+        context.set_function(name.to_string(), unary(f))?;
+    }
+
+    Ok(context)
+}
+
+impl BrightnessExpr {
+    /// Evaluates the expression, giving NaN if it fails, e.g. on a type
+    /// error, or doesn't give a number.
+    pub fn eval(&self, inputs: &Inputs) -> f64 {
+        context(inputs)
+            .and_then(|context| self.root.eval_number_with_context(&context))
+            .unwrap_or(f64::NAN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, t: f64) -> f64 {
+        let expr: BrightnessExpr = source.parse().unwrap();
+        expr.eval(&Inputs { t, ambient: t * 1000.0, min: 10.0, max: 110.0 })
+    }
+
+    #[test]
+    fn evaluates_curves_over_the_brightness_range() {
+        assert_eq!(eval("min + (max-min)*pow(t,2)", 0.5), 35.0);
+        assert_eq!(eval("clamp(ambient / 4, min, max)", 1.0), 110.0);
+        assert_eq!(eval("if(t < 0.5, min, max)", 0.25), 10.0);
+    }
+
+    #[test]
+    fn rejects_unknown_variables() {
+        assert!("min + x".parse::<BrightnessExpr>().is_err());
+        assert!("(min + 1".parse::<BrightnessExpr>().is_err());
+    }
+
+    #[test]
+    fn gives_nan_on_type_errors() {
+        assert!(eval("t < 0.5", 0.25).is_nan());
+    }
+}
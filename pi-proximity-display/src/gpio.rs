@@ -0,0 +1,62 @@
+//! Waits for edges on a GPIO line using the Linux GPIO character device, via
+//! `gpio-cdev`.
+
+use std::{io, os::fd::AsRawFd, path::Path, time::Duration};
+
+use color_eyre::{eyre::eyre, Result};
+use gpio_cdev::{Chip, EventRequestFlags, LineEventHandle, LineRequestFlags};
+
+/// A GPIO line configured to report falling edges, e.g. from the VCNL4010's
+/// active-low, open-drain INT pin.
+pub struct EdgeListener {
+    events: LineEventHandle,
+}
+
+impl EdgeListener {
+    pub fn open(chip: impl AsRef<Path>, line: u32) -> Result<EdgeListener> {
+        let chip = chip.as_ref();
+        let mut gpio = Chip::new(chip).map_err(|e| eyre!("could not open gpio chip {}: {e}", chip.display()))?;
+
+        let events = gpio
+            .get_line(line)
+            .and_then(|l| {
+                l.events(LineRequestFlags::INPUT, EventRequestFlags::FALLING_EDGE, env!("CARGO_PKG_NAME"))
+            })
+            .map_err(|e| eyre!("could not request gpio line {line} on {}: {e}", chip.display()))?;
+
+        Ok(EdgeListener { events })
+    }
+
+    /// Waits up to `timeout` for a falling edge, returning true if one
+    /// occurred. One queued event is consumed per call.
+    pub fn wait(&mut self, timeout: Duration) -> Result<bool> {
+        // gpio-cdev only offers blocking reads, so poll the event fd first to
+        // bound the wait
+        let mut fd = libc::pollfd {
+            fd: self.events.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+        // SAFETY: `fd` is a single valid pollfd
+        let ret = unsafe { libc::poll(&mut fd, 1, timeout_ms) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+
+            return Err(err.into());
+        }
+
+        if ret == 0 {
+            return Ok(false);
+        }
+
+        self.events.get_event()?;
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use tracing::{debug, warn};
+
+/// A user-provided shell command run in response to some event.
+///
+/// Commands are run via `sh -c` on a background thread so they never block the
+/// main loop. If a previous invocation of the same hook is still running when
+/// it fires again, the new invocation is skipped.
+#[derive(Debug)]
+pub struct Hook {
+    name: &'static str,
+    command: String,
+    running: Arc<AtomicBool>,
+}
+
+impl Hook {
+    pub fn new(name: &'static str, command: impl Into<String>) -> Hook {
+        Hook {
+            name,
+            command: command.into(),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Runs the hook, passing `args` as positional parameters (`$1`, `$2`,
+    /// ...) and `env` as additional environment variables.
+    pub fn fire(&self, args: &[String], env: &[(&str, String)]) {
+        if self.running.swap(true, Ordering::AcqRel) {
+            warn!("{} hook is still running, skipping", self.name);
+            return;
+        }
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&self.command).arg("sh").args(args);
+        for (key, value) in env {
+            command.env(key, value);
+        }
+
+        let name = self.name;
+        let running = Arc::clone(&self.running);
+        thread::spawn(move || {
+            match command.output() {
+                Ok(output) if output.status.success() => {
+                    debug!("{name} hook completed");
+                },
+                Ok(output) => warn!(
+                    "{name} hook exited with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(e) => warn!("could not run {name} hook: {e}"),
+            }
+
+            running.store(false, Ordering::Release);
+        });
+    }
+}
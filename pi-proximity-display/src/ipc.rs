@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::Result;
+use tracing::{debug, info, warn};
+
+use crate::reading::Reading;
+
+/// Serves readings as newline-delimited JSON to any clients connected to a
+/// Unix domain socket.
+///
+/// All socket I/O is non-blocking so clients can never stall the sensor loop:
+/// a client that can't accept a full line (i.e. its socket buffer is full) is
+/// disconnected.
+pub struct IpcServer {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<UnixStream>,
+}
+
+impl IpcServer {
+    pub fn bind(path: impl AsRef<Path>) -> Result<IpcServer> {
+        let path = path.as_ref().to_path_buf();
+
+        // remove any stale socket left behind by an unclean exit
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        info!("serving readings on {}", path.display());
+
+        Ok(IpcServer {
+            path,
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    fn accept_clients(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!("could not configure ipc client, dropping: {e}");
+                        continue;
+                    }
+
+                    debug!("ipc client connected");
+                    self.clients.push(stream);
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("error accepting ipc client: {e}");
+                    break;
+                },
+            }
+        }
+    }
+
+    /// Sends a reading to all connected clients, accepting any new clients
+    /// first.
+    pub fn publish(&mut self, reading: &Reading) {
+        self.accept_clients();
+        if self.clients.is_empty() {
+            return;
+        }
+
+        let mut line = match serde_json::to_vec(reading) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("could not serialize reading: {e}");
+                return;
+            },
+        };
+        line.push(b'\n');
+
+        self.clients.retain_mut(|client| match client.write(&line) {
+            Ok(n) if n == line.len() => true,
+            Ok(_) => {
+                debug!("ipc client too slow, disconnecting");
+                false
+            },
+            Err(e) => {
+                debug!("ipc client disconnected: {e}");
+                false
+            },
+        });
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("could not remove ipc socket {}: {e}", self.path.display());
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Mapping ambient light onto a discrete set of brightness levels.
+
+use std::str::FromStr;
+
+/// Fixed brightness levels selected by ambient light bands, e.g.
+/// `0:20,500:120,3000:255` uses brightness 20 below an ambient level of 500,
+/// 120 from 500 up to 3000, and 255 above that.
+#[derive(Debug, Clone)]
+pub struct AmbientLevels {
+    /// (ambient threshold, brightness) pairs, sorted by threshold.
+    bands: Vec<(u32, u32)>,
+}
+
+impl FromStr for AmbientLevels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bands: Vec<(u32, u32)> = Vec::new();
+        for band in s.split(',') {
+            let (ambient, brightness) = band
+                .trim()
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid ambient level (expected ambient:brightness): {band}"))?;
+
+            let ambient = u32::from_str(ambient)
+                .map_err(|_| format!("Invalid ambient threshold: {ambient}"))?;
+            let brightness = u32::from_str(brightness)
+                .map_err(|_| format!("Invalid brightness: {brightness}"))?;
+
+            if bands.last().is_some_and(|&(last, _)| ambient <= last) {
+                return Err(format!("Ambient levels must be sorted by increasing threshold: {s}"));
+            }
+
+            bands.push((ambient, brightness));
+        }
+
+        Ok(AmbientLevels { bands })
+    }
+}
+
+impl AmbientLevels {
+    /// Returns the highest brightness of any level.
+    pub fn max_brightness(&self) -> u32 {
+        self.bands.iter().map(|&(_, b)| b).max().unwrap_or_default()
+    }
+
+    /// Returns the highest ambient threshold of any level.
+    #[cfg(feature = "plot")]
+    pub fn max_threshold(&self) -> u32 {
+        self.bands.last().map(|&(t, _)| t).unwrap_or_default()
+    }
+
+    /// Returns the band for an ambient value without hysteresis.
+    fn band_for(&self, ambient: u32) -> usize {
+        self.bands.partition_point(|&(threshold, _)| threshold <= ambient).saturating_sub(1)
+    }
+
+    /// Returns the band to use for an ambient value given the current band, if
+    /// any. Moving to another band requires ambient light to pass that band's
+    /// boundary by more than `hysteresis` counts, so readings near a boundary
+    /// don't flap between levels.
+    pub fn select(&self, current: Option<usize>, ambient: u32, hysteresis: u32) -> usize {
+        let candidate = self.band_for(ambient);
+        let Some(current) = current.filter(|&c| c < self.bands.len()) else {
+            return candidate;
+        };
+
+        if candidate > current {
+            // moving up: must clear the boundary of the band being entered
+            let entered = (current + 1..=candidate)
+                .rev()
+                .find(|&i| ambient >= self.bands[i].0.saturating_add(hysteresis));
+            entered.unwrap_or(current)
+        } else if candidate < current {
+            // moving down: must fall far enough below the current band's floor
+            let left = (candidate..current)
+                .find(|&i| ambient.saturating_add(hysteresis) < self.bands[i + 1].0);
+            left.unwrap_or(current)
+        } else {
+            current
+        }
+    }
+
+    pub fn brightness(&self, band: usize) -> u32 {
+        self.bands[band].1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels() -> AmbientLevels {
+        AmbientLevels::from_str("0:20,500:120,3000:255").unwrap()
+    }
+
+    #[test]
+    fn selects_bands_without_a_current_band() {
+        let levels = levels();
+        assert_eq!(levels.select(None, 0, 50), 0);
+        assert_eq!(levels.select(None, 499, 50), 0);
+        assert_eq!(levels.select(None, 500, 50), 1);
+        assert_eq!(levels.select(None, 10_000, 50), 2);
+        assert_eq!(levels.brightness(1), 120);
+    }
+
+    #[test]
+    fn applies_hysteresis_between_bands() {
+        let levels = levels();
+
+        // moving up needs the boundary plus the hysteresis
+        assert_eq!(levels.select(Some(0), 520, 50), 0);
+        assert_eq!(levels.select(Some(0), 550, 50), 1);
+
+        // moving down needs the hysteresis below the boundary
+        assert_eq!(levels.select(Some(1), 470, 50), 1);
+        assert_eq!(levels.select(Some(1), 449, 50), 0);
+
+        // large jumps skip bands
+        assert_eq!(levels.select(Some(0), 5000, 50), 2);
+        assert_eq!(levels.select(Some(2), 100, 50), 0);
+    }
+
+    #[test]
+    fn rejects_unsorted_levels() {
+        assert!(AmbientLevels::from_str("500:120,0:20").is_err());
+        assert!(AmbientLevels::from_str("0:20,0:40").is_err());
+        assert!(AmbientLevels::from_str("0-20").is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use std::{mem::MaybeUninit, ptr};
+
+/// A broken-down wall-clock time in the system's local timezone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    /// Years since 1900.
+    pub year: i32,
+
+    /// Month of the year, 1-12.
+    pub month: u32,
+
+    /// Day of the month, 1-31.
+    pub day: u32,
+
+    /// Day of the year, 0-365.
+    pub yday: i32,
+
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl LocalTime {
+    /// Returns the current local time, as determined by libc's `localtime_r`
+    /// (and so the `TZ` environment variable or `/etc/localtime`).
+    pub fn now() -> LocalTime {
+        // SAFETY: `time` accepts a null pointer, and `localtime_r` fully
+        // initializes the provided `tm` when it returns non-null.
+        let tm = unsafe {
+            let t = libc::time(ptr::null_mut());
+            let mut tm = MaybeUninit::<libc::tm>::zeroed();
+            libc::localtime_r(&t, tm.as_mut_ptr());
+            tm.assume_init()
+        };
+
+        LocalTime {
+            year: tm.tm_year,
+            month: tm.tm_mon as u32 + 1,
+            day: tm.tm_mday as u32,
+            yday: tm.tm_yday,
+            hour: tm.tm_hour as u32,
+            minute: tm.tm_min as u32,
+            second: tm.tm_sec as u32,
+        }
+    }
+
+    /// Returns a key identifying the calendar day, for detecting midnight
+    /// rollover.
+    pub fn day_key(&self) -> (i32, i32) {
+        (self.year, self.yday)
+    }
+}
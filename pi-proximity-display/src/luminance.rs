@@ -0,0 +1,130 @@
+//! Brightness mapping calibrated to measured panel luminance.
+//!
+//! Backlight brightness values are rarely linear in emitted light, so mapping
+//! ambient light straight onto `--brightness-range` tends to bunch most of the
+//! visible change into a small part of the range. Given a table of brightness
+//! values and the luminance measured at each (e.g. with a colorimeter), ambient
+//! light can instead be mapped linearly onto luminance, and the table inverted
+//! to find the brightness value that produces it.
+//!
+//! The table file has one `brightness nits` pair per line, separated by
+//! whitespace or a comma. Blank lines and lines starting with `#` are ignored.
+//! Both columns must be strictly increasing. Between points, luminance is
+//! linearly interpolated.
+
+use std::{fs, ops::Range, path::Path};
+
+use color_eyre::{eyre::eyre, Result};
+
+#[derive(Debug, Clone)]
+pub struct LuminanceTable {
+    /// (brightness, nits) pairs, strictly increasing in both.
+    points: Vec<(u32, f32)>,
+}
+
+impl LuminanceTable {
+    pub fn load(path: impl AsRef<Path>) -> Result<LuminanceTable> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| eyre!("could not read luminance table {}: {e}", path.display()))?;
+
+        LuminanceTable::parse(&contents)
+            .map_err(|e| eyre!("invalid luminance table {}: {e}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<LuminanceTable, String> {
+        let mut points: Vec<(u32, f32)> = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|f| !f.is_empty());
+            let (Some(brightness), Some(nits), None) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(format!("line {}: expected 'brightness nits'", i + 1));
+            };
+
+            let brightness: u32 = brightness
+                .parse()
+                .map_err(|_| format!("line {}: invalid brightness: {brightness}", i + 1))?;
+            let nits: f32 = nits
+                .parse()
+                .map_err(|_| format!("line {}: invalid luminance: {nits}", i + 1))?;
+            if !nits.is_finite() || nits < 0.0 {
+                return Err(format!("line {}: luminance must be non-negative: {nits}", i + 1));
+            }
+
+            if let Some(&(last_brightness, last_nits)) = points.last() {
+                if brightness <= last_brightness || nits <= last_nits {
+                    return Err(format!(
+                        "line {}: brightness and luminance must both be strictly increasing",
+                        i + 1
+                    ));
+                }
+            }
+
+            points.push((brightness, nits));
+        }
+
+        if points.len() < 2 {
+            return Err("at least two points are required".to_string());
+        }
+
+        Ok(LuminanceTable { points })
+    }
+
+    /// Returns the interpolated luminance for a brightness value, clamped to
+    /// the table's range.
+    pub fn nits_for(&self, brightness: u32) -> f32 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if brightness <= first.0 {
+            return first.1;
+        }
+        if brightness >= last.0 {
+            return last.1;
+        }
+
+        let i = self.points.partition_point(|&(b, _)| b <= brightness);
+        let ((b0, n0), (b1, n1)) = (self.points[i - 1], self.points[i]);
+        n0 + (n1 - n0) * (brightness - b0) as f32 / (b1 - b0) as f32
+    }
+
+    /// Returns the brightness value producing the given luminance, clamped to
+    /// the table's range.
+    pub fn brightness_for(&self, nits: f32) -> u32 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if nits <= first.1 {
+            return first.0;
+        }
+        if nits >= last.1 {
+            return last.0;
+        }
+
+        let i = self.points.partition_point(|&(_, n)| n <= nits);
+        let ((b0, n0), (b1, n1)) = (self.points[i - 1], self.points[i]);
+        let brightness = b0 as f32 + (b1 - b0) as f32 * (nits - n0) / (n1 - n0);
+        brightness.round() as u32
+    }
+
+    /// Maps an ambient light value onto a brightness in `brightness_range`,
+    /// interpolating linearly in luminance rather than in brightness values.
+    pub fn map_ambient(
+        &self,
+        ambient: u32,
+        ambient_light_range: &Range<u32>,
+        brightness_range: &Range<u32>,
+    ) -> u32 {
+        let min_nits = self.nits_for(brightness_range.start);
+        let max_nits = self.nits_for(brightness_range.end);
+        let ambient_span = ambient_light_range.end.saturating_sub(ambient_light_range.start) as f32;
+        if ambient_span == 0.0 {
+            return brightness_range.start;
+        }
+
+        let fraction = ((ambient as f32 - ambient_light_range.start as f32) / ambient_span).clamp(0.0, 1.0);
+        let target = min_nits + (max_nits - min_nits) * fraction;
+
+        self.brightness_for(target).clamp(brightness_range.start, brightness_range.end)
+    }
+}
@@ -1,15 +1,97 @@
+use std::env;
+use std::ffi::OsString;
 use std::time::Instant;
-use std::{ops::Range, path::PathBuf, thread, time::Duration};
+use std::{ops::Range, path::{Path, PathBuf}, thread, time::Duration};
 use std::str::FromStr;
 
-use clap::Parser;
+use brightness::BrightnessLevel;
+use calibrate::{BaselineTracker, StoredBaseline};
+use clap::{Parser, Subcommand, ValueEnum};
+use clock::{Clock, SystemClock};
+use config::Config;
+use daemon::Daemon;
+use datalog::DataLogFormat;
 use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use display::{Display, DisplayPowerMode};
-use tracing::info;
-use vcnl4010::{ProximitySensor, SensorCommand};
+use display::{
+    AuxBacklight, AuxBacklightSpec, BrightnessBackend, Display, DisplayError, DisplayPowerMode, PowerBackend,
+};
+use daynight::{DayNight, TimeOfDay};
+use distance::CalibrationPoint;
+#[cfg(feature = "brightness-expr")]
+use expr::BrightnessExpr;
+use levels::AmbientLevels;
+use localtime::LocalTime;
+use luminance::LuminanceTable;
+#[cfg(feature = "plot")]
+use plot::{Marker, Plot, Series};
+#[cfg(feature = "plot")]
+use plotters::style::RGBColor;
+use tracing::{debug, info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use vcnl4010::{
+    Channel, I2CMux, InterruptControl, InterruptCountExceed, MuxKind, ProximitySensor, SensorCommand, SensorDevice,
+};
 
+mod autorange;
+mod bench;
+mod brightness;
+mod calibrate;
+mod clock;
+mod config;
+#[cfg(feature = "http")]
+mod control;
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod conversion;
 mod display;
+mod emulate;
+#[cfg(feature = "brightness-expr")]
+mod expr;
+mod datalog;
+mod daynight;
+mod distance;
+mod gpio;
+mod hooks;
+mod ipc;
+mod levels;
+mod localtime;
+mod luminance;
+mod overrides;
+mod poll;
+mod presence;
+#[cfg(feature = "plot")]
+mod plot;
+mod profile;
+mod reading;
+#[cfg(feature = "tui")]
+mod scope;
+mod signal;
+mod stats;
+mod status_led;
+mod sway;
+mod wake;
+
+fn parse_interrupt_count(s: &str) -> Result<InterruptCountExceed, String> {
+    u8::from_str(s)
+        .ok()
+        .and_then(InterruptCountExceed::from_count)
+        .ok_or_else(|| format!("Invalid count (expected 1, 2, 4, 8, 16, 32, 64, or 128): {s}"))
+}
+
+/// Parses a 7-bit I2C address, in hex with a `0x` prefix or decimal.
+fn parse_i2c_addr(s: &str) -> Result<u16, String> {
+    let addr = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => u16::from_str(s),
+    };
+
+    match addr {
+        Ok(addr) if addr <= 0x7f => Ok(addr),
+        _ => Err(format!("Invalid I2C address (expected 0x00 to 0x7f): {s}")),
+    }
+}
 
 fn parse_range(s: &str) -> Result<Range<u32>, String> {
     let parts: Vec<&str> = s.split("..").collect();
@@ -26,32 +108,320 @@ fn parse_range(s: &str) -> Result<Range<u32>, String> {
 }
 
 #[derive(Parser)]
-#[command(version, about)]
+#[command(version, about, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// A JSON config file providing default values for any of these options,
+    /// keyed by their long names (e.g. `"proximity-range": "2930..2975"`). It
+    /// may also contain a `displays` object with per-display overrides of
+    /// brightness settings. Options given on the command line take precedence.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Print the effective settings (including `--config` and defaults) in the
+    /// config file format and exit. The output can be loaded with `--config`.
+    #[arg(long)]
+    print_config: bool,
+
+    /// A built-in profile providing defaults for the proximity range, hold,
+    /// LED current, and polling rate for a typical installation. Options
+    /// given explicitly or in `--config` take precedence. Use `list` to
+    /// describe the available profiles.
+    #[arg(long, value_parser = profile::parse_profile_name)]
+    profile: Option<String>,
+
     /// An alternate I2C device to use
     #[arg(short = 'i', long, default_value = "/dev/i2c-1")]
     i2c_device: PathBuf,
 
+    /// Number of times the kernel should retry a failed I2C transfer, via the
+    /// `I2C_RETRIES` ioctl. This is separate from application-level retries.
+    /// The effect depends on the adapter driver: bit-banged adapters
+    /// (`i2c-gpio`) retry unacknowledged addresses, while most hardware
+    /// controllers are only retried after losing bus arbitration. Startup
+    /// fails if the ioctl is rejected.
+    #[arg(long)]
+    i2c_bus_retries: Option<u32>,
+
+    /// The kernel's I2C adapter transfer timeout, via the `I2C_TIMEOUT` ioctl
+    /// (rounded up to 10ms units). Only adapter drivers that use the
+    /// adapter's timeout are affected; some use a fixed timeout instead.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    i2c_bus_timeout: Option<Duration>,
+
+    /// The address of an I2C multiplexer the sensor is behind, e.g. 0x70,
+    /// letting several sensors (all at 0x13) share one bus. The mux channel
+    /// is selected before the sensor is accessed: once at startup when the
+    /// device is locked, or before every register access with
+    /// `--share-i2c-device`.
+    #[arg(long, value_parser = parse_i2c_addr, requires = "i2c_mux_channel")]
+    i2c_mux_addr: Option<u16>,
+
+    /// The mux channel the sensor is on, 0 to 7.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=7), requires = "i2c_mux_addr")]
+    i2c_mux_channel: Option<u8>,
+
+    /// The type of `--i2c-mux-addr`, which determines how channels are
+    /// selected.
+    #[arg(long, value_enum, default_value_t = I2CMuxType::Tca9548a)]
+    i2c_mux_type: I2CMuxType,
+
+    /// Assemble 16-bit proximity and ambient results low byte first, contrary
+    /// to the datasheet. Only needed for clone modules whose readings look
+    /// wildly wrong; startup warns if readings appear byte-swapped.
+    #[arg(long)]
+    swap_result_bytes: bool,
+
+    /// Access 16-bit registers one byte at a time, even if the I2C adapter
+    /// supports SMBus word transfers.
+    #[arg(long)]
+    no_word_access: bool,
+
+    /// The fuse program ID (the read-only top two bits of the LED current
+    /// register) expected of a genuine sensor, 0 to 3. The datasheet doesn't
+    /// list values, so take it from a known-genuine part; startup then warns
+    /// if the sensor reports a different one, which may indicate a clone.
+    /// The ID is always logged at startup.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=3))]
+    expected_fuse_prog_id: Option<u8>,
+
+    /// Don't take an exclusive lock on `--i2c-device`. By default, startup
+    /// fails if another instance is already using the device, since
+    /// concurrent access corrupts readings.
+    #[arg(long)]
+    share_i2c_device: bool,
+
+    /// Treat `--i2c-device` as a bus emulated by the Linux `i2c-stub` module
+    /// (e.g. `modprobe i2c-stub chip_addr=0x13`) and seed its registers so it
+    /// passes as a VCNL4010, for testing without hardware. Measurement
+    /// results stay fixed until changed with e.g. `i2cset`; see
+    /// `scripts/i2c-stub.sh`. Don't use this with a real sensor.
+    #[arg(long)]
+    emulated: bool,
+
     /// A current value for the proximity sensor LED in mA between 0 and 200.
-    #[arg(short = 'c', long, default_value = "200")]
+    /// The sensor sets the current in 10mA steps, so other values are
+    /// rounded down. 0 turns the LED off and disables proximity measurements
+    /// (see `--ambient-only`).
+    #[arg(short = 'c', long, default_value = "200", value_parser = clap::value_parser!(u16).range(0..=200))]
     proximity_led_current: u16,
 
+    /// Use only the ambient light sensor, for brightness control: the IR LED
+    /// is set to 0mA and proximity measurements are disabled, so the display
+    /// is never powered on or off. `--proximity-range` isn't needed.
+    #[arg(long, conflicts_with_all = [
+        "check_baseline", "auto_led", "baseline_file", "proximity_auto_zero", "interrupt_gpio", "proximity_invert",
+    ])]
+    ambient_only: bool,
+
+    /// Amount of time over which to ramp the LED current up from 0 to
+    /// `--proximity-led-current` at startup. Useful on marginal power supplies
+    /// that may brown out when the LED immediately draws its full current.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    proximity_led_ramp: Duration,
+
+    /// Measure the proximity baseline at startup and warn if it is near full
+    /// scale, e.g. due to IR reflecting off the enclosure. Nothing should be in
+    /// front of the sensor while this runs.
+    #[arg(long)]
+    check_baseline: bool,
+
+    /// Like `--check-baseline`, but also reduce the LED current until the
+    /// baseline is acceptable.
+    #[arg(long)]
+    auto_led: bool,
+
+    /// A file in which to persist the proximity baseline (and LED current
+    /// chosen by `--auto-led`) across restarts. If the file is missing or
+    /// invalid, the baseline is measured at startup. While idle, the baseline
+    /// is slowly refined to track drift and periodically saved back.
+    #[arg(long)]
+    baseline_file: Option<PathBuf>,
+
+    /// Interpret `--proximity-range` relative to the measured or stored
+    /// proximity baseline rather than as raw counts.
+    #[arg(long)]
+    proximity_auto_zero: bool,
+
+    /// How long to sample the proximity baseline for.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "2s")]
+    baseline_duration: Duration,
+
+    /// Fraction of full scale above which the proximity baseline is considered
+    /// too high.
+    #[arg(long, default_value = "0.9")]
+    baseline_max_fraction: f32,
+
+    /// The directory searched for backlights when discovering displays, for
+    /// sysfs mounted elsewhere (e.g. in a container) or a fake tree for
+    /// testing.
+    #[arg(long, default_value = display::BACKLIGHT_ROOT)]
+    backlight_root: PathBuf,
+
     /// An explicit display to manage, otherwise the first display with a
-    /// controllable backlight is used.
+    /// controllable backlight is used. If no sysfs backlights exist, the
+    /// named display's power is still controlled, but not its brightness.
     #[arg(long)]
     display_name: Option<String>,
 
+    /// Explicit sysfs backlight node(s) to manage, e.g.
+    /// `/sys/class/backlight/10-0045`. May be given multiple times for panels
+    /// with several backlight controllers; all nodes are written in tandem and
+    /// the first is used for the display name and reported brightness. Display
+    /// discovery and `--display-name` are ignored when set.
+    #[arg(long)]
+    backlight_path: Vec<PathBuf>,
+
+    /// An additional brightness node, such as a keyboard backlight under
+    /// `/sys/class/leds`, that turns off with the display and otherwise maps
+    /// `--ambient-light-range` onto its own range (or uses its maximum if
+    /// unset). Given as `path:min..max[:deadband]`, where the optional
+    /// deadband (default 1) is the change needed before the node is
+    /// rewritten. May be given multiple times.
+    #[arg(long)]
+    aux_backlight: Vec<AuxBacklightSpec>,
+
+    /// The mechanism used to turn the display on and off. `swayipc` requires
+    /// `SWAYSOCK` to be set; if it isn't, `wlopm` is used instead.
+    #[arg(long, value_enum, default_value_t = PowerBackend::Wlopm)]
+    power_backend: PowerBackend,
+
+    /// Path to the `wlopm` executable. If unset, `wlopm` is looked up on
+    /// `PATH`, which may be minimal under systemd.
+    #[arg(long)]
+    wlopm_path: Option<PathBuf>,
+
+    /// An extra argument to pass to every `wlopm` invocation. May be given
+    /// multiple times.
+    #[arg(long, allow_hyphen_values = true)]
+    wlopm_extra_arg: Vec<String>,
+
+    /// The minimum time between `wlopm` invocations, as a safety net against
+    /// rapid on/off changes, which some compositors handle poorly. Changes in
+    /// between are coalesced, and the latest is sent once the interval
+    /// elapses.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "500ms")]
+    wlopm_min_interval: Duration,
+
+    /// Query the display's power state after each power command, through
+    /// `wlopm` or sway IPC per `--power-backend`, and resend the command if it
+    /// didn't take. Each check waits briefly before querying, so this delays
+    /// the sensor loop slightly on every power change.
+    #[arg(long)]
+    verify_power: bool,
+
+    /// How many times `--verify-power` resends a power command that didn't
+    /// take before giving up with a warning.
+    #[arg(long, default_value = "2")]
+    verify_power_retries: u32,
+
     /// A range of proximity values in the format `min..max` such that 'min' is
     /// the proximity value below which the display should turn off, and 'max'
     /// is the value above which the display should turn on. The range in
-    /// between is used for hysteresis.
-    #[arg(long, value_parser = parse_range)]
-    proximity_range: Range<u32>,
+    /// between is used for hysteresis, and must not be empty; a margin of at
+    /// least a few tens of counts above the sensor's noise is recommended to
+    /// avoid flapping.
+    #[arg(long, value_parser = parse_range, required_unless_present_any = ["profile", "ambient_only"])]
+    proximity_range: Option<Range<u32>>,
+
+    /// Invert detection for beam-break setups, where the sensor faces a
+    /// reflector and presence is an object blocking it: detection then means
+    /// proximity at or below the start of `--proximity-range`, and clearing
+    /// means at or above its end. `--proximity-approach` and
+    /// `--rearm-threshold` are inverted too, triggering at or below their
+    /// values. Idle polling never backs off in this mode, as the idle reading
+    /// is high.
+    #[arg(long, conflicts_with_all = ["proximity_auto_zero", "proximity_boost_range"])]
+    proximity_invert: bool,
 
     /// Amount of time to keep the display on once detected and then cleared.
     #[arg(long, value_parser = humantime::parse_duration, default_value = "20s")]
     proximity_hold: Duration,
 
+    /// Restart the hold timer whenever proximity is back above the start of
+    /// `--proximity-range` during the hold, so lingering just out of
+    /// detection range keeps the display on.
+    #[arg(long)]
+    hold_extend: bool,
+
+    /// A lower proximity threshold at which to power the display on early, as
+    /// someone approaches, to hide the sensor and compositor wake latency. It
+    /// doesn't count as detection: if proximity falls back below it without
+    /// reaching the end of `--proximity-range`, the hold starts as usual.
+    /// Must be less than the end of `--proximity-range` (or, with
+    /// `--proximity-invert`, greater than its start).
+    #[arg(long)]
+    proximity_approach: Option<u32>,
+
+    /// After the display turns off, ignore detection for this long unless
+    /// proximity reaches `--rearm-threshold`, so an object lingering near the
+    /// threshold can't immediately wake it again. Disabled by default.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    rearm_cooldown: Option<Duration>,
+
+    /// A stronger proximity threshold that still wakes the display during
+    /// `--rearm-cooldown`. If unset, detection is ignored entirely during the
+    /// cooldown.
+    #[arg(long)]
+    rearm_threshold: Option<u32>,
+
+    /// A failsafe that forces the display off once proximity has been detected
+    /// continuously for this long, e.g. if a reflective object pins the
+    /// sensor. The display then stays off until proximity clears and is
+    /// detected again. Disabled by default.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    max_on_time: Option<Duration>,
+
+    /// A shell command to run whenever presence is detected. The proximity
+    /// value is passed as `$1` and in the `PROXIMITY` environment variable.
+    /// Commands run in the background and are skipped if still running from
+    /// a previous transition.
+    #[arg(long)]
+    on_detect_cmd: Option<String>,
+
+    /// A shell command to run whenever presence is cleared, after the hold
+    /// time has elapsed. Receives the same arguments as `--on-detect-cmd`.
+    #[arg(long)]
+    on_clear_cmd: Option<String>,
+
+    /// A shell command to run whenever the display actually powers on, e.g. to
+    /// switch a relay for a speaker amp. The display name is passed as `$1`
+    /// and in the `DISPLAY_NAME` environment variable. Unlike
+    /// `--on-detect-cmd`, this follows power changes from any source,
+    /// including manual overrides, and runs once per change.
+    #[arg(long)]
+    power_on_cmd: Option<String>,
+
+    /// A shell command to run whenever the display actually powers off.
+    /// Receives the same arguments as `--power-on-cmd`.
+    #[arg(long)]
+    power_off_cmd: Option<String>,
+
+    /// A calibration point for approximate distance estimates in the form
+    /// `distance_cm:count`, giving the proximity count measured with a target
+    /// at a known distance. At least two points are required to enable
+    /// estimates, which are informational only and appear in logs. The sensor
+    /// isn't a rangefinder, so estimates are only rough approximations for
+    /// targets similar to the one used to calibrate.
+    #[arg(long)]
+    distance_calibration: Vec<CalibrationPoint>,
+
+    /// Runs the state machine, logging and reporting presence and running
+    /// `--on-detect`/`--on-clear` as usual, but never turns the display on or
+    /// off (including through the control API). Brightness control remains
+    /// active, and since the display is never considered off, brightness
+    /// keeps following ambient light throughout.
+    #[arg(long)]
+    presence_log_only: bool,
+
+    /// If set, the hold time is included in the logged presence duration for
+    /// each session. By default a session is considered to end when proximity
+    /// first clears.
+    #[arg(long)]
+    presence_include_hold: bool,
+
     /// A range of ambient light levels in the form 'min..max', inclusive.
     /// Values in this range will be linearly mapped to the values in
     /// `--brightness-range` to calculate the desired brightness. If unset,
@@ -65,164 +435,2320 @@ struct Args {
     /// levels. If unset, brightness control is disabled.
     #[arg(long, value_parser = parse_range)]
     brightness_range: Option<Range<u32>>,
+
+    /// Fixed brightness levels by ambient light band, as comma-separated
+    /// `ambient:brightness` pairs sorted by ambient threshold, e.g.
+    /// `0:20,500:120,3000:255`. Each level applies from its threshold up to the
+    /// next; readings below the first threshold use the first level. When set,
+    /// this replaces `--ambient-light-range` and `--brightness-range`.
+    #[arg(long)]
+    ambient_levels: Option<AmbientLevels>,
+
+    /// How far (in ambient counts) readings must pass a band boundary before
+    /// `--ambient-levels` switches level.
+    #[arg(long, default_value = "25")]
+    ambient_level_hysteresis: u32,
+
+    /// A brightness range to use at night in place of `--brightness-range`.
+    /// The effective range blends linearly between the two across
+    /// `--twilight` windows centered on `--sunrise` and `--sunset`, and
+    /// ambient light is then mapped onto it as usual.
+    #[arg(long, value_parser = parse_range)]
+    night_brightness_range: Option<Range<u32>>,
+
+    /// Local time (HH:MM) at which `--brightness-range` takes over from
+    /// `--night-brightness-range`. Times use the system timezone (`TZ` or
+    /// `/etc/localtime`) and follow DST changes.
+    #[arg(long, default_value = "07:00")]
+    sunrise: TimeOfDay,
+
+    /// Local time (HH:MM) at which `--night-brightness-range` takes over.
+    #[arg(long, default_value = "19:00")]
+    sunset: TimeOfDay,
+
+    /// Length of the blend between day and night brightness ranges, centered
+    /// on `--sunrise` and `--sunset`.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1h")]
+    twilight: Duration,
+
+    /// A table of measured panel luminance, with one `brightness nits` pair
+    /// per line. When given, ambient light is mapped linearly onto the
+    /// luminance range spanned by `--brightness-range` and the table is
+    /// inverted to find the brightness to write, rather than mapping ambient
+    /// light directly onto brightness values.
+    #[arg(long)]
+    luminance_table: Option<PathBuf>,
+
+    /// A custom ambient light to brightness transfer function, used in place
+    /// of the linear mapping of `--ambient-light-range` onto
+    /// `--brightness-range`, e.g. "min + (max-min)*pow(t,1.8)". `t` is ambient
+    /// light normalized to 0..1 over the ambient range, `ambient` the raw
+    /// value, and `min` and `max` the current brightness range. Operators,
+    /// comparisons, and functions including `pow`, `clamp`, and `if` are
+    /// supported. The expression is checked at startup, and rejected if it
+    /// gives non-finite values or values outside the brightness range.
+    /// Requires the `brightness-expr` feature.
+    #[cfg(feature = "brightness-expr")]
+    #[arg(long, conflicts_with = "luminance_table")]
+    brightness_expr: Option<BrightnessExpr>,
+
+    /// The mechanism used to set brightness. `command` runs `--brightness-cmd`
+    /// as a subprocess for every change, so the command should be fast.
+    #[arg(long, value_enum, default_value_t = BrightnessBackend::Sysfs)]
+    brightness_backend: BrightnessBackend,
+
+    /// The shell command used by `--brightness-backend command`, where
+    /// `{value}` is replaced by the brightness to set, e.g.
+    /// `mytool --set {value}`. `--brightness-range` and the display's
+    /// `max_brightness` still bound the value; without a backlight the
+    /// upper bound is the end of `--brightness-range` (or the highest
+    /// `--ambient-levels` level, else 255).
+    #[arg(long, required_if_eq("brightness_backend", "command"))]
+    brightness_cmd: Option<String>,
+
+    /// The minimum time between brightness writes. Changes in between are
+    /// coalesced, and the most recent target is written once the interval
+    /// elapses. Useful for PWM panels that flicker when written too often.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    brightness_write_interval: Duration,
+
+    /// A fixed brightness (absolute, or a percentage such as `60%`) to use
+    /// whenever the display is on, instead of mapping ambient light. Takes
+    /// precedence over `--ambient-light-range` and `--ambient-levels`.
+    #[arg(long)]
+    detected_brightness: Option<BrightnessLevel>,
+
+    /// A fixed brightness (absolute, or a percentage such as `5%`) to use at
+    /// night, i.e. from `--sunset` until `--sunrise`, e.g. for a dim bedside
+    /// clock. The display is kept on all night, even once presence clears,
+    /// and ambient light is ignored; `--proximity-boost-range` still
+    /// brightens it. Takes precedence over `--detected-brightness`.
+    #[arg(long)]
+    night_fixed_brightness: Option<BrightnessLevel>,
+
+    /// A brightness to dim to during the hold window after proximity clears,
+    /// when using `--detected-brightness`.
+    #[arg(long, requires = "detected_brightness")]
+    hold_brightness: Option<BrightnessLevel>,
+
+    /// A range of proximity values in the form 'min..max' over which to boost
+    /// the ambient-derived brightness as a person gets closer: no boost at or
+    /// below `min`, rising linearly to `--proximity-boost` at `max`. The
+    /// result is still bounded by `--brightness-range` (or the display's max
+    /// brightness with `--ambient-levels`).
+    #[arg(long, value_parser = parse_range)]
+    proximity_boost_range: Option<Range<u32>>,
+
+    /// The largest brightness boost from `--proximity-boost-range`, absolute
+    /// or as a percentage of the display's max brightness.
+    #[arg(long, default_value = "25%")]
+    proximity_boost: BrightnessLevel,
+
+    /// A gamma applied to brightness values as they're written, scaled to the
+    /// display's max brightness, for panels whose response is nonlinear
+    /// (e.g. `2.2`). Brightness is still configured and reported in
+    /// uncorrected values. 1.0 disables correction.
+    #[arg(long, value_parser = parse_gamma, default_value = "1.0")]
+    panel_gamma: f32,
+
+    /// A hard ceiling on the written brightness, either as an absolute value
+    /// or as a percentage of the display's max brightness (e.g. `80%`). This
+    /// is applied after all other brightness calculations, independent of
+    /// `--brightness-range`.
+    #[arg(long)]
+    brightness_cap: Option<BrightnessLevel>,
+
+    /// A path which, while it exists, disables all display power and brightness
+    /// changes. The sensor is still read and logged, and control resumes once
+    /// the file is removed. The state machine keeps running while disabled,
+    /// so on resume the display is set to the power of whatever state it
+    /// ended up in, e.g. off if presence cleared in the meantime.
+    #[arg(long)]
+    disable_file: Option<PathBuf>,
+
+    /// After the display powers on, hold the previous brightness for this long
+    /// before resuming ambient tracking, since the first ambient readings after
+    /// a wake may be unrepresentative. Has no effect if no brightness has been
+    /// set yet, or with `--brightness-while-off zero`.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    ambient_settle: Duration,
+
+    /// Before powering the display off, fade brightness down to 0 over this
+    /// long. The previous brightness is restored when it powers back on. The
+    /// loop is paused during the fade. Ignored without brightness control.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    fade_out: Duration,
+
+    /// After powering the display on, fade brightness up from 0 over this
+    /// long. Ignored without brightness control.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    fade_in: Duration,
+
+    /// What to do with brightness while the display is off: `skip` leaves it
+    /// untouched and applies the latest value once the display powers on,
+    /// `keep` keeps writing the mapped value, and `zero` sets it to 0. Writing
+    /// brightness while off can briefly light some panels.
+    #[arg(long, value_enum, default_value_t = BrightnessWhileOff::Skip)]
+    brightness_while_off: BrightnessWhileOff,
+
+    /// How often to re-read the display's `max_brightness`, which may change
+    /// after mode switches or hotplug. It is also re-read whenever a
+    /// brightness write is rejected as out of range.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "60s")]
+    display_refresh_interval: Duration,
+
+    /// Also write logs to this file, rotated per `--log-rotation`. The current
+    /// period (in UTC) is appended to the file name, e.g.
+    /// `daemon.log.2025-01-31`.
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// How often to start a new `--log-file`.
+    #[arg(long, value_enum, default_value_t = LogRotation::Daily)]
+    log_rotation: LogRotation,
+
+    /// Disables logging to stderr. Only useful with `--log-file`.
+    #[arg(long, requires = "log_file")]
+    no_stderr_log: bool,
+
+    /// Log output format. `compact` prints a terse, fixed-width status line
+    /// (e.g. `P=  123 A=  456 B=  80 S=ON`) at the heartbeat interval and
+    /// otherwise only warnings and errors, for slow serial consoles. Set
+    /// `RUST_LOG` (e.g. `RUST_LOG=debug`) to see the full output again.
+    #[arg(long, value_enum, default_value_t = LogFormat::Full)]
+    log_format: LogFormat,
+
+    /// Serve live readings as newline-delimited JSON to any clients connected
+    /// to a Unix socket at this path. The socket is removed on shutdown.
+    #[arg(long)]
+    ipc_socket: Option<PathBuf>,
+
+    /// Only publish a reading on `--ipc-socket` once proximity has changed by
+    /// at least this much since the last one published. State and brightness
+    /// changes are always published. 0 publishes every reading.
+    #[arg(long, default_value = "0")]
+    ipc_proximity_delta: u32,
+
+    /// Like `--ipc-proximity-delta`, for ambient light.
+    #[arg(long, default_value = "0")]
+    ipc_ambient_delta: u32,
+
+    /// With `--ipc-proximity-delta` or `--ipc-ambient-delta`, still publish a
+    /// reading at least this often, even if nothing changed.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    ipc_heartbeat: Duration,
+
+    /// Rounds the proximity and ambient values in the periodic data log to the
+    /// nearest multiple of this value, to make trends easier to skim. Only
+    /// affects logging.
+    #[arg(long, default_value = "1")]
+    log_granularity: u32,
+
+    /// Channels to include in the periodic data log, comma separated (e.g.
+    /// `ambient`). Defaults to the measured channels: ambient light, plus
+    /// proximity unless it's disabled.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    summary_fields: Vec<SummaryField>,
+
+    /// Instead of polling, wait for the sensor's INT pin on this GPIO line
+    /// (offset on `--gpio-chip`) and only read the sensor when it fires. INT
+    /// is open-drain and active low, so it needs a pull-up (the Adafruit
+    /// breakout includes one). The sensor's thresholds are programmed from
+    /// `--proximity-range` so interrupts fire on detection and clearing. Falls
+    /// back to polling if the line can't be opened.
+    #[arg(long)]
+    interrupt_gpio: Option<u32>,
+
+    /// With `--interrupt-gpio`, the number of consecutive measurements outside
+    /// the thresholds the sensor requires before raising an interrupt, which
+    /// debounces in hardware. Must be a power of two from 1 to 128.
+    #[arg(long, value_parser = parse_interrupt_count, default_value = "1")]
+    interrupt_count: InterruptCountExceed,
+
+    /// The GPIO chip for `--interrupt-gpio`.
+    #[arg(long, default_value = "/dev/gpiochip0")]
+    gpio_chip: PathBuf,
+
+    /// In interrupt mode, the longest to wait for an interrupt before reading
+    /// the sensor anyway, which is needed for the hold timer to expire.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1s")]
+    interrupt_timeout: Duration,
+
+    /// A sysfs LED class device (e.g. `/sys/class/leds/led0`) used to show the
+    /// current state: off when cleared, on when detected, and blinking while
+    /// the hold timer runs. The LED's trigger is changed, so any existing
+    /// trigger (e.g. disk activity) is replaced.
+    #[arg(long)]
+    status_led: Option<PathBuf>,
+
+    /// Serve an HTTP API for overriding display power and brightness on this
+    /// address (`host:port`, or just a port to bind on localhost), e.g.
+    /// `curl -d on localhost:8321/power`. `POST /power` accepts `on`, `off`, or
+    /// `auto`, and `POST /brightness` a value or `auto`. Overrides take
+    /// precedence over the proximity and ambient logic, but not over
+    /// `--disable-file`. There is no authentication. Requires the `http`
+    /// feature.
+    #[cfg(feature = "http")]
+    #[arg(long, value_parser = control::parse_control_addr)]
+    control_addr: Option<std::net::SocketAddr>,
+
+    /// Serve sensor and display state on this D-Bus bus, under the name
+    /// `io.github.timothyb89.ProximityDisplay` at the object path
+    /// `/io/github/timothyb89/ProximityDisplay`. Properties cover the state,
+    /// readings, brightness, and power, and `SetPower`/`SetBrightness` set
+    /// the same overrides as `--control-addr`. If the bus can't be reached,
+    /// a warning is logged and the daemon runs without it. Requires the
+    /// `dbus` feature.
+    #[cfg(feature = "dbus")]
+    #[arg(long, value_enum)]
+    dbus: Option<dbus::Bus>,
+
+    /// Append a row (timestamp, proximity, ambient, lux, state, brightness)
+    /// to this file every `--data-log-interval`, for long-term analysis. Rows
+    /// are buffered and flushed about once a minute.
+    #[arg(long)]
+    data_log: Option<PathBuf>,
+
+    /// The format of `--data-log`: `csv`, or `sqlite` for a `readings` table
+    /// in an SQLite database, which requires the `sqlite` feature.
+    #[arg(long, value_enum, default_value_t = DataLogFormat::Csv)]
+    data_log_format: DataLogFormat,
+
+    /// How often to write a row to `--data-log`.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    data_log_interval: Duration,
+
+    /// Size (e.g. `10M`) at which `--data-log` is rotated to a `.1` file,
+    /// replacing any previous one.
+    #[arg(long, value_parser = datalog::parse_size, default_value = "10M")]
+    data_log_max_size: u64,
+
+    /// A file to write the run summary logged at shutdown to, as JSON.
+    #[arg(long)]
+    stats_file: Option<PathBuf>,
+
+    /// How often to log the cumulative health counters (reads, read errors,
+    /// power transitions, brightness writes, and so on). They are always
+    /// logged at shutdown, and available from the control API and in ipc
+    /// readings. 0 disables periodic logging.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    counters_log_interval: Duration,
+
+    /// Log a trace of every decision made each poll: raw and adjusted
+    /// proximity, which threshold applied, the resulting state, and how the
+    /// brightness was mapped, capped, or paused. Very verbose; intended for
+    /// debugging unexpected behavior.
+    #[arg(long)]
+    explain: bool,
+
+    /// Align polls to wall-clock multiples of the poll interval (e.g. every
+    /// second on the second) rather than sleeping a fixed interval after each
+    /// poll, which drifts. A poll that overruns its slot skips to the next
+    /// boundary. Useful for correlating logs across devices.
+    #[arg(long)]
+    align_poll: bool,
+
+    /// Interval between sensor polls.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "250ms")]
+    poll_interval: Duration,
+
+    /// If set, polling backs off to progressively slower intervals once the
+    /// display has been cleared for this long. Any proximity reading at or
+    /// above `--idle-wake-threshold` immediately resumes the normal interval.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    idle_poll_after: Option<Duration>,
+
+    /// The slowest poll interval used while idle.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "2s")]
+    idle_poll_max: Duration,
+
+    /// Factor by which the poll interval grows on each idle poll until it
+    /// reaches `--idle-poll-max`.
+    #[arg(long, default_value = "2.0")]
+    idle_poll_backoff: f32,
+
+    /// Proximity value at or above which idle polling snaps back to the normal
+    /// interval. Defaults to the low end of `--proximity-range`.
+    #[arg(long)]
+    idle_wake_threshold: Option<u32>,
+
+    /// Number of back-to-back proximity and ambient reads to take each poll,
+    /// which are combined per `--sample-reduction` into a single value. Each
+    /// extra sample adds four byte reads to the I2C bus per poll.
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    samples_per_read: u32,
+
+    /// Treat proximity readings of exactly 0xFFFF as invalid and drop them,
+    /// rather than as a strong detection. Some sensor faults return this value.
+    #[arg(long)]
+    reject_saturated_proximity: bool,
+
+    /// Treat proximity readings of exactly zero as invalid and drop them.
+    #[arg(long)]
+    reject_zero_proximity: bool,
+
+    /// How to treat ambient light readings of exactly zero. These usually
+    /// mean the ambient channel isn't actually measuring, rather than true
+    /// darkness: `warn` logs when they start, `hold` also keeps using the
+    /// last nonzero reading so brightness doesn't drop to minimum, and
+    /// `accept` uses them as-is.
+    #[arg(long, value_enum, default_value_t = ZeroAmbient::Warn)]
+    zero_ambient: ZeroAmbient,
+
+    /// When ambient light readings saturate at full scale, step the ambient
+    /// light averaging down (halving it each time) to try to restore
+    /// headroom, and back up toward its starting value once readings have
+    /// stayed below half of full scale for a minute. Each change is logged,
+    /// and brightness mapping starts over from the next reading.
+    #[arg(long)]
+    ambient_autorange: bool,
+
+    /// Minimum time between ambient light reads. Between reads, the last
+    /// ambient value is reused, so the proximity loop isn't slowed by ambient
+    /// reads. 0 reads ambient light every poll.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    ambient_interval: Duration,
+
+    /// Also treat a rise in ambient light of at least this much within
+    /// `--ambient-wake-window` as a detection, e.g. room lights turning on
+    /// where the sensor can't see the doorway. The display then clears after
+    /// `--proximity-hold` as usual unless proximity is also detected.
+    #[arg(long)]
+    ambient_wake_delta: Option<u32>,
+
+    /// The window over which `--ambient-wake-delta` is measured.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "2s")]
+    ambient_wake_window: Duration,
+
+    /// How often to re-read the sensor's command register to detect silent
+    /// resets (e.g. from a brownout) or external interference. If the
+    /// measurement enable bits no longer match what was set, the register is
+    /// logged and the sensor reinitialized. 0 disables the check.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    command_check_interval: Duration,
+
+    /// Number of consecutive invalid readings (see
+    /// `--reject-saturated-proximity`) after which the sensor is reinitialized.
+    #[arg(long, default_value = "20", value_parser = clap::value_parser!(u32).range(1..))]
+    invalid_reading_limit: u32,
+
+    /// Number of consecutive failed sensor reads after which the I2C device is
+    /// reopened and the sensor reinitialized, e.g. after its cable is
+    /// reconnected.
+    #[arg(long, default_value = "3", value_parser = clap::value_parser!(u32).range(1..))]
+    sensor_error_limit: u32,
+
+    /// Number of times to try reopening the sensor (with exponential backoff
+    /// from 1s to 60s) before giving up and exiting.
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u32).range(1..))]
+    sensor_reopen_attempts: u32,
+
+    /// How multiple samples per read are combined.
+    #[arg(long, value_enum, default_value_t = SampleReduction::Mean)]
+    sample_reduction: SampleReduction,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-enum State {
-    Detected,
-    Cleared,
-    ClearedTransitioning(Instant),
+#[derive(Subcommand)]
+enum Command {
+    /// Measures the achievable sensor read rate on the current I2C bus by
+    /// issuing back-to-back proximity and ambient reads, and, if self-timed
+    /// measurements are running, the sensor's conversion periods. Neither the
+    /// display nor the sensor configuration is changed.
+    Bench {
+        /// How long to run the benchmark.
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
+        duration: Duration,
+
+        /// Print results as JSON rather than a table.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Renders the configured ambient light to brightness mapping and the
+    /// proximity thresholds to an SVG file, using the same mapping code as
+    /// the daemon. No hardware is needed. `--ambient-levels` are drawn
+    /// without hysteresis. Requires the `plot` feature.
+    #[cfg(feature = "plot")]
+    ExplainPlot {
+        /// The SVG file to write.
+        output: PathBuf,
+
+        /// The display max brightness used for the brightness axis and for
+        /// percentage brightness values. Defaults to the end of
+        /// `--brightness-range`, or the highest `--ambient-levels` level.
+        #[arg(long)]
+        max_brightness: Option<u32>,
+    },
+
+    /// Shows live proximity and ambient readings in the terminal as gauges
+    /// and sparklines, with `--proximity-range` marked, for tuning. Readings
+    /// are raw, without `--proximity-auto-zero`. The sensor is configured as
+    /// usual, but the display isn't touched. The measured conversion periods
+    /// are printed first. Exit with Ctrl-C. Requires the `tui` feature.
+    #[cfg(feature = "tui")]
+    Scope,
 }
 
-impl State {
-    fn update(&self, args: &Args, proximity: u32) -> Option<State> {
-        // if the detection threshold is exceeded, it's always detected
-        if self != &State::Detected && proximity >= args.proximity_range.end {
-            return Some(State::Detected);
-        }
+impl Args {
+    /// The configured proximity range, which clap requires unless a subcommand,
+    /// a profile (which always sets one), or `--ambient-only` is given, and
+    /// `validate_args` requires for the daemon. Without a range, the returned
+    /// range can never be reached.
+    fn proximity_range(&self) -> &Range<u32> {
+        self.proximity_range.as_ref().unwrap_or(&UNREACHABLE_PROXIMITY_RANGE)
+    }
 
-        match self {
-            State::Detected if proximity <= args.proximity_range.start => {
-                return Some(State::ClearedTransitioning(Instant::now()));
-            },
-            State::ClearedTransitioning(i) if i.elapsed() > args.proximity_hold => {
-                return Some(State::Cleared);
-            },
-            _ => (),
+    /// Whether proximity is measured, i.e. unless `--ambient-only` is set or
+    /// the LED current is 0.
+    fn proximity_enabled(&self) -> bool {
+        !self.ambient_only && self.proximity_led_current > 0
+    }
+
+    /// The LED current to program, which is 0 with `--ambient-only`.
+    fn led_current_ma(&self) -> u16 {
+        match self.ambient_only {
+            true => 0,
+            false => self.proximity_led_current,
         }
+    }
 
-        None
+    /// The channels that are measured, for data-ready waits.
+    fn measured_channels(&self) -> Channel {
+        match self.proximity_enabled() {
+            true => Channel::Both,
+            false => Channel::Ambient,
+        }
     }
 
-    fn transition(&self, display: &mut Display) -> Result<()> {
-        match self {
-            State::Detected => display.set_power(DisplayPowerMode::On)?,
-            State::Cleared => display.set_power(DisplayPowerMode::Off)?,
-            _ => (),
+    /// Whether `field` is included in the periodic data log.
+    fn summary_includes(&self, field: SummaryField) -> bool {
+        match (self.summary_fields.is_empty(), field) {
+            (false, _) => self.summary_fields.contains(&field),
+            (true, SummaryField::Proximity) => self.proximity_enabled(),
+            (true, SummaryField::Ambient) => true,
         }
+    }
 
-        Ok(())
+    /// Whether `proximity` is at or past `threshold` in the direction of
+    /// detection: at or above it, or at or below it with `--proximity-invert`.
+    fn toward_detection(&self, proximity: u32, threshold: u32) -> bool {
+        match self.proximity_invert {
+            false => proximity >= threshold,
+            true => proximity <= threshold,
+        }
     }
-}
 
-fn select_display(args: &Args) -> Result<Display> {
-    let displays = display::list_displays()?;
-    info!("found displays: {:?}", displays);
+    /// Whether any option that sets brightness is given.
+    fn brightness_control(&self) -> bool {
+        self.brightness_range.is_some()
+            || self.ambient_levels.is_some()
+            || self.detected_brightness.is_some()
+            || self.night_fixed_brightness.is_some()
+    }
 
-    if let Some(display_name) = &args.display_name {
-        let display = displays
-            .into_iter()
-            .find(|d| d.name.eq_ignore_ascii_case(display_name));
+    /// The largest brightness the options configure, for displays that have
+    /// no `max_brightness` of their own to bound it.
+    fn configured_max_brightness(&self) -> Option<u32> {
+        self.brightness_range
+            .as_ref()
+            .map(|r| r.end)
+            .or(self.ambient_levels.as_ref().map(|l| l.max_brightness()))
+    }
 
-        if let Some(display) = display {
-            Ok(display)
-        } else {
-            return Err(eyre!("requested display {display_name} not found"));
-        }
-    } else {
-        if let Some(display) = displays.into_iter().next() {
-            Ok(display)
-        } else {
-            return Err(eyre!("no displays found"));
+    fn day_night(&self) -> DayNight {
+        DayNight {
+            sunrise: self.sunrise,
+            sunset: self.sunset,
+            twilight: self.twilight,
         }
     }
-}
 
-fn map_ambient_to_display_brightness(
-    ambient: u32,
-    ambient_light_range: &Range<u32>,
-    brightness_range: &Range<u32>
-) -> u32 {
-    let ambient_start = ambient_light_range.start as f32;
-    let brightness_start = brightness_range.start as f32;
-    let ambient_span = (ambient_light_range.end - ambient_light_range.start) as f32;
-    let brightness_span = (brightness_range.end - brightness_range.start) as f32;
+    /// Whether `--night-fixed-brightness` applies right now.
+    fn night_fixed_active(&self) -> bool {
+        self.night_fixed_active_at(TimeOfDay::of(&LocalTime::now()))
+    }
 
-    if ambient_span == 0.0 || brightness_span == 0.0 {
-        return brightness_range.start;
+    /// Whether `--night-fixed-brightness` applies at `time`.
+    fn night_fixed_active_at(&self, time: TimeOfDay) -> bool {
+        self.night_fixed_brightness.is_some() && self.day_night().is_night(time)
     }
 
-    let mapped = brightness_start + ((ambient as f32 - ambient_start) * brightness_span) / ambient_span;
+    /// Whether `proximity` reaches the detection end of `--proximity-range`.
+    fn proximity_detected(&self, proximity: u32) -> bool {
+        match self.proximity_invert {
+            false => proximity >= self.proximity_range().end,
+            true => proximity <= self.proximity_range().start,
+        }
+    }
 
-    (mapped.round() as u32).clamp(brightness_range.start, brightness_range.end)
+    /// Whether `proximity` reaches the clearing end of `--proximity-range`.
+    fn proximity_cleared(&self, proximity: u32) -> bool {
+        match self.proximity_invert {
+            false => proximity <= self.proximity_range().start,
+            true => proximity >= self.proximity_range().end,
+        }
+    }
 }
 
-fn main() -> Result<()> {
-    install_tracing();
-    color_eyre::install()?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BrightnessWhileOff {
+    Keep,
+    Zero,
+    Skip,
+}
 
-    let args = Args::parse();
+impl BrightnessWhileOff {
+    /// Whether brightness mapping is skipped entirely.
+    fn pauses(self, off: bool) -> bool {
+        off && self == BrightnessWhileOff::Skip
+    }
 
-    let mut selected_display = select_display(&args)?;
-    info!("selected display: {selected_display:?}");
+    /// The brightness to write for a mapped value.
+    fn apply(self, off: bool, mapped: Option<u32>) -> Option<u32> {
+        match (off, self) {
+            (true, BrightnessWhileOff::Zero) => mapped.map(|_| 0),
+            (true, BrightnessWhileOff::Skip) => None,
+            _ => mapped,
+        }
+    }
+}
 
-    let mut sensor = ProximitySensor::try_new(&args.i2c_device)?;
-    let product = sensor.read_product()?.verify()?;
-    info!("product: {product:?}");
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SummaryField {
+    Proximity,
+    Ambient,
+}
 
-    let command = sensor.read_command_register()?;
-    info!("command: {command:?}");
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ZeroAmbient {
+    Accept,
+    Warn,
+    Hold,
+}
 
-    sensor.set_command_register(
-        SensorCommand::new()
-            .with_self_timed_enabled(true)
-            .with_proximity_enabled(true)
-            .with_ambient_light_enabled(true),
-    )?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum I2CMuxType {
+    /// TCA9548A, PCA9548A, and other muxes with one enable bit per channel.
+    Tca9548a,
 
-    sensor.set_led_current_ma(args.proximity_led_current)?;
-    info!("current: {:?}", sensor.read_led_current()?);
+    /// PCA9547 and other muxes taking an encoded channel number.
+    Pca9547,
+}
 
-    let command = sensor.read_command_register()?;
-    info!("updated command: {command:?}");
+impl From<I2CMuxType> for MuxKind {
+    fn from(kind: I2CMuxType) -> MuxKind {
+        match kind {
+            I2CMuxType::Tca9548a => MuxKind::Tca9548a,
+            I2CMuxType::Pca9547 => MuxKind::Pca9547,
+        }
+    }
+}
 
-    let mut count: usize = 0;
-    let mut state = State::Cleared;
-    let mut brightness: u32 = 0;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Full,
+    Compact,
+}
 
-    loop {
-        let proximity_val = sensor.read_proximity()? as u32;
-        let ambient_light_val = sensor.read_ambient_light()? as u32;
+/// How often `--log-file` starts a new file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogRotation {
+    Hourly,
+    Daily,
+}
 
-        if let Some(new) = state.update(&args, proximity_val) {
-            info!("new state: {new:?}");
-            state = new;
-            state.transition(&mut selected_display)?;
+impl LogRotation {
+    fn rotation(self) -> tracing_appender::rolling::Rotation {
+        match self {
+            LogRotation::Hourly => tracing_appender::rolling::Rotation::HOURLY,
+            LogRotation::Daily => tracing_appender::rolling::Rotation::DAILY,
         }
+    }
+}
 
-        if let (Some(ambient), Some(display)) = (&args.ambient_light_range, &args.brightness_range) {
-            let new_brightness = map_ambient_to_display_brightness(ambient_light_val, ambient, display);
-            if new_brightness != brightness {
-                brightness = new_brightness;
-                selected_display.set_brightness(brightness)?;
-                info!("set brightness to {brightness} (ambient: {ambient_light_val})")
-            }
-        }
+/// The proximity range used with `--ambient-only` if none is given, which
+/// proximity (always read as 0) never reaches.
+static UNREACHABLE_PROXIMITY_RANGE: Range<u32> = u32::MAX - 1..u32::MAX;
 
-        count += 1;
-        if count % 20 == 0 {
-            // log the current data every 5s
-            info!("proximity: {proximity_val} | ambient: {ambient_light_val}");
-        }
+/// Tracing target for `--log-format compact` status lines.
+const STATUS_TARGET: &str = "status";
 
-        thread::sleep(Duration::from_millis(250));
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SampleReduction {
+    Mean,
+    Median,
 }
 
-pub fn install_tracing() {
+impl SampleReduction {
+    fn reduce(self, samples: &mut [u32]) -> u32 {
+        match self {
+            SampleReduction::Mean => {
+                let sum: u64 = samples.iter().map(|&v| v as u64).sum();
+                (sum as f64 / samples.len() as f64).round() as u32
+            },
+            SampleReduction::Median => {
+                samples.sort_unstable();
+                samples[samples.len() / 2]
+            },
+        }
+    }
+}
+
+fn open_sensor(args: &Args) -> Result<ProximitySensor> {
+    let mux = match (args.i2c_mux_addr, args.i2c_mux_channel) {
+        (Some(addr), Some(channel)) => Some(I2CMux { kind: args.i2c_mux_type.into(), addr, channel }),
+        _ => None,
+    };
+    let mut sensor = match (mux, args.share_i2c_device) {
+        (Some(mux), shared) => {
+            info!("using i2c mux {:#04x} channel {} ({:?})", mux.addr, mux.channel, mux.kind);
+            ProximitySensor::try_new_with_mux(&args.i2c_device, mux, !shared)?
+        },
+        (None, true) => ProximitySensor::try_new_shared(&args.i2c_device)?,
+        (None, false) => ProximitySensor::try_new(&args.i2c_device)?,
+    };
+    sensor.set_swap_result_bytes(args.swap_result_bytes);
+    sensor.set_word_access(!args.no_word_access);
+    if let Some(retries) = args.i2c_bus_retries {
+        sensor.set_bus_retries(retries)?;
+    }
+    if let Some(timeout) = args.i2c_bus_timeout {
+        sensor.set_bus_timeout(timeout)?;
+    }
+
+    Ok(sensor)
+}
+
+/// Takes a few on-demand proximity measurements and warns if they look
+/// byte-swapped, returning whether they did. Consecutive measurements of a
+/// still scene differ by only a few counts, so if they instead vary by
+/// hundreds while the byte-swapped values are stable, the result bytes are
+/// likely in the wrong order.
+fn check_byte_order(sensor: &mut ProximitySensor<impl SensorDevice>, swapped: bool) -> bool {
+    let mut samples = Vec::with_capacity(BYTE_ORDER_CHECK_SAMPLES);
+    for _ in 0..BYTE_ORDER_CHECK_SAMPLES {
+        match sensor.measure_proximity_on_demand(Duration::from_millis(100)) {
+            Ok(v) => samples.push(v),
+            Err(e) => {
+                warn!("could not take on-demand measurements, skipping byte order check: {e}");
+                return false;
+            },
+        }
+    }
+
+    let Some((as_read, other_order)) = byte_swap_spreads(&samples) else {
+        return false;
+    };
+    warn!(
+        "proximity samples vary by {as_read} counts but only {other_order} with bytes swapped; \
+        results look byte-swapped, try {} --swap-result-bytes (samples: {samples:?})",
+        if swapped { "removing" } else { "adding" }
+    );
+
+    true
+}
+
+/// Returns the spread of `samples` as read and with bytes swapped, if they
+/// look byte-swapped per `check_byte_order`.
+fn byte_swap_spreads(samples: &[u16]) -> Option<(u16, u16)> {
+    let spread = |values: &mut dyn Iterator<Item = u16>| {
+        let (min, max) = values.fold((u16::MAX, 0), |(min, max), v| (min.min(v), max.max(v)));
+        max.saturating_sub(min)
+    };
+    let as_read = spread(&mut samples.iter().copied());
+    let other_order = spread(&mut samples.iter().map(|v| v.swap_bytes()));
+
+    (as_read >= 256 && other_order < 256).then_some((as_read, other_order))
+}
+
+/// Logs a state transition along with the proximity value that caused it.
+/// When clearing, this includes how long the detection session lasted
+/// (`session`) and how long the hold timer ran.
+fn log_transition(old: &State, new: &State, proximity: u32, session: Option<Duration>) {
+    let state = new.name();
+    match (old, new) {
+        (State::ClearedTransitioning(hold_start), State::Cleared) => {
+            let hold = hold_start.elapsed();
+            let session = session.unwrap_or_default();
+            info!(
+                state,
+                proximity,
+                session_secs = session.as_secs_f64(),
+                hold_secs = hold.as_secs_f64(),
+                "new state: {state} (proximity: {proximity}) after a session of {} including {} hold",
+                humantime::format_duration(Duration::from_secs(session.as_secs())),
+                humantime::format_duration(Duration::from_secs(hold.as_secs())),
+            );
+        },
+        (_, State::Cleared) => {
+            let session = session.unwrap_or_default();
+            info!(
+                state,
+                proximity,
+                session_secs = session.as_secs_f64(),
+                "new state: {state} (proximity: {proximity}) after a session of {}",
+                humantime::format_duration(Duration::from_secs(session.as_secs())),
+            );
+        },
+        _ => info!(state, proximity, "new state: {state} (proximity: {proximity})"),
+    }
+}
+
+/// Programs the sensor's proximity thresholds from `--proximity-range` (plus
+/// the auto-zero offset, if any) and enables threshold interrupts, returning
+/// the (low, high) thresholds.
+fn program_interrupts(sensor: &mut ProximitySensor<impl SensorDevice>, args: &Args, offset: u32) -> Result<(u16, u16)> {
+    let range = args.proximity_range();
+    let low = (range.start + offset).min(u16::MAX as u32) as u16;
+    let high = (range.end + offset).min(u16::MAX as u32) as u16;
+
+    sensor.set_thresholds(low, high)?;
+    sensor.set_interrupt_control(
+        InterruptControl::new()
+            .with_threshold_enabled(true)
+            .with_count_exceed(args.interrupt_count.value()),
+    )?;
+
+    Ok((low, high))
+}
+
+/// Re-reads the command register and reinitializes the sensor if measurements
+/// are no longer enabled as set by `reinitialize_sensor`. Read errors are left
+/// to the normal sample read path, which handles recovery.
+fn check_command_register(
+    sensor: &mut ProximitySensor<impl SensorDevice>,
+    args: &Args,
+    led_current_ma: u16,
+    interrupt_offset: Option<u32>,
+) -> Result<()> {
+    let command = match sensor.read_command_register() {
+        Ok(command) => command,
+        Err(e) => {
+            debug!("could not read command register: {e}");
+            return Ok(());
+        },
+    };
+
+    let expected = measurement_command(args);
+    let enabled = |c: SensorCommand| (c.self_timed_enabled(), c.proximity_enabled(), c.ambient_light_enabled());
+    if enabled(command) == enabled(expected) {
+        return Ok(());
+    }
+
+    warn!(
+        command = command.into_bits(),
+        self_timed_enabled = command.self_timed_enabled(),
+        proximity_enabled = command.proximity_enabled(),
+        ambient_light_enabled = command.ambient_light_enabled(),
+        config_lock = command.config_lock(),
+        "command register changed unexpectedly (sensor reset?): {command:?}, expected {expected:?}; reinitializing"
+    );
+    reinitialize_sensor(sensor, args, led_current_ma)?;
+    if let Some(offset) = interrupt_offset {
+        program_interrupts(sensor, args, offset)?;
+    }
+    if !sensor.wait_for_channel_ready(args.measured_channels(), DATA_READY_TIMEOUT)? {
+        warn!("sensor data not ready after {DATA_READY_TIMEOUT:?}, readings may be invalid");
+    }
+
+    Ok(())
+}
+
+/// A sensor that `recover_sensor` can reopen.
+trait Reopen {
+    fn reopen(&mut self) -> vcnl4010::Result<()>;
+}
+
+impl Reopen for ProximitySensor {
+    fn reopen(&mut self) -> vcnl4010::Result<()> {
+        ProximitySensor::reopen(self)
+    }
+}
+
+/// The mock device can't be disconnected, so there's nothing to reopen.
+#[cfg(test)]
+impl Reopen for ProximitySensor<vcnl4010::mock::MockDevice> {
+    fn reopen(&mut self) -> vcnl4010::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reopens and reinitializes the sensor after repeated read failures, e.g.
+/// because it was disconnected, retrying with exponential backoff up to
+/// `--sensor-reopen-attempts` times.
+fn recover_sensor<S: SensorDevice>(
+    sensor: &mut ProximitySensor<S>,
+    args: &Args,
+    led_current_ma: u16,
+    interrupt_offset: Option<u32>,
+) -> Result<()>
+where
+    ProximitySensor<S>: Reopen,
+{
+    let mut delay = SENSOR_REOPEN_INITIAL_DELAY;
+    for attempt in 1..=args.sensor_reopen_attempts {
+        if signal::shutdown_requested() {
+            return Ok(());
+        }
+
+        let result = sensor.reopen().map_err(|e| eyre!(e)).and_then(|_| {
+            sensor.read_product()?.verify()?;
+            reinitialize_sensor(sensor, args, led_current_ma)?;
+            if let Some(offset) = interrupt_offset {
+                program_interrupts(sensor, args, offset)?;
+            }
+            if !sensor.wait_for_channel_ready(args.measured_channels(), DATA_READY_TIMEOUT)? {
+                warn!("sensor data not ready after {DATA_READY_TIMEOUT:?}, readings may be invalid");
+            }
+
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => {
+                info!("sensor recovered after {attempt} reopen attempt(s)");
+                return Ok(());
+            },
+            Err(e) => {
+                warn!(
+                    "sensor reopen attempt {attempt}/{} failed, retrying in {}: {e:#}",
+                    args.sensor_reopen_attempts,
+                    humantime::format_duration(delay)
+                );
+                thread::sleep(delay);
+                delay = (delay * 2).min(SENSOR_REOPEN_MAX_DELAY);
+            },
+        }
+    }
+
+    Err(eyre!("sensor could not be reopened after {} attempts", args.sensor_reopen_attempts))
+}
+
+/// Returns true if a raw proximity reading should be dropped per
+/// `--reject-saturated-proximity` and `--reject-zero-proximity`.
+fn is_invalid_proximity(args: &Args, proximity: u16) -> bool {
+    (args.reject_saturated_proximity && proximity == u16::MAX)
+        || (args.reject_zero_proximity && proximity == 0)
+}
+
+/// Whether ambient light should be read this iteration, per
+/// `--ambient-interval`. Until a value has been read, it always is.
+fn ambient_read_due(last_read: Option<Instant>, interval: Duration, clock: &impl Clock) -> bool {
+    last_read.is_none_or(|t| clock.since(t) >= interval)
+}
+
+/// Reads `--samples-per-read` proximity and ambient light samples, returning
+/// the reduced (proximity, ambient) values. Samples with invalid proximity
+/// readings are dropped, and `None` is returned if all of them were invalid.
+/// Ambient light is only read if `read_ambient` is set, and is otherwise
+/// returned as `None`.
+fn read_samples(
+    sensor: &mut ProximitySensor<impl SensorDevice>,
+    args: &Args,
+    read_ambient: bool,
+) -> Result<Option<(u32, Option<u32>)>> {
+    let n = args.samples_per_read as usize;
+    let mut proximity = Vec::with_capacity(n);
+    let mut ambient = Vec::with_capacity(n);
+
+    for _ in 0..n {
+        // with proximity disabled it's reported as 0, which is never detected
+        let p = if args.proximity_enabled() { sensor.read_proximity()? } else { 0 };
+        let a = if read_ambient { Some(sensor.read_ambient_light()?) } else { None };
+        if args.proximity_enabled() && is_invalid_proximity(args, p) {
+            continue;
+        }
+
+        proximity.push(p as u32);
+        ambient.extend(a.map(u32::from));
+    }
+
+    if proximity.is_empty() {
+        return Ok(None);
+    }
+
+    let ambient = (!ambient.is_empty()).then(|| args.sample_reduction.reduce(&mut ambient));
+    Ok(Some((args.sample_reduction.reduce(&mut proximity), ambient)))
+}
+
+/// The command register value used for normal operation.
+fn measurement_command(args: &Args) -> SensorCommand {
+    SensorCommand::new()
+        .with_self_timed_enabled(true)
+        .with_proximity_enabled(args.proximity_enabled())
+        .with_ambient_light_enabled(true)
+}
+
+/// Rewrites the sensor's configuration, e.g. after it starts returning
+/// invalid readings.
+fn reinitialize_sensor(
+    sensor: &mut ProximitySensor<impl SensorDevice>,
+    args: &Args,
+    led_current_ma: u16,
+) -> Result<()> {
+    sensor.set_command_register_verified(measurement_command(args))?;
+    sensor.set_led_current_ma(led_current_ma)?;
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum State {
+    Detected,
+    Cleared,
+    ClearedTransitioning(Instant),
+
+    /// Proximity reached `--proximity-approach` but not the detection
+    /// threshold; the display is powered on early.
+    Approaching,
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Detected => "detected",
+            State::Cleared => "cleared",
+            State::ClearedTransitioning(_) => "cleared_transitioning",
+            State::Approaching => "approaching",
+        }
+    }
+
+    /// Returns the next state for a proximity reading, if it changes.
+    /// `cleared_at` is when the state last became `Cleared`, for
+    /// `--rearm-cooldown`.
+    ///
+    /// With `start..end` as `--proximity-range`:
+    ///
+    /// | state                  | proximity  | hold elapsed | next                            |
+    /// |------------------------|------------|--------------|---------------------------------|
+    /// | `Detected`             | `<= start` | -            | `ClearedTransitioning`          |
+    /// | `Detected`             | `> start`  | -            | unchanged                       |
+    /// | `ClearedTransitioning` | `>= end`   | -            | `Detected`                      |
+    /// | `ClearedTransitioning` | `< end`    | `> hold`     | `Cleared`                       |
+    /// | `ClearedTransitioning` | `< end`    | `<= hold`    | unchanged                       |
+    /// | `Cleared`              | `>= end`   | -            | `Detected`, unless cooling down |
+    /// | `Cleared`              | `< end`    | -            | unchanged                       |
+    ///
+    /// Note that during the hold, readings back inside the hysteresis band
+    /// (above `start` but below `end`) don't cancel it; only reaching `end`
+    /// does. The hold must also strictly exceed `--proximity-hold`.
+    ///
+    /// With `--proximity-approach`, readings at or above it (but below `end`)
+    /// move `Cleared` (outside any cooldown) and `ClearedTransitioning` to
+    /// `Approaching`, which powers the display on early. Falling back below
+    /// the approach threshold starts the hold as usual.
+    ///
+    /// With `--proximity-invert`, every comparison is flipped: `<= start`
+    /// detects, `>= end` starts the hold, and approach and rearm thresholds
+    /// trigger at or below their values.
+    ///
+    /// With `--hold-extend`, see `extend_hold` for readings above `start`
+    /// during the hold.
+    ///
+    /// `ambient_wake` (from `--ambient-wake-delta`) counts as reaching `end`.
+    /// It never overrides a rearm cooldown.
+    fn update(
+        &self,
+        args: &Args,
+        proximity: u32,
+        ambient_wake: bool,
+        clock: &impl Clock,
+        cleared_at: Option<Instant>,
+    ) -> Option<State> {
+        let cooling_down = match (self, args.rearm_cooldown, cleared_at) {
+            (State::Cleared, Some(cooldown), Some(t)) => clock.since(t) < cooldown,
+            _ => false,
+        };
+
+        // if the detection threshold is exceeded, it's always detected, unless
+        // the display only just turned off and the reading isn't strong enough
+        // to override the cooldown
+        if self != &State::Detected && (args.proximity_detected(proximity) || ambient_wake) {
+            let strong = args.rearm_threshold.is_some_and(|threshold| args.toward_detection(proximity, threshold));
+            if cooling_down && !strong {
+                return None;
+            }
+
+            return Some(State::Detected);
+        }
+
+        let approaching = args.proximity_approach.is_some_and(|approach| args.toward_detection(proximity, approach));
+        match self {
+            State::Detected if args.proximity_cleared(proximity) => {
+                return Some(State::ClearedTransitioning(clock.now()));
+            },
+            State::Cleared | State::ClearedTransitioning(_) if approaching && !cooling_down => {
+                return Some(State::Approaching);
+            },
+            State::Approaching if !approaching => {
+                return Some(State::ClearedTransitioning(clock.now()));
+            },
+            State::ClearedTransitioning(i) if clock.since(*i) > args.proximity_hold => {
+                return Some(State::Cleared);
+            },
+            _ => (),
+        }
+
+        None
+    }
+
+    /// Restarts the hold timer if `--hold-extend` is set and `proximity` is
+    /// still inside the hysteresis band, returning whether it did. This is
+    /// applied before `update`, and isn't a state transition.
+    fn extend_hold(&mut self, args: &Args, proximity: u32, clock: &impl Clock) -> bool {
+        match self {
+            State::ClearedTransitioning(start) if args.hold_extend && !args.proximity_cleared(proximity) => {
+                *start = clock.now();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Describes which rule `update` applied for a proximity value, for
+    /// `--explain`.
+    fn explain_update(&self, args: &Args, proximity: u32, ambient_wake: bool) -> &'static str {
+        let approaching = args.proximity_approach.is_some_and(|a| args.toward_detection(proximity, a));
+        match (self, args.proximity_invert) {
+            (_, _) if self != &State::Detected && ambient_wake && !args.proximity_detected(proximity) => {
+                "ambient light rose past --ambient-wake-delta: detected"
+            },
+            (_, false) if self != &State::Detected && args.proximity_detected(proximity) => {
+                "at or above range end: detected"
+            },
+            (_, true) if self != &State::Detected && args.proximity_detected(proximity) => {
+                "at or below range start (inverted): detected"
+            },
+            (State::Detected, false) if args.proximity_cleared(proximity) => "at or below range start: starting hold",
+            (State::Detected, true) if args.proximity_cleared(proximity) => {
+                "at or above range end (inverted): starting hold"
+            },
+            (State::Detected, false) => "above range start: staying detected",
+            (State::Detected, true) => "below range end (inverted): staying detected",
+            (State::Cleared | State::ClearedTransitioning(_), _) if approaching => {
+                "reached approach threshold: approaching"
+            },
+            (State::Approaching, _) if approaching => "reached approach threshold: staying on",
+            (State::Approaching, _) => "left approach threshold: starting hold",
+            (State::ClearedTransitioning(_), _) if args.hold_extend && !args.proximity_cleared(proximity) => {
+                "detection threshold not reached, but near: hold timer restarted"
+            },
+            (State::ClearedTransitioning(_), _) => "detection threshold not reached: hold timer running",
+            (State::Cleared, _) => "detection threshold not reached: staying cleared",
+        }
+    }
+
+    /// Powers the display on or off for this state, unless
+    /// `--presence-log-only` is set, fading brightness per `--fade-in` and
+    /// `--fade-out`. With `--night-fixed-brightness`, `Cleared` keeps the
+    /// display on at night.
+    fn transition(&self, display: &mut Display, args: &Args) -> Result<()> {
+        if args.presence_log_only {
+            return Ok(());
+        }
+
+        let fade = args.brightness_control();
+        let on = display.power == Some(DisplayPowerMode::On);
+        match self {
+            State::Detected | State::Approaching if fade && !on && !args.fade_in.is_zero() => {
+                display.fade_on(args.fade_in)?
+            },
+            State::Detected | State::Approaching => display.set_power(DisplayPowerMode::On)?,
+            State::Cleared if args.night_fixed_active() => display.set_power(DisplayPowerMode::On)?,
+            State::Cleared if fade && !display.is_off() && !args.fade_out.is_zero() => display.fade_off(args.fade_out)?,
+            State::Cleared => display.set_power(DisplayPowerMode::Off)?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Selects the display to manage, returning it along with the names of all
+/// known displays.
+fn select_display(args: &Args) -> Result<(Display, Vec<String>)> {
+    if let Some((primary, linked)) = args.backlight_path.split_first() {
+        let mut display = Display::try_from_path(primary)?;
+        for path in linked {
+            display.link_backlight(path)?;
+        }
+
+        let names = vec![display.name.clone()];
+        return Ok((display, names));
+    }
+
+    let displays = display::list_displays(&args.backlight_root)?;
+    info!("found displays: {:?}", displays);
+    let names = displays.iter().map(|d| d.name.clone()).collect();
+
+    // without any backlights, power can still be controlled by name
+    if let (true, Some(display_name)) = (displays.is_empty(), &args.display_name) {
+        if args.brightness_control() && args.brightness_backend == BrightnessBackend::Sysfs {
+            return Err(eyre!("no sysfs backlights found, so brightness can't be controlled"));
+        }
+
+        warn!("no sysfs backlights found, controlling power only for display {display_name}");
+        return Ok((Display::without_backlight(display_name.clone()), vec![display_name.clone()]));
+    }
+
+    if let Some(display_name) = &args.display_name {
+        let display = displays
+            .into_iter()
+            .find(|d| d.name.eq_ignore_ascii_case(display_name));
+
+        if let Some(display) = display {
+            Ok((display, names))
+        } else {
+            Err(eyre!("requested display {display_name} not found"))
+        }
+    } else {
+        if let Some(display) = displays.into_iter().next() {
+            Ok((display, names))
+        } else {
+            Err(eyre!("no displays found"))
+        }
+    }
+}
+
+/// Checks for argument combinations clap can't validate on its own.
+fn validate_args(args: &Args) -> Result<()> {
+    // subcommands like explain-plot work without a range
+    if args.proximity_range.is_none() && !args.ambient_only && args.command.is_none() {
+        return Err(eyre!("--proximity-range is required unless --ambient-only is set"));
+    }
+
+    if args.proximity_invert && !args.proximity_enabled() {
+        return Err(eyre!("--proximity-invert needs proximity measurements, but --proximity-led-current is 0"));
+    }
+
+    if let Some(proximity) = &args.proximity_range {
+        validate_proximity_range(args, proximity)?;
+    }
+
+    // inverted ranges would underflow or panic when mapping
+    let ranges = [
+        ("--ambient-light-range", &args.ambient_light_range),
+        ("--brightness-range", &args.brightness_range),
+        ("--night-brightness-range", &args.night_brightness_range),
+        ("--proximity-boost-range", &args.proximity_boost_range),
+    ];
+    for (flag, range) in ranges {
+        if let Some(range) = range.as_ref().filter(|r| r.start > r.end) {
+            return Err(eyre!("{flag} {}..{} is inverted; the start must not exceed the end", range.start, range.end));
+        }
+    }
+
+    #[cfg(feature = "brightness-expr")]
+    if let Some(expr) = &args.brightness_expr {
+        validate_brightness_expr(expr, args)?;
+    }
+
+    if args.ambient_wake_delta == Some(0) {
+        return Err(eyre!("--ambient-wake-delta must be above 0, or every ambient reading would wake the display"));
+    }
+
+    Ok(())
+}
+
+/// Fails if brightness control is requested through a sysfs backlight that
+/// reports a `max_brightness` of 0, rather than failing on the first write.
+fn check_backlight_supported(display: &Display, args: &Args) -> Result<()> {
+    let unsupported_backlight = display.has_backlight() && display.max_brightness == 0;
+    if unsupported_backlight && args.brightness_control() && args.brightness_backend == BrightnessBackend::Sysfs {
+        return Err(eyre!(
+            "display {} reports a max_brightness of 0, so its brightness can't be controlled; remove the \
+            brightness options to control power only",
+            display.name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks `--proximity-range` and the thresholds relative to it.
+fn validate_proximity_range(args: &Args, proximity: &Range<u32>) -> Result<()> {
+    if proximity.start >= proximity.end {
+        return Err(eyre!(
+            "--proximity-range {}..{} has no hysteresis band, which would cause the display to \
+            flap on and off; use a lower start than end, e.g. {}..{}",
+            proximity.start,
+            proximity.end,
+            proximity.start.min(proximity.end).saturating_sub(25),
+            proximity.start.max(proximity.end) + 25,
+        ));
+    }
+
+    match args.proximity_approach {
+        Some(approach) if args.proximity_invert && approach <= proximity.start => {
+            return Err(eyre!(
+                "--proximity-approach {approach} must be above the start of --proximity-range ({}) \
+                with --proximity-invert",
+                proximity.start
+            ));
+        },
+        Some(approach) if !args.proximity_invert && approach >= proximity.end => {
+            return Err(eyre!(
+                "--proximity-approach {approach} must be below the end of --proximity-range ({})",
+                proximity.end
+            ));
+        },
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Warns if the configured brightness range exceeds what the display supports.
+fn validate_brightness_range(display: &Display, args: &Args) {
+    let max = display.max_brightness;
+    if let Some(range) = &args.brightness_range {
+        if range.end > max {
+            warn!("brightness range {range:?} exceeds display max_brightness {max}, values will be limited");
+        }
+    }
+
+    if args.detected_brightness.is_some() && (args.ambient_light_range.is_some() || args.ambient_levels.is_some()) {
+        warn!("--detected-brightness is set, ambient light will not affect brightness");
+    }
+
+    if let Some(levels) = &args.ambient_levels {
+        if levels.max_brightness() > max {
+            warn!("ambient levels exceed display max_brightness {max}, values will be limited");
+        }
+    }
+}
+
+/// Re-reads the display's `max_brightness`, logging and re-validating the
+/// brightness range if it changed.
+fn refresh_display(display: &mut Display, args: &Args) -> Result<()> {
+    let old = display.max_brightness;
+    if display.refresh_max_brightness()? {
+        let new = display.max_brightness;
+        info!("display max_brightness changed: {old} -> {new}");
+        validate_brightness_range(display, args);
+    }
+
+    Ok(())
+}
+
+/// Sets the display brightness, refreshing `max_brightness` and retrying once
+/// (limited to the new maximum) if the write is rejected as out of range.
+/// `--panel-gamma` is applied to the value written, but the returned value is
+/// the uncorrected brightness.
+fn set_brightness(display: &mut Display, args: &Args, brightness: u32) -> Result<u32> {
+    let corrected = apply_panel_gamma(brightness, display.max_brightness, args.panel_gamma);
+    match display.set_brightness(corrected) {
+        Ok(()) => Ok(brightness),
+        Err(DisplayError::BrightnessOutOfRange { .. }) => {
+            refresh_display(display, args)?;
+
+            let limited = brightness.min(display.max_brightness);
+            display.set_brightness(apply_panel_gamma(limited, display.max_brightness, args.panel_gamma))?;
+
+            Ok(limited)
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The fixed brightness for `--detected-brightness`, using `--hold-brightness`
+/// instead during the hold, if set.
+fn detected_brightness(args: &Args, state: &State, max_brightness: u32) -> Option<u32> {
+    let level = match (state, args.hold_brightness) {
+        (State::ClearedTransitioning(_), Some(hold)) => Some(hold),
+        _ => args.detected_brightness,
+    };
+    level.map(|l| l.resolve(max_brightness))
+}
+
+/// The brightness for `--night-fixed-brightness`, plus any proximity boost.
+fn night_fixed_brightness(args: &Args, proximity: u32, max_brightness: u32) -> Option<u32> {
+    args.night_fixed_brightness.map(|level| {
+        let boost = proximity_boost(args, proximity, max_brightness);
+        (level.resolve(max_brightness) + boost).min(max_brightness)
+    })
+}
+
+/// Whether a brightness write may happen now, per `--brightness-write-interval`.
+/// With an interval, intermediate targets are dropped and the latest is
+/// written once the interval has elapsed since the last write.
+fn brightness_write_due(last_write: Option<Instant>, interval: Duration, clock: &impl Clock) -> bool {
+    last_write.is_none_or(|t| clock.since(t) >= interval)
+}
+
+/// Maps a brightness onto the value written to the panel through a gamma
+/// curve scaled to `max_brightness`. A gamma of 1.0 returns it unchanged.
+fn apply_panel_gamma(brightness: u32, max_brightness: u32, gamma: f32) -> u32 {
+    if gamma == 1.0 || max_brightness == 0 {
+        return brightness;
+    }
+
+    let fraction = brightness as f32 / max_brightness as f32;
+    (fraction.powf(gamma) * max_brightness as f32).round() as u32
+}
+
+fn parse_gamma(s: &str) -> Result<f32, String> {
+    match f32::from_str(s) {
+        Ok(gamma) if gamma.is_finite() && gamma > 0.0 => Ok(gamma),
+        _ => Err(format!("Invalid gamma (expected a positive number): {s}")),
+    }
+}
+
+/// Scales a proximity value (after any auto-zero offset) to a 0-100 presence
+/// strength, from `floor` up to the end of `--proximity-range`, where the
+/// state machine detects presence. The floor is the baseline when one is
+/// known, and otherwise the start of the range; it is kept below the end of
+/// the range so a baseline that has drifted up to it still maps cleanly.
+fn presence_strength(args: &Args, proximity: u32, baseline: Option<&BaselineTracker>) -> u8 {
+    let range = args.proximity_range();
+    let floor = match (baseline, args.proximity_auto_zero) {
+        (Some(_), true) => 0,
+        (Some(b), false) => b.baseline(),
+        (None, _) => range.start,
+    };
+    let floor = floor.min(range.end.saturating_sub(1));
+
+    map_ambient_to_display_brightness(proximity, &(floor..range.end), &(0..100)) as u8
+}
+
+/// Rounds a value to the nearest multiple of `granularity`.
+fn round_to(value: u32, granularity: u32) -> u32 {
+    if granularity <= 1 {
+        return value;
+    }
+
+    ((value + granularity / 2) / granularity).saturating_mul(granularity)
+}
+
+/// How often the current sensor data is logged.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait for the first measurements after (re)initializing the
+/// sensor.
+const DATA_READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Number of on-demand measurements used to check the result byte order.
+const BYTE_ORDER_CHECK_SAMPLES: usize = 8;
+
+/// Backoff bounds between attempts to reopen a failed sensor.
+const SENSOR_REOPEN_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const SENSOR_REOPEN_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Number of steps used when ramping the LED current via `--proximity-led-ramp`.
+const LED_RAMP_STEPS: u32 = 5;
+
+/// Sets the sensor's LED current, ramping it up from 0 in a few steps over the
+/// given duration. A zero duration sets the target current immediately.
+fn ramp_led_current(
+    sensor: &mut ProximitySensor,
+    target_ma: u16,
+    duration: Duration
+) -> Result<()> {
+    if duration.is_zero() {
+        sensor.set_led_current_ma(target_ma)?;
+        return Ok(());
+    }
+
+    let step_delay = duration / LED_RAMP_STEPS;
+    for step in 0..=LED_RAMP_STEPS {
+        let current = (target_ma as u32 * step / LED_RAMP_STEPS) as u16;
+        sensor.set_led_current_ma(current)?;
+
+        if step < LED_RAMP_STEPS {
+            thread::sleep(step_delay);
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps an ambient light value onto `brightness_range`, through
+/// `--brightness-expr` or the luminance table if either is given.
+fn map_ambient(
+    args: &Args,
+    luminance: Option<&LuminanceTable>,
+    ambient: u32,
+    ambient_light_range: &Range<u32>,
+    brightness_range: &Range<u32>,
+) -> u32 {
+    #[cfg(feature = "brightness-expr")]
+    if let Some(expr) = &args.brightness_expr {
+        let value = eval_brightness_expr(expr, ambient, ambient_light_range, brightness_range);
+        if !value.is_finite() {
+            return brightness_range.start;
+        }
+
+        return (value.round().max(0.0) as u32).clamp(brightness_range.start, brightness_range.end);
+    }
+    #[cfg(not(feature = "brightness-expr"))]
+    let _ = args;
+
+    match luminance {
+        Some(table) => table.map_ambient(ambient, ambient_light_range, brightness_range),
+        None => map_ambient_to_display_brightness(ambient, ambient_light_range, brightness_range),
+    }
+}
+
+/// Evaluates `--brightness-expr` for an ambient light value, unrounded and
+/// unclamped.
+#[cfg(feature = "brightness-expr")]
+fn eval_brightness_expr(
+    expr: &BrightnessExpr,
+    ambient: u32,
+    ambient_light_range: &Range<u32>,
+    brightness_range: &Range<u32>,
+) -> f64 {
+    let span = (ambient_light_range.end - ambient_light_range.start) as f64;
+    let t = if span == 0.0 {
+        0.0
+    } else {
+        ((ambient as f64 - ambient_light_range.start as f64) / span).clamp(0.0, 1.0)
+    };
+
+    expr.eval(&expr::Inputs {
+        t,
+        ambient: ambient as f64,
+        min: brightness_range.start as f64,
+        max: brightness_range.end as f64,
+    })
+}
+
+/// Number of ambient light values `--brightness-expr` is checked at.
+#[cfg(feature = "brightness-expr")]
+const EXPR_CHECK_SAMPLES: u32 = 100;
+
+/// Checks `--brightness-expr` over the ambient range, against each configured
+/// brightness range.
+#[cfg(feature = "brightness-expr")]
+fn validate_brightness_expr(expr: &BrightnessExpr, args: &Args) -> Result<()> {
+    let (Some(ambient), Some(day)) = (&args.ambient_light_range, &args.brightness_range) else {
+        return Err(eyre!("--brightness-expr requires --ambient-light-range and --brightness-range"));
+    };
+
+    for range in [Some(day), args.night_brightness_range.as_ref()].into_iter().flatten() {
+        for i in 0..=EXPR_CHECK_SAMPLES {
+            let span = (ambient.end - ambient.start) as u64;
+            let value = ambient.start + (span * i as u64 / EXPR_CHECK_SAMPLES as u64) as u32;
+            let result = eval_brightness_expr(expr, value, ambient, range);
+            let rounded = result.round();
+            if !result.is_finite() || rounded < range.start as f64 || rounded > range.end as f64 {
+                return Err(eyre!(
+                    "--brightness-expr \"{expr}\" gives {result} at ambient {value} with brightness range \
+                    {}..{}, outside the range; clamp it, e.g. with clamp(..., min, max)",
+                    range.start,
+                    range.end,
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of points sampled along each curve for `explain-plot`.
+#[cfg(feature = "plot")]
+const PLOT_SAMPLES: u32 = 256;
+
+/// Builds the `explain-plot` plot from the configured mappings.
+#[cfg(feature = "plot")]
+fn explain_plot(args: &Args, max_brightness: Option<u32>) -> Result<Plot> {
+    let luminance = args.luminance_table.as_ref().map(LuminanceTable::load).transpose()?;
+    let max_brightness = max_brightness.or(args.configured_max_brightness()).unwrap_or(255);
+
+    let ambient_end = match (&args.ambient_levels, &args.ambient_light_range) {
+        (Some(levels), _) => levels.max_threshold(),
+        (None, Some(range)) => range.end,
+        (None, None) => 1000,
+    };
+    let ambient_max = (ambient_end as f32 * 1.25).ceil().max(1.0);
+    let samples = || (0..=PLOT_SAMPLES).map(move |i| (ambient_max * i as f32 / PLOT_SAMPLES as f32) as u32);
+    let curve = |label: &str, color, f: &dyn Fn(u32) -> u32| Series {
+        label: label.to_string(),
+        color,
+        points: samples().map(|a| (a as f32, f(a) as f32)).collect(),
+    };
+
+    let (gray, green, purple, orange) = (
+        RGBColor(0x33, 0x33, 0x33),
+        RGBColor(0, 0x88, 0),
+        RGBColor(0x66, 0, 0x88),
+        RGBColor(0xaa, 0x66, 0),
+    );
+    let mut series = Vec::new();
+    match (&args.detected_brightness, &args.ambient_levels, &args.ambient_light_range, &args.brightness_range) {
+        (Some(level), _, _, _) => {
+            let b = level.resolve(max_brightness);
+            series.push(curve("--detected-brightness", gray, &|_| b));
+        },
+        (None, Some(levels), _, _) => {
+            series.push(curve("--ambient-levels", green, &|a| levels.brightness(levels.select(None, a, 0))));
+        },
+        (None, None, Some(ambient), Some(day)) => {
+            let lum = luminance.as_ref();
+            series.push(curve("--brightness-range (day)", green, &|a| map_ambient(args, lum, a, ambient, day)));
+            if let Some(night) = &args.night_brightness_range {
+                series.push(curve("--night-brightness-range", purple, &|a| map_ambient(args, lum, a, ambient, night)));
+            }
+            if args.proximity_boost_range.is_some() {
+                let boost = args.proximity_boost.resolve(max_brightness);
+                series.push(curve("day, with full --proximity-boost", orange, &|a| {
+                    (map_ambient(args, lum, a, ambient, day) + boost).min(day.end)
+                }));
+            }
+        },
+        _ => warn!("brightness control isn't configured, only proximity thresholds will be plotted"),
+    }
+
+    let (start_label, end_label) = match args.proximity_invert {
+        false => ("clear", "detect"),
+        true => ("detect (inverted)", "clear (inverted)"),
+    };
+    let mut proximity_markers = match args.proximity_range.as_ref().filter(|_| args.proximity_enabled()) {
+        Some(range) => vec![
+            Marker { label: start_label.to_string(), value: range.start as f32 },
+            Marker { label: end_label.to_string(), value: range.end as f32 },
+        ],
+        None => Vec::new(),
+    };
+    let optional = [
+        ("approach", args.proximity_approach),
+        ("rearm", args.rearm_threshold),
+        ("boost start", args.proximity_boost_range.as_ref().map(|r| r.start)),
+        ("boost full", args.proximity_boost_range.as_ref().map(|r| r.end)),
+    ];
+    for (label, value) in optional {
+        if let Some(value) = value {
+            proximity_markers.push(Marker { label: label.to_string(), value: value as f32 });
+        }
+    }
+    let proximity_max = proximity_markers.iter().map(|m| m.value).fold(1.0, f32::max) * 1.25;
+
+    Ok(Plot {
+        ambient_max,
+        brightness_max: max_brightness as f32,
+        series,
+        cap: args.brightness_cap.map(|c| c.resolve(max_brightness) as f32),
+        proximity_max: proximity_max.ceil(),
+        proximity_markers,
+    })
+}
+
+/// Returns the brightness boost for a proximity reading per
+/// `--proximity-boost-range`, or 0 if unset.
+fn proximity_boost(args: &Args, proximity: u32, max_brightness: u32) -> u32 {
+    let Some(range) = &args.proximity_boost_range else {
+        return 0;
+    };
+
+    let max_boost = args.proximity_boost.resolve(max_brightness);
+    map_ambient_to_display_brightness(proximity, range, &(0..max_boost))
+}
+
+/// Linearly maps an ambient light value onto a brightness. Both ranges are
+/// inclusive: ambient values at or below `ambient_light_range.start` map to
+/// `brightness_range.start`, and at or above `ambient_light_range.end` to
+/// `brightness_range.end`. If either range has zero width, the brightness
+/// range's start is used. Ranges must not be inverted (see `validate_args`).
+fn map_ambient_to_display_brightness(
+    ambient: u32,
+    ambient_light_range: &Range<u32>,
+    brightness_range: &Range<u32>
+) -> u32 {
+    let ambient_start = ambient_light_range.start as f32;
+    let brightness_start = brightness_range.start as f32;
+    let ambient_span = (ambient_light_range.end - ambient_light_range.start) as f32;
+    let brightness_span = (brightness_range.end - brightness_range.start) as f32;
+
+    if ambient_span == 0.0 || brightness_span == 0.0 {
+        return brightness_range.start;
+    }
+
+    let mapped = brightness_start + ((ambient as f32 - ambient_start) * brightness_span) / ambient_span;
+
+    (mapped.round() as u32).clamp(brightness_range.start, brightness_range.end)
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    signal::install_handlers();
+
+    let argv: Vec<OsString> = env::args_os().collect();
+    let mut config = Config::from_args::<Args>(&argv)?;
+    let mut args: Args = config.parse_args(&argv, None)?;
+    match args.profile.as_deref() {
+        Some("list") => {
+            println!("{}", profile::describe_all());
+            return Ok(());
+        },
+        Some(name) => {
+            if let Some(profile) = profile::find(name) {
+                config.apply_profile(profile);
+                args = config.parse_args(&argv, None)?;
+            }
+        },
+        None => (),
+    }
+
+    if args.print_config {
+        println!("{}", config.render_effective::<Args>(&argv)?);
+        return Ok(());
+    }
+
+    let _log_guard = install_tracing(&args)?;
+
+    if args.emulated {
+        info!("seeding emulated sensor on {}", args.i2c_device.display());
+        emulate::seed_i2c_stub(&args.i2c_device, emulate::PROXIMITY, emulate::AMBIENT)?;
+    }
+
+    if let Some(Command::Bench { duration, json }) = &args.command {
+        let mut sensor = open_sensor(&args)?;
+        sensor.read_product()?.verify()?;
+
+        let mut result = bench::run(&mut sensor, *duration)?;
+        let command = sensor.read_command_register()?;
+        if !args.emulated && command.self_timed_enabled() {
+            result.conversion = Some(conversion::measure(&mut sensor, command.proximity_enabled())?);
+        }
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&result)?);
+        } else {
+            result.print_table();
+        }
+
+        return Ok(());
+    }
+
+    #[cfg(feature = "tui")]
+    if let Some(Command::Scope) = &args.command {
+        let mut sensor = open_sensor(&args)?;
+        sensor.read_product()?.verify()?;
+        sensor.set_command_register_verified(measurement_command(&args))?;
+        sensor.set_led_current_ma(args.led_current_ma())?;
+        if !args.emulated && !sensor.wait_for_channel_ready(args.measured_channels(), DATA_READY_TIMEOUT)? {
+            warn!("sensor data not ready after {DATA_READY_TIMEOUT:?}, initial readings may be invalid");
+        }
+        if !args.emulated {
+            println!("{}", conversion::measure(&mut sensor, args.proximity_enabled())?.summary());
+        }
+
+        return scope::run(&mut sensor, args.poll_interval, args.proximity_range.as_ref());
+    }
+
+    validate_args(&args)?;
+
+    #[cfg(feature = "plot")]
+    if let Some(Command::ExplainPlot { output, max_brightness }) = &args.command {
+        let svg = explain_plot(&args, *max_brightness)?.render()?;
+        std::fs::write(output, svg).map_err(|e| eyre!("could not write {}: {e}", output.display()))?;
+        info!("wrote {}", output.display());
+
+        return Ok(());
+    }
+
+    let (mut selected_display, display_names) = select_display(&args)?;
+    config.warn_unknown_displays(&display_names);
+    if config.display_section(&selected_display.name).is_some() {
+        info!("applying config overrides for display {}", selected_display.name);
+        args = config.parse_args(&argv, Some(&selected_display.name))?;
+        validate_args(&args)?;
+    }
+    selected_display.power_backend = args.power_backend;
+    if selected_display.power_backend == PowerBackend::Swayipc && !sway::is_available() {
+        warn!("--power-backend swayipc requested but SWAYSOCK is not set, falling back to wlopm");
+        selected_display.power_backend = PowerBackend::Wlopm;
+    }
+    if let Some(path) = &args.wlopm_path {
+        selected_display.wlopm.path = path.clone();
+    }
+    if selected_display.power_backend == PowerBackend::Wlopm && !args.presence_log_only {
+        // otherwise this would only fail on the first power change, which may
+        // be long after startup
+        selected_display.wlopm.validate().map_err(|e| {
+            eyre!("{e}; install wlopm, point --wlopm-path at it, or use --power-backend swayipc")
+        })?;
+    }
+    selected_display.wlopm.extra_args = args.wlopm_extra_arg.clone();
+    selected_display.wlopm.min_interval = args.wlopm_min_interval;
+    selected_display.verify_power = args.verify_power.then_some(args.verify_power_retries);
+    selected_display.brightness_backend = args.brightness_backend;
+    selected_display.brightness_command = args.brightness_cmd.clone();
+    if !selected_display.has_backlight() && args.brightness_backend == BrightnessBackend::Command {
+        // there's no sysfs max_brightness to bound the command's values, so
+        // take it from the configured range instead
+        selected_display.max_brightness = args.configured_max_brightness().unwrap_or(255);
+        info!("brightness command values limited to 0..={}", selected_display.max_brightness);
+    }
+    info!("selected display: {selected_display:?}");
+    if args.presence_log_only {
+        info!("logging presence only, display power won't be changed");
+    }
+    if selected_display.has_backlight() {
+        info!(
+            "using brightness interface {} (0..={})",
+            selected_display.sysfs_path.join("brightness").display(),
+            selected_display.max_brightness,
+        );
+    }
+    check_backlight_supported(&selected_display, &args)?;
+    validate_brightness_range(&selected_display, &args);
+
+    let aux_backlights = args
+        .aux_backlight
+        .iter()
+        .map(|spec| {
+            let aux = AuxBacklight::open(spec.clone())
+                .map_err(|e| eyre!("invalid aux backlight {}: {e}", spec.sysfs_path.display()))?;
+            info!("aux backlight: {} (0..={})", spec.sysfs_path.display(), aux.max_brightness);
+            Ok(aux)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut sensor = open_sensor(&args)?;
+    let product = sensor.read_product()?.verify()?;
+    info!("product: {product:?}");
+    info!("i2c functionality: {:#x}", sensor.functionality().0);
+
+    if !args.emulated && args.proximity_enabled() {
+        check_byte_order(&mut sensor, args.swap_result_bytes);
+    }
+
+    let command = sensor.read_command_register()?;
+    info!("command: {command:?}");
+
+    let command = sensor.set_command_register_verified(measurement_command(&args))?;
+    info!("updated command: {command:?}");
+
+    if !args.proximity_led_current.is_multiple_of(10) {
+        warn!(
+            "--proximity-led-current {}mA is not a multiple of 10mA, and will be rounded down to {}mA",
+            args.proximity_led_current,
+            args.proximity_led_current / 10 * 10,
+        );
+    }
+
+    if !args.proximity_enabled() {
+        info!("proximity disabled, using ambient light only");
+    }
+    ramp_led_current(&mut sensor, args.led_current_ma(), args.proximity_led_ramp)?;
+    let current = sensor.read_led_current()?;
+    info!("current: {current:?} ({}mA)", current.to_milliamps());
+    info!("fuse program id: {}", current.fuse_prog_id());
+    if let Some(expected) = args.expected_fuse_prog_id {
+        if let Err(e) = current.verify_fuse_prog_id(expected) {
+            warn!("{e}; readings may differ from a genuine VCNL4010");
+        }
+    }
+
+    // results read as zero until the first self-timed measurements complete,
+    // which would otherwise briefly drive the state machine and brightness
+    if !args.emulated {
+        if !sensor.wait_for_channel_ready(args.measured_channels(), DATA_READY_TIMEOUT)? {
+            warn!("sensor data not ready after {DATA_READY_TIMEOUT:?}, initial readings may be invalid");
+        }
+        conversion::measure(&mut sensor, args.proximity_enabled())?.log();
+    }
+
+    let stored_baseline = args.baseline_file.as_deref().and_then(StoredBaseline::load);
+    if let (Some(stored), true) = (stored_baseline, args.auto_led) {
+        info!("using stored LED current {}mA", stored.led_current_ma);
+        sensor.set_led_current_ma(stored.led_current_ma)?;
+    }
+
+    let needs_baseline = args.check_baseline
+        || args.auto_led
+        || args.baseline_file.is_some()
+        || args.proximity_auto_zero;
+    let baseline = match stored_baseline {
+        Some(stored) => Some(stored),
+        None if needs_baseline => {
+            let (led_current_ma, proximity) = calibrate::check_baseline(
+                &mut sensor,
+                args.proximity_led_current,
+                args.baseline_duration,
+                args.baseline_max_fraction,
+                args.auto_led,
+            )?;
+
+            let measured = StoredBaseline { proximity, led_current_ma };
+            if let Some(path) = &args.baseline_file {
+                measured.save(path)?;
+            }
+
+            Some(measured)
+        },
+        None => None,
+    };
+    let baseline = baseline.map(|b| BaselineTracker::new(b, args.baseline_file.clone()));
+    let clock = SystemClock;
+    let mut daemon = Daemon::new(&args, &clock, sensor, selected_display, aux_backlights, baseline)?;
+    daemon.run()?;
+
+    info!("shutting down");
+
+    let summary = daemon.summary();
+    summary.log();
+    if let Some(path) = &args.stats_file {
+        summary.save(path)?;
+    }
+
+    Ok(())
+}
+
+/// Installs the tracing subscriber. The returned guard flushes `--log-file`
+/// when dropped, so it must be held until the daemon exits.
+fn install_tracing(args: &Args) -> Result<Option<WorkerGuard>> {
+    use tracing_appender::rolling::RollingFileAppender;
     use tracing_error::ErrorLayer;
     use tracing_subscriber::prelude::*;
     use tracing_subscriber::{fmt, EnvFilter};
 
-    let fmt_layer = fmt::layer().with_target(true).with_writer(std::io::stderr);
+    let compact = args.log_format == LogFormat::Compact;
+    let fmt_layer = (!args.no_stderr_log).then(|| {
+        let layer = fmt::layer().with_writer(std::io::stderr);
+        match compact {
+            true => layer.without_time().with_target(false).with_ansi(false).boxed(),
+            false => layer.with_target(true).boxed(),
+        }
+    });
 
+    let (file_layer, guard) = match &args.log_file {
+        Some(path) => {
+            let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| eyre!("invalid --log-file {}", path.display()))?;
+            let file = RollingFileAppender::builder()
+                .rotation(args.log_rotation.rotation())
+                .filename_prefix(name)
+                .build(dir)?;
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            (Some(fmt::layer().with_target(true).with_ansi(false).with_writer(writer)), Some(guard))
+        },
+        None => (None, None),
+    };
+
+    // in compact mode, only status lines are shown at info by default
+    let default_filter = match compact {
+        true => format!("warn,{STATUS_TARGET}=info"),
+        false => "info".to_string(),
+    };
     let filter_layer = EnvFilter::try_from_default_env()
-        .or_else(|_| EnvFilter::try_new("info"))
+        .or_else(|_| EnvFilter::try_new(default_filter))
         .unwrap();
 
     tracing_subscriber::registry()
         .with(filter_layer)
         .with(fmt_layer)
+        .with(file_layer)
         .with(ErrorLayer::default())
         .init();
+
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+    use vcnl4010::{mock::MockDevice, I2CFunctionality};
+    use wake::AmbientWake;
+
+    fn args(extra: &[&str]) -> Args {
+        let base = ["pi-proximity-display", "--proximity-range", "100..200", "--proximity-hold", "10s"];
+        Args::try_parse_from(base.iter().chain(extra)).unwrap()
+    }
+
+    #[test]
+    fn hold_clears_exactly_after_boundary() {
+        let args = args(&[]);
+        let clock = MockClock::new();
+        let state = State::ClearedTransitioning(clock.now());
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(state.update(&args, 50, false, &clock, None), None);
+
+        clock.advance(Duration::from_nanos(1));
+        assert_eq!(state.update(&args, 50, false, &clock, None), Some(State::Cleared));
+    }
+
+    #[test]
+    fn rejects_zero_width_proximity_range() {
+        let args = Args::try_parse_from(["pi-proximity-display", "--proximity-range", "500..500"]).unwrap();
+        let err = validate_args(&args).unwrap_err().to_string();
+        assert!(err.contains("no hysteresis band"), "{err}");
+        assert!(err.contains("475..525"), "{err}");
+
+        let args = Args::try_parse_from(["pi-proximity-display", "--proximity-range", "499..500"]).unwrap();
+        assert!(validate_args(&args).is_ok());
+    }
+
+    #[test]
+    fn detects_byte_swapped_samples() {
+        let samples = [2000u16, 2003, 2001, 2006];
+        assert_eq!(byte_swap_spreads(&samples), None);
+
+        let swapped: Vec<u16> = samples.iter().map(|v| v.swap_bytes()).collect();
+        assert_eq!(byte_swap_spreads(&swapped), Some((1536, 6)));
+
+        // a noisy but correctly ordered scene isn't flagged
+        assert_eq!(byte_swap_spreads(&[100, 900, 400]), None);
+    }
+
+    #[test]
+    fn byte_order_check_flags_swapped_sensors() {
+        let samples = [2000u16, 2003, 2001, 2006, 2002, 2004, 2000, 2005];
+        let functionality = I2CFunctionality(
+            I2CFunctionality::SMBUS_READ_BYTE_DATA | I2CFunctionality::SMBUS_WRITE_BYTE_DATA,
+        );
+
+        let device = MockDevice::new();
+        let mut sensor = ProximitySensor::from_device(device.clone(), functionality);
+        device.queue_on_demand(samples);
+        assert!(!check_byte_order(&mut sensor, false));
+
+        // a clone that stores the low byte first
+        device.queue_on_demand(samples.map(u16::swap_bytes));
+        assert!(check_byte_order(&mut sensor, false));
+
+        // which --swap-result-bytes corrects
+        sensor.set_swap_result_bytes(true);
+        device.queue_on_demand(samples.map(u16::swap_bytes));
+        assert!(!check_byte_order(&mut sensor, true));
+    }
+
+    #[test]
+    fn panel_gamma_keeps_the_ends_fixed() {
+        for gamma in [0.5, 1.0, 2.2] {
+            assert_eq!(apply_panel_gamma(0, 255, gamma), 0, "gamma {gamma}");
+            assert_eq!(apply_panel_gamma(255, 255, gamma), 255, "gamma {gamma}");
+        }
+
+        assert_eq!(apply_panel_gamma(128, 255, 1.0), 128);
+        assert_eq!(apply_panel_gamma(128, 255, 2.0), 64);
+        assert_eq!(apply_panel_gamma(64, 256, 0.5), 128);
+        assert_eq!(apply_panel_gamma(7, 0, 2.2), 7);
+    }
+
+    #[test]
+    fn proximity_boost_scales_with_closeness() {
+        assert_eq!(proximity_boost(&args(&[]), 1000, 255), 0);
+
+        let args = args(&["--proximity-boost-range", "200..1000", "--proximity-boost", "40"]);
+        assert_eq!(proximity_boost(&args, 100, 255), 0);
+        assert_eq!(proximity_boost(&args, 200, 255), 0);
+        assert_eq!(proximity_boost(&args, 600, 255), 20);
+        assert_eq!(proximity_boost(&args, 1000, 255), 40);
+        assert_eq!(proximity_boost(&args, 5000, 255), 40);
+    }
+
+    #[test]
+    fn drops_sentinel_proximity_readings() {
+        let default = args(&[]);
+        assert!(!is_invalid_proximity(&default, u16::MAX));
+        assert!(!is_invalid_proximity(&default, 0));
+
+        let args = args(&["--reject-saturated-proximity", "--reject-zero-proximity"]);
+        assert!(is_invalid_proximity(&args, u16::MAX));
+        assert!(is_invalid_proximity(&args, 0));
+        assert!(!is_invalid_proximity(&args, 1));
+        assert!(!is_invalid_proximity(&args, u16::MAX - 1));
+    }
+
+    #[test]
+    fn maps_ambient_at_range_ends() {
+        let ambient = 100..500;
+        let brightness = 10..90;
+        let map = |a| map_ambient_to_display_brightness(a, &ambient, &brightness);
+
+        assert_eq!(map(0), 10);
+        assert_eq!(map(99), 10);
+        assert_eq!(map(100), 10);
+        assert_eq!(map(101), 10);
+        assert_eq!(map(300), 50);
+        assert_eq!(map(499), 90);
+        assert_eq!(map(500), 90);
+        assert_eq!(map(501), 90);
+        assert_eq!(map(u32::MAX), 90);
+    }
+
+    #[test]
+    fn approach_powers_on_early() {
+        let args = args(&["--proximity-approach", "150"]);
+        let clock = MockClock::new();
+
+        assert_eq!(State::Cleared.update(&args, 149, false, &clock, None), None);
+        assert_eq!(State::Cleared.update(&args, 150, false, &clock, None), Some(State::Approaching));
+        assert_eq!(State::Approaching.update(&args, 170, false, &clock, None), None);
+        assert_eq!(State::Approaching.update(&args, 200, false, &clock, None), Some(State::Detected));
+        assert_eq!(
+            State::Approaching.update(&args, 120, false, &clock, None),
+            Some(State::ClearedTransitioning(clock.now()))
+        );
+        assert_eq!(
+            State::ClearedTransitioning(clock.now()).update(&args, 160, false, &clock, None),
+            Some(State::Approaching)
+        );
+    }
+
+    #[test]
+    fn rejects_approach_outside_range() {
+        let err = validate_args(&args(&["--proximity-approach", "200"])).unwrap_err();
+        assert!(err.to_string().contains("must be below the end"), "{err}");
+    }
+
+    #[test]
+    fn rearm_cooldown_needs_a_strong_reading() {
+        let args = args(&["--rearm-cooldown", "5s", "--rearm-threshold", "400"]);
+        let clock = MockClock::new();
+        let cleared_at = Some(clock.now());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(State::Cleared.update(&args, 250, false, &clock, cleared_at), None);
+        assert_eq!(State::Cleared.update(&args, 400, false, &clock, cleared_at), Some(State::Detected));
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(State::Cleared.update(&args, 250, false, &clock, cleared_at), Some(State::Detected));
+    }
+
+    #[test]
+    fn detected_brightness_dims_during_hold() {
+        let holding = State::ClearedTransitioning(Instant::now());
+
+        let with_hold = args(&["--detected-brightness", "80%", "--hold-brightness", "20"]);
+        assert_eq!(detected_brightness(&with_hold, &State::Detected, 255), Some(204));
+        assert_eq!(detected_brightness(&with_hold, &holding, 255), Some(20));
+
+        let without_hold = args(&["--detected-brightness", "200"]);
+        assert_eq!(detected_brightness(&without_hold, &holding, 255), Some(200));
+        assert_eq!(detected_brightness(&without_hold, &State::Detected, 100), Some(100));
+    }
+
+    #[test]
+    fn brightness_writes_are_rate_limited() {
+        let clock = MockClock::new();
+        let interval = Duration::from_millis(500);
+        assert!(brightness_write_due(None, interval, &clock));
+
+        let last = Some(clock.now());
+        clock.advance(Duration::from_millis(499));
+        assert!(!brightness_write_due(last, interval, &clock));
+        clock.advance(Duration::from_millis(1));
+        assert!(brightness_write_due(last, interval, &clock));
+
+        assert!(brightness_write_due(Some(clock.now()), Duration::ZERO, &clock));
+    }
+
+    #[test]
+    fn maps_zero_width_ranges_to_start() {
+        assert_eq!(map_ambient_to_display_brightness(0, &(200..200), &(10..90)), 10);
+        assert_eq!(map_ambient_to_display_brightness(200, &(200..200), &(10..90)), 10);
+        assert_eq!(map_ambient_to_display_brightness(1000, &(200..200), &(10..90)), 10);
+        assert_eq!(map_ambient_to_display_brightness(300, &(0..1000), &(40..40)), 40);
+    }
+
+    #[test]
+    fn rejects_inverted_ranges() {
+        for flag in ["--ambient-light-range", "--brightness-range", "--night-brightness-range"] {
+            let err = validate_args(&args(&[flag, "500..100"])).unwrap_err();
+            assert!(err.to_string().contains("is inverted"), "{flag}: {err}");
+        }
+
+        assert!(validate_args(&args(&["--ambient-light-range", "100..100", "--brightness-range", "0..255"])).is_ok());
+    }
+
+    #[test]
+    fn brightness_while_off() {
+        assert!(BrightnessWhileOff::Skip.pauses(true));
+        assert!(!BrightnessWhileOff::Skip.pauses(false));
+        assert!(!BrightnessWhileOff::Keep.pauses(true));
+        assert!(!BrightnessWhileOff::Zero.pauses(true));
+
+        assert_eq!(BrightnessWhileOff::Skip.apply(true, Some(80)), None);
+        assert_eq!(BrightnessWhileOff::Zero.apply(true, Some(80)), Some(0));
+        assert_eq!(BrightnessWhileOff::Keep.apply(true, Some(80)), Some(80));
+        for mode in [BrightnessWhileOff::Skip, BrightnessWhileOff::Zero, BrightnessWhileOff::Keep] {
+            assert_eq!(mode.apply(false, Some(80)), Some(80), "{mode:?} while on");
+        }
+    }
+
+    #[test]
+    fn ambient_is_read_at_its_interval() {
+        let clock = MockClock::new();
+        let poll = Duration::from_millis(250);
+        let interval = Duration::from_secs(1);
+
+        let mut last_read = None;
+        let mut reads = vec![];
+        for tick in 0..16 {
+            if ambient_read_due(last_read, interval, &clock) {
+                last_read = Some(clock.now());
+                reads.push(tick);
+            }
+            clock.advance(poll);
+        }
+        assert_eq!(reads, [0, 4, 8, 12]);
+
+        // a zero interval reads every iteration
+        assert!(ambient_read_due(Some(clock.now()), Duration::ZERO, &clock));
+    }
+
+    /// A starting state, proximity, time elapsed, extra args, and the
+    /// expected next state.
+    type TransitionCase = (&'static str, u32, Duration, &'static [&'static str], Option<&'static str>);
+
+    /// One case per row of the table on `State::update`, in order.
+    #[test]
+    fn update_follows_transition_table() {
+        let hold = Duration::from_secs(10);
+        let cases: &[TransitionCase] = &[
+            ("detected", 100, Duration::ZERO, &[], Some("cleared_transitioning")),
+            ("detected", 101, Duration::ZERO, &[], None),
+            ("cleared_transitioning", 200, Duration::ZERO, &[], Some("detected")),
+            ("cleared_transitioning", 199, hold + Duration::from_millis(1), &[], Some("cleared")),
+            ("cleared_transitioning", 199, hold, &[], None),
+            ("cleared", 200, Duration::ZERO, &[], Some("detected")),
+            ("cleared", 200, Duration::ZERO, &["--rearm-cooldown", "5s"], None),
+            ("cleared", 199, Duration::ZERO, &[], None),
+        ];
+
+        for (i, (from, proximity, elapsed, extra, expected)) in cases.iter().enumerate() {
+            let args = args(extra);
+            let clock = MockClock::new();
+            let state = match *from {
+                "detected" => State::Detected,
+                "cleared" => State::Cleared,
+                _ => State::ClearedTransitioning(clock.now()),
+            };
+            let cleared_at = (state == State::Cleared).then(|| clock.now());
+            clock.advance(*elapsed);
+
+            let next = state.update(&args, *proximity, false, &clock, cleared_at);
+            assert_eq!(next.as_ref().map(State::name), *expected, "case {i}: {from} at {proximity}");
+            if let Some(State::ClearedTransitioning(start)) = next {
+                assert_eq!(start, clock.now(), "case {i}: the hold starts now");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_brightness_control_with_zero_max() {
+        let mut display = Display::without_backlight("dummy");
+        display.sysfs_path = PathBuf::from("/sys/class/backlight/dummy");
+        let brightness = args(&["--brightness-range", "0..255"]);
+        assert!(check_backlight_supported(&display, &brightness).is_err());
+        assert!(check_backlight_supported(&display, &args(&[])).is_ok());
+
+        let command = args(&[
+            "--brightness-range", "0..255", "--brightness-backend", "command", "--brightness-cmd", "true",
+        ]);
+        assert!(check_backlight_supported(&display, &command).is_ok());
+
+        display.max_brightness = 255;
+        assert!(check_backlight_supported(&display, &brightness).is_ok());
+        assert!(check_backlight_supported(&Display::without_backlight("dummy"), &brightness).is_ok());
+    }
+
+    #[test]
+    fn night_fixed_brightness_applies_at_night() {
+        let at = |s| TimeOfDay::from_str(s).unwrap();
+        let night = args(&["--night-fixed-brightness", "10%", "--sunrise", "07:00", "--sunset", "22:00"]);
+        assert!(night.night_fixed_active_at(at("23:00")));
+        assert!(night.night_fixed_active_at(at("03:00")));
+        assert!(!night.night_fixed_active_at(at("12:00")));
+        assert!(!args(&[]).night_fixed_active_at(at("03:00")));
+
+        assert_eq!(night_fixed_brightness(&night, 0, 200), Some(20));
+        assert_eq!(night_fixed_brightness(&args(&[]), 0, 200), None);
+
+        let boosted = args(&[
+            "--night-fixed-brightness", "10%", "--proximity-boost-range", "200..1000", "--proximity-boost", "190",
+        ]);
+        assert_eq!(night_fixed_brightness(&boosted, 600, 200), Some(115));
+        assert_eq!(night_fixed_brightness(&boosted, 1000, 200), Some(200));
+    }
+
+    #[test]
+    fn hold_extend_restarts_the_hold() {
+        let extend = args(&["--hold-extend"]);
+        let clock = MockClock::new();
+        let mut state = State::ClearedTransitioning(clock.now());
+
+        clock.advance(Duration::from_secs(8));
+        assert!(state.extend_hold(&extend, 150, &clock));
+        assert_eq!(state, State::ClearedTransitioning(clock.now()));
+
+        // 16s since clearing, but only 8s since the restart
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(state.update(&extend, 150, false, &clock, None), None);
+
+        // at the clearing end, the hold keeps running
+        assert!(!state.extend_hold(&extend, 100, &clock));
+        clock.advance(Duration::from_secs(2) + Duration::from_millis(1));
+        assert_eq!(state.update(&extend, 100, false, &clock, None), Some(State::Cleared));
+
+        let mut held = State::ClearedTransitioning(clock.now());
+        assert!(!held.extend_hold(&args(&[]), 150, &clock));
+        assert!(!State::Detected.extend_hold(&extend, 150, &clock));
+    }
+
+    #[test]
+    fn ambient_wake_detects_then_holds() {
+        let args = args(&["--ambient-wake-delta", "100"]);
+        let clock = MockClock::new();
+        let mut wake = AmbientWake::new(100, args.ambient_wake_window);
+        assert!(!wake.observe(10, &clock));
+        assert_eq!(State::Cleared.update(&args, 50, false, &clock, None), None);
+
+        let woke = wake.observe(500, &clock);
+        assert!(woke);
+        let state = State::Cleared.update(&args, 50, woke, &clock, None).unwrap();
+        assert_eq!(state, State::Detected);
+
+        // with nothing in range, the usual hold follows
+        let state = state.update(&args, 50, wake.observe(500, &clock), &clock, None).unwrap();
+        assert_eq!(state.name(), "cleared_transitioning");
+        clock.advance(args.proximity_hold + Duration::from_millis(1));
+        let state = state.update(&args, 50, wake.observe(500, &clock), &clock, None).unwrap();
+        assert_eq!(state, State::Cleared);
+    }
+
+    #[test]
+    fn ambient_only_disables_proximity() {
+        let ambient_only = args(&["--ambient-only"]);
+        assert_eq!(ambient_only.led_current_ma(), 0);
+        assert!(!ambient_only.proximity_enabled());
+        assert_eq!(ambient_only.measured_channels(), Channel::Ambient);
+
+        let command = measurement_command(&ambient_only);
+        assert!(!command.proximity_enabled());
+        assert!(command.ambient_light_enabled());
+        assert!(command.self_timed_enabled());
+
+        // a zero LED current does the same without the flag
+        assert!(!measurement_command(&args(&["--proximity-led-current", "0"])).proximity_enabled());
+        assert!(measurement_command(&args(&[])).proximity_enabled());
+    }
+
+    #[test]
+    fn inverted_proximity_swaps_range_ends() {
+        let inverted = args(&["--proximity-invert"]);
+        assert!(inverted.proximity_detected(100));
+        assert!(inverted.proximity_detected(0));
+        assert!(!inverted.proximity_detected(101));
+        assert!(!inverted.proximity_detected(200));
+        assert!(inverted.proximity_cleared(200));
+        assert!(inverted.proximity_cleared(300));
+        assert!(!inverted.proximity_cleared(199));
+
+        // between the range ends, neither state changes
+        let clock = MockClock::new();
+        assert_eq!(State::Detected.update(&inverted, 150, false, &clock, None), None);
+        assert_eq!(State::Cleared.update(&inverted, 150, false, &clock, None), None);
+        let cleared = State::Detected.update(&inverted, 200, false, &clock, None);
+        assert_eq!(cleared.as_ref().map(State::name), Some("cleared_transitioning"));
+        let detected = State::Cleared.update(&inverted, 100, false, &clock, None);
+        assert_eq!(detected.as_ref().map(State::name), Some("detected"));
+    }
 }
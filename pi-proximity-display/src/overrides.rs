@@ -0,0 +1,20 @@
+use serde_derive::Serialize;
+
+use crate::display::DisplayPowerMode;
+
+/// Runtime overrides set through `--control-addr` or D-Bus. `None` means
+/// automatic control.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Overrides {
+    #[serde(serialize_with = "serialize_power")]
+    pub power: Option<DisplayPowerMode>,
+    pub brightness: Option<u32>,
+}
+
+fn serialize_power<S: serde::Serializer>(power: &Option<DisplayPowerMode>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(match power {
+        Some(DisplayPowerMode::On) => "on",
+        Some(DisplayPowerMode::Off) => "off",
+        None => "auto",
+    })
+}
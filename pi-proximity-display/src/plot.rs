@@ -0,0 +1,129 @@
+//! Rendering the configured mappings as an SVG with `plotters`, for
+//! `explain-plot`.
+//!
+//! The plot has two panels: the ambient light to brightness curves on top,
+//! with any brightness cap drawn as a dashed line, and the proximity
+//! thresholds as markers along a strip below.
+
+use color_eyre::{eyre::eyre, Result};
+use plotters::{coord::Shift, prelude::*};
+
+const WIDTH: u32 = 640;
+const CURVE_HEIGHT: u32 = 400;
+const STRIP_HEIGHT: u32 = 140;
+
+const CAP_COLOR: RGBColor = RGBColor(0xcc, 0, 0);
+const MARKER_COLOR: RGBColor = RGBColor(0, 0x66, 0xcc);
+
+/// A curve to plot, as (x, y) points in data units.
+pub struct Series {
+    pub label: String,
+    pub color: RGBColor,
+    pub points: Vec<(f32, f32)>,
+}
+
+/// A labeled value marked on an axis.
+pub struct Marker {
+    pub label: String,
+    pub value: f32,
+}
+
+pub struct Plot {
+    /// Upper bound of the ambient axis; both axes start at 0.
+    pub ambient_max: f32,
+
+    /// Upper bound of the brightness axis.
+    pub brightness_max: f32,
+
+    pub series: Vec<Series>,
+
+    /// A brightness ceiling, drawn across the curve panel.
+    pub cap: Option<f32>,
+
+    /// Upper bound of the proximity strip.
+    pub proximity_max: f32,
+    pub proximity_markers: Vec<Marker>,
+}
+
+impl Plot {
+    pub fn render(&self) -> Result<String> {
+        let mut svg = String::new();
+        self.draw(SVGBackend::with_string(&mut svg, (WIDTH, CURVE_HEIGHT + STRIP_HEIGHT)).into_drawing_area())
+            .map_err(|e| eyre!("could not render plot: {e}"))?;
+
+        Ok(svg)
+    }
+
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root: DrawingArea<DB, Shift>,
+    ) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+        root.fill(&WHITE)?;
+        let (curves, strip) = root.split_vertically(CURVE_HEIGHT);
+
+        let mut chart = ChartBuilder::on(&curves)
+            .margin(16)
+            .x_label_area_size(36)
+            .y_label_area_size(48)
+            .build_cartesian_2d(0.0..self.ambient_max.max(1.0), 0.0..self.brightness_max.max(1.0))?;
+        chart
+            .configure_mesh()
+            .x_desc("ambient light (counts)")
+            .y_desc("brightness")
+            .x_label_formatter(&|v| format!("{v:.0}"))
+            .y_label_formatter(&|v| format!("{v:.0}"))
+            .draw()?;
+
+        if let Some(cap) = self.cap {
+            chart
+                .draw_series(DashedLineSeries::new(
+                    [(0.0, cap), (self.ambient_max, cap)],
+                    4,
+                    3,
+                    CAP_COLOR.into(),
+                ))?
+                .label(format!("cap {cap}"))
+                .legend(|(x, y)| PathElement::new([(x, y), (x + 16, y)], CAP_COLOR));
+        }
+        for series in &self.series {
+            let color = series.color;
+            chart
+                .draw_series(LineSeries::new(series.points.iter().copied(), color.stroke_width(2)))?
+                .label(&series.label)
+                .legend(move |(x, y)| PathElement::new([(x, y), (x + 16, y)], color.stroke_width(2)));
+        }
+        if self.cap.is_some() || !self.series.is_empty() {
+            chart
+                .configure_series_labels()
+                .position(SeriesLabelPosition::UpperLeft)
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()?;
+        }
+
+        let mut chart = ChartBuilder::on(&strip)
+            .margin(16)
+            .x_label_area_size(36)
+            .y_label_area_size(48)
+            .build_cartesian_2d(0.0..self.proximity_max.max(1.0), 0.0..1.0f32)?;
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .disable_y_axis()
+            .x_desc("proximity (counts)")
+            .x_label_formatter(&|v| format!("{v:.0}"))
+            .draw()?;
+
+        // labels are staggered so nearby thresholds stay readable
+        for (i, marker) in self.proximity_markers.iter().enumerate() {
+            chart.draw_series(LineSeries::new([(marker.value, 0.0), (marker.value, 1.0)], MARKER_COLOR))?;
+            chart.draw_series([Text::new(
+                format!(" {} ({})", marker.label, marker.value),
+                (marker.value, 1.0 - 0.18 * i as f32),
+                ("sans-serif", 12).into_font().color(&MARKER_COLOR),
+            )])?;
+        }
+
+        root.present()
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, info};
+
+use crate::State;
+
+/// Determines how long to sleep between polls, backing off to slower intervals
+/// while the display has been idle for a while.
+#[derive(Debug)]
+pub struct PollScheduler {
+    /// The normal, fast poll interval.
+    base: Duration,
+
+    /// If set, how long the state must be `Cleared` before backing off.
+    idle_after: Option<Duration>,
+
+    /// Maximum interval to back off to.
+    max: Duration,
+
+    /// Factor by which the interval grows each idle poll.
+    factor: f32,
+
+    /// Any proximity reading at or above this resets to the base interval.
+    wake_threshold: u32,
+
+    current: Duration,
+    cleared_since: Option<Instant>,
+}
+
+impl PollScheduler {
+    pub fn new(
+        base: Duration,
+        idle_after: Option<Duration>,
+        max: Duration,
+        factor: f32,
+        wake_threshold: u32,
+    ) -> PollScheduler {
+        PollScheduler {
+            base,
+            idle_after,
+            max: max.max(base),
+            factor: factor.max(1.0),
+            wake_threshold,
+            current: base,
+            cleared_since: None,
+        }
+    }
+
+    /// Returns the interval to sleep before the next poll, given the current
+    /// state and latest proximity reading.
+    pub fn next_interval(&mut self, state: &State, proximity: u32) -> Duration {
+        let Some(idle_after) = self.idle_after else {
+            return self.base;
+        };
+
+        if *state != State::Cleared || proximity >= self.wake_threshold {
+            if self.current != self.base {
+                info!("activity detected (proximity: {proximity}), resuming fast polling");
+            }
+
+            self.cleared_since = None;
+            self.current = self.base;
+            return self.current;
+        }
+
+        let cleared_since = *self.cleared_since.get_or_insert_with(Instant::now);
+        if cleared_since.elapsed() < idle_after {
+            return self.current;
+        }
+
+        let next = self.current.mul_f32(self.factor).min(self.max);
+        if next != self.current {
+            if self.current == self.base {
+                info!("idle for {}, backing off polling", humantime::format_duration(idle_after));
+            }
+
+            debug!("poll interval now {}", humantime::format_duration(next));
+            self.current = next;
+        }
+
+        self.current
+    }
+}
+
+/// Returns how long to sleep to land on the next wall-clock multiple of
+/// `interval`, e.g. every second on the second. Time spent processing since
+/// the last boundary is absorbed, and if a poll overran its slot the sleep
+/// simply targets the next boundary rather than catching up.
+pub fn until_aligned(interval: Duration) -> Duration {
+    let interval_ns = interval.as_nanos();
+    if interval_ns == 0 {
+        return Duration::ZERO;
+    }
+
+    let now_ns = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let remaining = interval_ns - now_ns % interval_ns;
+
+    Duration::from_nanos(remaining.min(u64::MAX as u128) as u64)
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::localtime::LocalTime;
+use crate::State;
+
+/// Tracks how long someone was present for each display session, i.e. between
+/// the `Detected` transition and the eventual `Cleared` transition.
+#[derive(Debug)]
+pub struct PresenceTracker {
+    /// If set, the hold window is included in each session's dwell time.
+    include_hold: bool,
+
+    session_start: Option<Instant>,
+    hold_start: Option<Instant>,
+
+    daily_total: Duration,
+    day: (i32, i32),
+}
+
+impl PresenceTracker {
+    pub fn new(include_hold: bool) -> PresenceTracker {
+        PresenceTracker {
+            include_hold,
+            session_start: None,
+            hold_start: None,
+            daily_total: Duration::ZERO,
+            day: LocalTime::now().day_key(),
+        }
+    }
+
+    /// Updates the tracker with a state transition, returning the length of
+    /// the session it ended, if any. This always includes the hold window;
+    /// `include_hold` only affects the dwell time and daily total.
+    pub fn observe(&mut self, state: &State) -> Option<Duration> {
+        self.tick();
+
+        let now = Instant::now();
+        match state {
+            State::Detected => {
+                self.session_start.get_or_insert(now);
+                self.hold_start = None;
+            },
+            State::ClearedTransitioning(_) => {
+                self.hold_start.get_or_insert(now);
+            },
+            // not a session until detected
+            State::Approaching => (),
+            State::Cleared => {
+                let start = self.session_start.take()?;
+                let hold_start = self.hold_start.take().unwrap_or(now);
+                let end = if self.include_hold { now } else { hold_start };
+
+                let dwell = end.saturating_duration_since(start);
+                self.daily_total += dwell;
+
+                info!(
+                    dwell_secs = dwell.as_secs_f32(),
+                    daily_total_secs = self.daily_total.as_secs_f32(),
+                    "presence session ended after {}",
+                    humantime::format_duration(round_secs(dwell)),
+                );
+
+                return Some(now.saturating_duration_since(start));
+            },
+        }
+
+        None
+    }
+
+    /// Resets the daily total if the local date has changed. Should be called
+    /// periodically so the total resets at midnight.
+    pub fn tick(&mut self) {
+        let day = LocalTime::now().day_key();
+        if day != self.day {
+            info!(
+                daily_total_secs = self.daily_total.as_secs_f32(),
+                "daily presence total: {}",
+                humantime::format_duration(round_secs(self.daily_total)),
+            );
+
+            self.day = day;
+            self.daily_total = Duration::ZERO;
+        }
+    }
+}
+
+fn round_secs(d: Duration) -> Duration {
+    Duration::from_secs(d.as_secs())
+}
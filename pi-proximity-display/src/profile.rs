@@ -0,0 +1,103 @@
+//! Built-in profiles providing starting points for common installations.
+//!
+//! Profiles set defaults for other options, and are applied beneath both the
+//! config file and the command line so any setting may still be overridden.
+//! All profiles enable `--proximity-auto-zero` so their proximity ranges are
+//! relative to the measured baseline, which varies considerably with the
+//! enclosure and LED current; the baseline is measured at startup, so nothing
+//! should be in front of the sensor then. Pair them with `--baseline-file` to
+//! avoid remeasuring on every start.
+//!
+//! Proximity ranges assume the Adafruit VCNL4010 breakout behind a clear
+//! window, and a typical person (rather than e.g. a hand) as the target. The
+//! ambient light range covers typical indoor lighting, and only takes effect
+//! along with `--brightness-range`, which depends on the display.
+
+use std::fmt::Write;
+
+pub struct Profile {
+    pub name: &'static str,
+
+    /// A description of the installation the profile assumes.
+    pub summary: &'static str,
+
+    /// Settings keyed by long flag name, with values as given on the command
+    /// line. Boolean flags are enabled with `true`.
+    pub settings: &'static [(&'static str, &'static str)],
+}
+
+pub const PROFILES: &[Profile] = &[
+    Profile {
+        name: "desk-monitor",
+        summary: "A display on a desk, with the user seated roughly 40-70cm away \
+            and mostly still. A long hold avoids turning off while the user reads.",
+        settings: &[
+            ("proximity-auto-zero", "true"),
+            ("proximity-range", "15..40"),
+            ("proximity-hold", "2m"),
+            ("proximity-led-current", "200"),
+            ("poll-interval", "250ms"),
+            ("ambient-light-range", "0..2000"),
+        ],
+    },
+    Profile {
+        name: "doorway",
+        summary: "A wall-mounted display near a doorway or hallway, woken by people \
+            passing 1m or more away. Low thresholds and fast polling catch brief, \
+            distant movement, at the cost of more false wakes.",
+        settings: &[
+            ("proximity-auto-zero", "true"),
+            ("proximity-range", "8..20"),
+            ("proximity-hold", "60s"),
+            ("proximity-led-current", "200"),
+            ("poll-interval", "100ms"),
+            ("ambient-light-range", "0..2000"),
+        ],
+    },
+    Profile {
+        name: "kiosk",
+        summary: "A touch screen or dashboard that users approach to within roughly \
+            30cm to interact with. Higher thresholds and a reduced LED current ignore \
+            passers-by and avoid saturating at close range.",
+        settings: &[
+            ("proximity-auto-zero", "true"),
+            ("proximity-range", "40..120"),
+            ("proximity-hold", "30s"),
+            ("proximity-led-current", "120"),
+            ("poll-interval", "250ms"),
+            ("ambient-light-range", "0..2000"),
+        ],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Profile> {
+    PROFILES.iter().find(|p| p.name == name)
+}
+
+/// Parses a `--profile` value, which must be a known profile or `list`.
+pub fn parse_profile_name(s: &str) -> Result<String, String> {
+    if s == "list" || find(s).is_some() {
+        return Ok(s.to_string());
+    }
+
+    let names: Vec<&str> = PROFILES.iter().map(|p| p.name).collect();
+    Err(format!("unknown profile '{s}', expected one of: list, {}", names.join(", ")))
+}
+
+/// Describes all profiles and their settings, for `--profile list`.
+pub fn describe_all() -> String {
+    let mut out = String::new();
+    for profile in PROFILES {
+        writeln!(out, "{}:\n  {}", profile.name, profile.summary).ok();
+        for (key, value) in profile.settings {
+            match *value {
+                "true" => writeln!(out, "    --{key}"),
+                value => writeln!(out, "    --{key} {value}"),
+            }
+            .ok();
+        }
+        out.push('\n');
+    }
+
+    out.trim_end().to_string()
+}
@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_derive::Serialize;
+
+use crate::{clock::Clock, stats::Counters};
+
+/// A snapshot of sensor readings and daemon state for one loop iteration, as
+/// published to external consumers.
+///
+/// Readings carry two timestamps, both taken right after the sensor read
+/// rather than when the reading is published: `monotonic_ms` never jumps and
+/// should be used for intervals between readings, while `timestamp_ms` is the
+/// wall clock, for correlating with external events, but may jump if the
+/// system clock is adjusted (e.g. by NTP). With `--ambient-interval`, the
+/// ambient value may come from an earlier read.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reading {
+    /// Milliseconds since the daemon started, on the monotonic clock.
+    pub monotonic_ms: f64,
+
+    /// Milliseconds since the Unix epoch, on the wall clock.
+    pub timestamp_ms: u64,
+
+    pub proximity: u32,
+
+    /// Proximity scaled to 0-100 between the baseline and the detection
+    /// threshold; see `presence_strength`.
+    pub presence: u8,
+    pub ambient: u32,
+    pub lux: f32,
+    pub state: &'static str,
+    pub brightness: Option<u32>,
+    pub counters: Counters,
+}
+
+/// Decides which readings are worth publishing, so consumers only see
+/// meaningful changes rather than every poll. A reading is published if the
+/// state or brightness changed, if proximity or ambient light moved by at
+/// least their threshold since the last published reading, or if `heartbeat`
+/// has elapsed since then. Thresholds of 0 publish every reading.
+#[derive(Debug)]
+pub struct ChangeFilter {
+    proximity_delta: u32,
+    ambient_delta: u32,
+    heartbeat: Duration,
+    last: Option<(Reading, Instant)>,
+}
+
+impl ChangeFilter {
+    pub fn new(proximity_delta: u32, ambient_delta: u32, heartbeat: Duration) -> ChangeFilter {
+        ChangeFilter { proximity_delta, ambient_delta, heartbeat, last: None }
+    }
+
+    /// Returns whether `reading` should be published, remembering it if so.
+    pub fn changed(&mut self, reading: &Reading, clock: &impl Clock) -> bool {
+        let changed = match &self.last {
+            None => true,
+            Some((last, at)) => {
+                last.state != reading.state
+                    || last.brightness != reading.brightness
+                    || last.proximity.abs_diff(reading.proximity) >= self.proximity_delta
+                    || last.ambient.abs_diff(reading.ambient) >= self.ambient_delta
+                    || clock.since(*at) >= self.heartbeat
+            },
+        };
+        if changed {
+            self.last = Some((reading.clone(), clock.now()));
+        }
+
+        changed
+    }
+}
+
+/// Converts a sample time on each clock to the `Reading` timestamps, with
+/// `monotonic` relative to `started`. Returns (`monotonic_ms`, `timestamp_ms`).
+pub fn timestamps(started: Instant, monotonic: Instant, wall: SystemTime) -> (f64, u64) {
+    let monotonic_ms = monotonic.saturating_duration_since(started).as_secs_f64() * 1000.0;
+    let timestamp_ms = wall.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+
+    (monotonic_ms, timestamp_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn reading(proximity: u32, ambient: u32) -> Reading {
+        Reading {
+            monotonic_ms: 0.0,
+            timestamp_ms: 0,
+            proximity,
+            presence: 0,
+            ambient,
+            lux: 0.0,
+            state: "Detected",
+            brightness: Some(100),
+            counters: Counters::default(),
+        }
+    }
+
+    #[test]
+    fn filters_small_changes_until_heartbeat() {
+        let clock = MockClock::new();
+        let mut filter = ChangeFilter::new(10, 50, Duration::from_secs(30));
+        assert!(filter.changed(&reading(100, 500), &clock));
+        assert!(!filter.changed(&reading(109, 549), &clock));
+
+        // thresholds are measured from the last published reading, not the
+        // last seen one
+        assert!(filter.changed(&reading(110, 500), &clock));
+        assert!(filter.changed(&reading(110, 450), &clock));
+
+        let state = Reading { state: "Cleared", ..reading(110, 450) };
+        assert!(filter.changed(&state, &clock));
+        let brightness = Reading { brightness: Some(50), ..state.clone() };
+        assert!(filter.changed(&brightness, &clock));
+
+        clock.advance(Duration::from_secs(29));
+        assert!(!filter.changed(&brightness, &clock));
+        clock.advance(Duration::from_secs(1));
+        assert!(filter.changed(&brightness, &clock));
+    }
+
+    #[test]
+    fn zero_thresholds_publish_everything() {
+        let clock = MockClock::new();
+        let mut filter = ChangeFilter::new(0, 0, Duration::from_secs(30));
+        assert!(filter.changed(&reading(100, 500), &clock));
+        assert!(filter.changed(&reading(100, 500), &clock));
+    }
+}
@@ -0,0 +1,123 @@
+//! A live terminal view of sensor readings, for tuning thresholds over SSH.
+//!
+//! Each channel is drawn as a bar gauge, with `--proximity-range` marked on
+//! the proximity gauge, above a sparkline of recent values. Output uses plain
+//! ANSI escapes rather than a TUI library: the view is redrawn in place, and
+//! the cursor is restored once a shutdown signal (e.g. Ctrl-C) is received.
+
+use std::{
+    collections::VecDeque,
+    env,
+    io::{self, Write},
+    ops::Range,
+    thread,
+    time::Duration,
+};
+
+use color_eyre::Result;
+use vcnl4010::ProximitySensor;
+
+use crate::signal;
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Width used if the terminal width isn't known.
+const DEFAULT_WIDTH: usize = 80;
+
+/// Columns taken by the label and value before each gauge or sparkline.
+const LABEL_WIDTH: usize = 17;
+
+/// Recent values and the observed maximum for one channel.
+struct Channel {
+    name: &'static str,
+    history: VecDeque<u32>,
+    max: u32,
+}
+
+impl Channel {
+    fn new(name: &'static str, max: u32) -> Channel {
+        Channel { name, history: VecDeque::new(), max: max.max(1) }
+    }
+
+    fn push(&mut self, value: u32, capacity: usize) {
+        self.max = self.max.max(value);
+        self.history.push_back(value);
+        while self.history.len() > capacity {
+            self.history.pop_front();
+        }
+    }
+
+    /// Renders the gauge and sparkline lines, marking any thresholds.
+    fn render(&self, out: &mut impl Write, width: usize, thresholds: Option<&Range<u32>>) -> io::Result<()> {
+        let value = self.history.back().copied().unwrap_or_default();
+        let column = |v: u32| ((v as u64 * (width - 1) as u64) / self.max as u64) as usize;
+
+        let mut gauge: Vec<char> = (0..width).map(|i| if i <= column(value) { '=' } else { ' ' }).collect();
+        if let Some(range) = thresholds {
+            for threshold in [range.start, range.end] {
+                if let Some(c) = gauge.get_mut(column(threshold)) {
+                    *c = '|';
+                }
+            }
+        }
+
+        let sparkline: String = self
+            .history
+            .iter()
+            .map(|&v| SPARKS[((v as u64 * (SPARKS.len() - 1) as u64) / self.max as u64) as usize])
+            .collect();
+
+        write!(out, "\x1b[2K{:<10}{value:>6} ", self.name)?;
+        writeln!(out, "{}", gauge.into_iter().collect::<String>())?;
+        writeln!(out, "\x1b[2K{:>LABEL_WIDTH$}{sparkline}", "")
+    }
+}
+
+/// Returns the terminal width from `COLUMNS`, if set.
+fn terminal_width() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Reads the sensor every `interval` and redraws the view until a shutdown
+/// signal is received. The sensor must already be taking measurements.
+pub fn run(sensor: &mut ProximitySensor, interval: Duration, proximity_range: Option<&Range<u32>>) -> Result<()> {
+    let width = terminal_width().saturating_sub(LABEL_WIDTH).max(10);
+
+    // scale the proximity gauge so the thresholds sit in the middle
+    let proximity_max = proximity_range.map(|r| r.end.saturating_mul(2)).unwrap_or(1);
+    let mut proximity = Channel::new("proximity", proximity_max);
+    let mut ambient = Channel::new("ambient", 1);
+
+    let mut out = io::stdout().lock();
+    write!(out, "\x1b[?25l")?;
+
+    let result = (|| -> Result<()> {
+        let mut first = true;
+        while !signal::shutdown_requested() {
+            proximity.push(sensor.read_proximity()? as u32, width);
+            ambient.push(sensor.read_ambient_light()? as u32, width);
+
+            if !first {
+                // move back up over the previous frame
+                write!(out, "\x1b[4A")?;
+            }
+            first = false;
+
+            proximity.render(&mut out, width, proximity_range)?;
+            ambient.render(&mut out, width, None)?;
+            out.flush()?;
+
+            thread::sleep(interval);
+        }
+
+        Ok(())
+    })();
+
+    write!(out, "\x1b[?25h")?;
+    out.flush()?;
+
+    result
+}
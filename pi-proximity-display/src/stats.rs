@@ -0,0 +1,346 @@
+//! Streaming statistics over a run, summarized at shutdown.
+//!
+//! Percentiles are estimated with the P² algorithm (Jain & Chlamtac, 1985),
+//! which tracks five markers per quantile and so needs constant time and
+//! memory per sample regardless of run length. Estimates are approximate,
+//! but generally close for the smooth distributions seen here.
+
+use std::{fs, path::Path, time::{Duration, Instant}};
+
+use color_eyre::{eyre::eyre, Result};
+use serde_derive::Serialize;
+use tracing::info;
+
+/// A P² estimator for a single quantile.
+#[derive(Debug, Clone)]
+struct P2Quantile {
+    p: f64,
+    count: usize,
+
+    /// Marker heights.
+    q: [f64; 5],
+
+    /// Actual marker positions.
+    n: [f64; 5],
+
+    /// Desired marker positions, and their increments per sample.
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    fn new(p: f64) -> P2Quantile {
+        P2Quantile {
+            p,
+            count: 0,
+            q: [0.0; 5],
+            n: [0.0, 1.0, 2.0, 3.0, 4.0],
+            desired: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    fn observe(&mut self, x: f64) {
+        if self.count < 5 {
+            self.q[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.q.sort_by(f64::total_cmp);
+            }
+
+            return;
+        }
+        self.count += 1;
+
+        let k = if x < self.q[0] {
+            self.q[0] = x;
+            0
+        } else if x >= self.q[4] {
+            self.q[4] = x;
+            3
+        } else {
+            (1..5).find(|&i| x < self.q[i]).unwrap_or(4) - 1
+        };
+
+        for n in &mut self.n[k + 1..] {
+            *n += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        for i in 1..4 {
+            let d = self.desired[i] - self.n[i];
+            if (d >= 1.0 && self.n[i + 1] - self.n[i] > 1.0) || (d <= -1.0 && self.n[i - 1] - self.n[i] < -1.0) {
+                let d = d.signum();
+                let parabolic = self.parabolic(i, d);
+                self.q[i] = if self.q[i - 1] < parabolic && parabolic < self.q[i + 1] {
+                    parabolic
+                } else {
+                    self.linear(i, d)
+                };
+                self.n[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.q, &self.n);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let j = if d < 0.0 { i - 1 } else { i + 1 };
+        self.q[i] + d * (self.q[j] - self.q[i]) / (self.n[j] - self.n[i])
+    }
+
+    fn estimate(&self) -> Option<f64> {
+        if self.count >= 5 {
+            return Some(self.q[2]);
+        }
+        if self.count == 0 {
+            return None;
+        }
+
+        // too few samples for the markers, use the exact value
+        let mut seen = self.q[..self.count].to_vec();
+        seen.sort_by(f64::total_cmp);
+        let i = ((seen.len() as f64 * self.p).ceil() as usize).clamp(1, seen.len()) - 1;
+        Some(seen[i])
+    }
+}
+
+/// Running statistics for a single value.
+#[derive(Debug, Clone)]
+pub struct RunningStats {
+    count: u64,
+    min: u32,
+    max: u32,
+    mean: f64,
+    p50: P2Quantile,
+    p90: P2Quantile,
+    p99: P2Quantile,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    pub min: u32,
+    pub max: u32,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl RunningStats {
+    pub fn new() -> RunningStats {
+        RunningStats {
+            count: 0,
+            min: u32::MAX,
+            max: 0,
+            mean: 0.0,
+            p50: P2Quantile::new(0.50),
+            p90: P2Quantile::new(0.90),
+            p99: P2Quantile::new(0.99),
+        }
+    }
+
+    pub fn observe(&mut self, value: u32) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.mean += (value as f64 - self.mean) / self.count as f64;
+
+        for q in [&mut self.p50, &mut self.p90, &mut self.p99] {
+            q.observe(value as f64);
+        }
+    }
+
+    pub fn summary(&self) -> Option<Summary> {
+        Some(Summary {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            p50: self.p50.estimate()?,
+            p90: self.p90.estimate()?,
+            p99: self.p99.estimate()?,
+        })
+    }
+}
+
+/// Cumulative health counters over the daemon's run. These are only kept in
+/// memory, and are reset on restart.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Counters {
+    pub uptime_secs: u64,
+
+    /// Successful sensor reads, including invalid readings.
+    pub reads: u64,
+
+    /// Failed sensor reads, each retried on the next poll.
+    pub read_errors: u64,
+
+    /// Times `--sensor-error-limit` consecutive read errors required the
+    /// sensor to be reopened.
+    pub sensor_recoveries: u64,
+
+    /// Readings dropped as invalid, e.g. by `--reject-saturated-proximity`.
+    pub invalid_readings: u64,
+    pub power_transitions: u32,
+
+    /// With `--verify-power`, power changes the compositor confirmed, and
+    /// those it didn't.
+    pub power_verified: u64,
+    pub power_unverified: u64,
+    pub brightness_writes: u64,
+}
+
+impl Counters {
+    /// Logs the counters with their values as structured fields.
+    pub fn log(&self) {
+        let uptime = humantime::format_duration(Duration::from_secs(self.uptime_secs));
+        info!(
+            uptime_secs = self.uptime_secs,
+            reads = self.reads,
+            read_errors = self.read_errors,
+            sensor_recoveries = self.sensor_recoveries,
+            invalid_readings = self.invalid_readings,
+            power_transitions = self.power_transitions,
+            power_verified = self.power_verified,
+            power_unverified = self.power_unverified,
+            brightness_writes = self.brightness_writes,
+            "counters after {uptime}: {} reads, {} read errors, {} sensor recoveries, {} invalid readings, \
+            {} power transitions ({} verified, {} unverified), {} brightness writes",
+            self.reads,
+            self.read_errors,
+            self.sensor_recoveries,
+            self.invalid_readings,
+            self.power_transitions,
+            self.power_verified,
+            self.power_unverified,
+            self.brightness_writes,
+        );
+    }
+}
+
+/// Statistics collected over the daemon's run.
+pub struct RunStats {
+    started: Instant,
+    proximity: RunningStats,
+    ambient: RunningStats,
+    counters: Counters,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub duration_secs: f64,
+
+    #[serde(flatten)]
+    pub counters: Counters,
+    pub proximity: Option<Summary>,
+    pub ambient: Option<Summary>,
+}
+
+impl RunStats {
+    pub fn new() -> RunStats {
+        RunStats {
+            started: Instant::now(),
+            proximity: RunningStats::new(),
+            ambient: RunningStats::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn observe(&mut self, proximity: u32, ambient: u32) {
+        self.proximity.observe(proximity);
+        self.ambient.observe(ambient);
+    }
+
+    pub fn read(&mut self) {
+        self.counters.reads += 1;
+    }
+
+    pub fn read_error(&mut self) {
+        self.counters.read_errors += 1;
+    }
+
+    pub fn sensor_recovery(&mut self) {
+        self.counters.sensor_recoveries += 1;
+    }
+
+    pub fn invalid_reading(&mut self) {
+        self.counters.invalid_readings += 1;
+    }
+
+    pub fn power_transition(&mut self) {
+        self.counters.power_transitions += 1;
+    }
+
+    pub fn brightness_write(&mut self) {
+        self.counters.brightness_writes += 1;
+    }
+
+    /// Updates the `--verify-power` totals, which the display tracks.
+    pub fn set_power_verification(&mut self, verified: u64, unverified: u64) {
+        self.counters.power_verified = verified;
+        self.counters.power_unverified = unverified;
+    }
+
+    /// The current counters, with uptime filled in.
+    pub fn counters(&self) -> Counters {
+        Counters { uptime_secs: self.started.elapsed().as_secs(), ..self.counters }
+    }
+
+    pub fn summary(&self) -> RunSummary {
+        RunSummary {
+            duration_secs: self.started.elapsed().as_secs_f64(),
+            counters: self.counters(),
+            proximity: self.proximity.summary(),
+            ambient: self.ambient.summary(),
+        }
+    }
+}
+
+impl RunSummary {
+    /// Logs the summary with its values as structured fields.
+    pub fn log(&self) {
+        let duration = humantime::format_duration(Duration::from_secs(self.duration_secs as u64));
+        info!(
+            duration_secs = self.duration_secs,
+            power_transitions = self.counters.power_transitions,
+            "run summary: {} power transitions over {duration}",
+            self.counters.power_transitions,
+        );
+        self.counters.log();
+
+        for (channel, summary) in [("proximity", &self.proximity), ("ambient", &self.ambient)] {
+            let Some(s) = summary else {
+                continue;
+            };
+
+            info!(
+                channel,
+                count = s.count,
+                min = s.min,
+                max = s.max,
+                mean = s.mean,
+                p50 = s.p50,
+                p90 = s.p90,
+                p99 = s.p99,
+                "{channel}: min {} / p50 {:.0} / p90 {:.0} / p99 {:.0} / max {} (mean {:.1}, {} samples)",
+                s.min, s.p50, s.p90, s.p99, s.max, s.mean, s.count,
+            );
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents)
+            .map_err(|e| eyre!("could not write stats file {}: {e}", path.display()))
+    }
+}
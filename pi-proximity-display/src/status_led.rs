@@ -0,0 +1,76 @@
+//! Indicates the daemon's state on a sysfs LED class device, e.g.
+//! `/sys/class/leds/led0`.
+
+use std::{fs, io, path::{Path, PathBuf}};
+
+use tracing::warn;
+
+use crate::State;
+
+/// Blink period used while in the hold window.
+const BLINK_DELAY_MS: u32 = 250;
+
+pub struct StatusLed {
+    path: PathBuf,
+    max_brightness: u32,
+}
+
+impl StatusLed {
+    /// Opens the LED at the given path, returning `None` (after warning) if it
+    /// isn't usable.
+    pub fn open(path: impl AsRef<Path>) -> Option<StatusLed> {
+        let path = path.as_ref();
+        let max_brightness = match fs::read_to_string(path.join("max_brightness")) {
+            Ok(s) => s.trim().parse().unwrap_or(1),
+            Err(e) => {
+                warn!("could not open status LED {}, continuing without it: {e}", path.display());
+                return None;
+            },
+        };
+
+        let mut led = StatusLed { path: path.to_path_buf(), max_brightness };
+        if let Err(e) = led.write("trigger", "none") {
+            warn!("could not control status LED {}, continuing without it: {e}", path.display());
+            return None;
+        }
+
+        Some(led)
+    }
+
+    fn write(&mut self, attribute: &str, value: impl ToString) -> io::Result<()> {
+        fs::write(self.path.join(attribute), value.to_string())
+    }
+
+    fn apply(&mut self, state: &State) -> io::Result<()> {
+        match state {
+            State::Detected | State::Approaching => {
+                self.write("trigger", "none")?;
+                self.write("brightness", self.max_brightness)
+            },
+            State::Cleared => {
+                self.write("trigger", "none")?;
+                self.write("brightness", 0)
+            },
+            State::ClearedTransitioning(_) => {
+                // the timer trigger creates delay_on/delay_off once selected
+                self.write("trigger", "timer")?;
+                self.write("delay_on", BLINK_DELAY_MS)?;
+                self.write("delay_off", BLINK_DELAY_MS)
+            },
+        }
+    }
+
+    /// Updates the LED to reflect the given state: off when cleared, on when
+    /// detected, and blinking during the hold window. Returns false (after
+    /// warning) if the LED could not be written, after which it should be
+    /// dropped.
+    pub fn set(&mut self, state: &State) -> bool {
+        match self.apply(state) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("could not write status LED {}, disabling it: {e}", self.path.display());
+                false
+            },
+        }
+    }
+}
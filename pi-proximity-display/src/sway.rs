@@ -0,0 +1,53 @@
+//! Output power control over sway IPC, used to turn the display on and off
+//! without shelling out to `wlopm`.
+
+use std::env;
+
+use color_eyre::{eyre::eyre, Result};
+use swayipc::Connection;
+
+use crate::display::DisplayPowerMode;
+
+/// Returns true if a sway IPC socket is advertised via `SWAYSOCK`.
+pub fn is_available() -> bool {
+    env::var_os("SWAYSOCK").is_some()
+}
+
+/// Finds the named output in `get_outputs` and returns whether it is powered
+/// on, or `None` if sway doesn't list it.
+fn find_output_power(ipc: &mut Connection, name: &str) -> Result<Option<bool>> {
+    Ok(ipc
+        .get_outputs()?
+        .into_iter()
+        .find(|o| o.name == name)
+        // sway 1.8+ reports `power`; older versions only report `dpms`
+        .map(|o| o.power || o.dpms))
+}
+
+/// Queries the current power state of the named output, if it exists.
+pub fn output_power(name: &str) -> Result<Option<bool>> {
+    find_output_power(&mut Connection::new()?, name)
+}
+
+/// Sets the power state of the named output and confirms with `get_outputs`
+/// that sway reports the requested state afterward. `--verify-power` can
+/// additionally wait for the panel and retry.
+pub fn set_output_power(name: &str, mode: DisplayPowerMode) -> Result<()> {
+    let mut ipc = Connection::new()?;
+
+    let (arg, expected) = match mode {
+        DisplayPowerMode::On => ("on", true),
+        DisplayPowerMode::Off => ("off", false),
+    };
+
+    let command = format!("output {name} dpms {arg}");
+    for outcome in ipc.run_command(&command)? {
+        outcome.map_err(|e| eyre!("sway command '{command}' failed: {e}"))?;
+    }
+
+    match find_output_power(&mut ipc, name)? {
+        Some(actual) if actual == expected => Ok(()),
+        Some(_) => Err(eyre!("sway did not set output {name} power {arg}")),
+        None => Err(eyre!("sway output {name} not found")),
+    }
+}
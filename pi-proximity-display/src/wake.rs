@@ -0,0 +1,79 @@
+//! Waking the display on a sharp rise in ambient light, for
+//! `--ambient-wake-delta`.
+//!
+//! Room lights turning on are a decent proxy for someone entering where the
+//! sensor can't see the doorway. A wake triggers when the latest ambient value
+//! exceeds the lowest value seen within the window by at least the delta; the
+//! history is then reset so a single step only wakes the display once.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::clock::Clock;
+
+#[derive(Debug)]
+pub struct AmbientWake {
+    delta: u32,
+    window: Duration,
+    history: VecDeque<(Instant, u32)>,
+}
+
+impl AmbientWake {
+    pub fn new(delta: u32, window: Duration) -> AmbientWake {
+        AmbientWake { delta, window, history: VecDeque::new() }
+    }
+
+    /// Records an ambient reading, returning whether it is a wake.
+    pub fn observe(&mut self, ambient: u32, clock: &impl Clock) -> bool {
+        let now = clock.now();
+        while self.history.front().is_some_and(|(t, _)| now.saturating_duration_since(*t) > self.window) {
+            self.history.pop_front();
+        }
+
+        let woke = self
+            .history
+            .iter()
+            .map(|(_, v)| *v)
+            .min()
+            .is_some_and(|min| ambient.saturating_sub(min) >= self.delta);
+        if woke {
+            self.history.clear();
+        }
+
+        self.history.push_back((now, ambient));
+        woke
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn wakes_once_on_a_step() {
+        let clock = MockClock::new();
+        let mut wake = AmbientWake::new(100, Duration::from_secs(5));
+        assert!(!wake.observe(50, &clock));
+        clock.advance(Duration::from_secs(1));
+        assert!(!wake.observe(149, &clock));
+        clock.advance(Duration::from_secs(1));
+        assert!(wake.observe(150, &clock));
+
+        // the history was reset, so staying bright doesn't wake again
+        clock.advance(Duration::from_secs(1));
+        assert!(!wake.observe(160, &clock));
+    }
+
+    #[test]
+    fn ignores_rises_slower_than_the_window() {
+        let clock = MockClock::new();
+        let mut wake = AmbientWake::new(100, Duration::from_secs(5));
+        for ambient in [0, 40, 80, 120, 160, 200] {
+            assert!(!wake.observe(ambient, &clock), "at {ambient}");
+            clock.advance(Duration::from_secs(3));
+        }
+    }
+}
@@ -0,0 +1,36 @@
+#!/bin/sh
+# Runs pi-proximity-display against a VCNL4010 emulated by the i2c-stub kernel
+# module, then simulates someone approaching and leaving by rewriting the
+# proximity result registers. Requires root, i2c-tools, and a kernel with
+# CONFIG_I2C_STUB. Extra arguments are passed to pi-proximity-display, e.g.
+# `--backlight-path` for a display to control.
+#
+#   sudo scripts/i2c-stub.sh target/release/pi-proximity-display --proximity-hold 5s
+set -eu
+
+BIN=${1:?usage: $0 path/to/pi-proximity-display [args...]}
+shift
+
+modprobe i2c-stub chip_addr=0x13
+BUS=$(i2cdetect -l | awk '/SMBus stub driver/ { sub("i2c-", "", $1); print $1; exit }')
+if [ -z "$BUS" ]; then
+    echo "could not find the i2c-stub bus" >&2
+    exit 1
+fi
+
+set_proximity() {
+    i2cset -y "$BUS" 0x13 0x87 $(( ($1 >> 8) & 0xff ))
+    i2cset -y "$BUS" 0x13 0x88 $(( $1 & 0xff ))
+}
+
+"$BIN" --emulated --i2c-device "/dev/i2c-$BUS" --proximity-range 2930..2975 "$@" &
+PID=$!
+trap 'kill $PID 2>/dev/null; wait $PID; rmmod i2c-stub' EXIT
+
+sleep 3
+echo "simulating approach"
+set_proximity 3200
+sleep 5
+echo "simulating departure"
+set_proximity 2900
+sleep 30
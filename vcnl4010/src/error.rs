@@ -1,13 +1,47 @@
-use std::backtrace::Backtrace;
+use std::{io, path::PathBuf, time::Duration};
 
 use i2cdev::linux::LinuxI2CError;
 use thiserror::Error;
 
+use crate::{Channel, SensorCommand};
+
 #[derive(Debug, Error)]
 pub enum Error {
 
   #[error("i2c error: {0}")]
-  I2CError(#[from] LinuxI2CError),
+  I2C(#[from] LinuxI2CError),
+
+  #[error("could not select channel {channel} on i2c mux {addr:#04x}: {source}")]
+  MuxSelect {
+    addr: u16,
+    channel: u8,
+    source: LinuxI2CError,
+  },
+
+  #[error("i2c device {} is in use by another process", path.display())]
+  DeviceInUse {
+    path: PathBuf,
+  },
+
+  #[error("i2c device is closed after a failed reopen")]
+  DeviceClosed,
+
+  #[error("could not lock i2c device {}: {source}", path.display())]
+  Lock {
+    path: PathBuf,
+    source: io::Error,
+  },
+
+  #[error("i2c adapter rejected {name}: {source}")]
+  BusConfig {
+    name: &'static str,
+    source: io::Error,
+  },
+
+  #[error("i2c adapter does not support required transfers: {}", missing.join(", "))]
+  UnsupportedAdapter {
+    missing: Vec<&'static str>,
+  },
 
   #[error("unsupported product: product={product}, revision={revision}")]
   InvalidProduct {
@@ -17,6 +51,30 @@ pub enum Error {
 
   #[error("invalid LED current value: {0}")]
   InvalidLEDCurrent(u8),
+
+  #[error("unexpected fuse program ID {actual} (expected {expected}), possibly a clone sensor")]
+  UnexpectedFuseProgId {
+    expected: u8,
+    actual: u8,
+  },
+
+  #[error("measurement did not complete within {0:?}")]
+  MeasurementTimeout(Duration),
+
+  #[error("stale or torn {0} measurement")]
+  StaleMeasurement(Channel),
+
+  #[error("short i2c read: expected {expected} bytes, got {actual}")]
+  ShortRead {
+    expected: usize,
+    actual: usize,
+  },
+
+  #[error("command register did not take: wrote {expected:?}, read back {actual:?} (check wiring and address)")]
+  CommandMismatch {
+    expected: SensorCommand,
+    actual: SensorCommand,
+  },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,13 +1,52 @@
-use std::path::Path;
+use std::{fmt, io, os::fd::AsRawFd, path::{Path, PathBuf}, thread, time::{Duration, Instant}};
 
 use bitfield_struct::bitfield;
-use i2cdev::{core::*, linux::LinuxI2CDevice};
+use i2cdev::{core::*, linux::{LinuxI2CDevice, LinuxI2CError}};
 
 mod error;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 
-use error::{Error, Result};
+pub use error::{Error, Result};
 
 pub const ADDR: u16 = 0x13; // hard-coded
+
+// i2c-dev ioctls, from linux/i2c-dev.h
+const I2C_RETRIES: libc::c_ulong = 0x0701;
+const I2C_TIMEOUT: libc::c_ulong = 0x0702;
+const I2C_FUNCS: libc::c_ulong = 0x0705;
+
+/// I2C adapter functionality flags, as reported by the `I2C_FUNCS` ioctl.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2CFunctionality(pub libc::c_ulong);
+
+impl I2CFunctionality {
+    pub const I2C: libc::c_ulong = 0x0000_0001;
+    pub const SMBUS_READ_BYTE_DATA: libc::c_ulong = 0x0008_0000;
+    pub const SMBUS_WRITE_BYTE_DATA: libc::c_ulong = 0x0010_0000;
+    pub const SMBUS_READ_WORD_DATA: libc::c_ulong = 0x0020_0000;
+    pub const SMBUS_WRITE_WORD_DATA: libc::c_ulong = 0x0040_0000;
+    pub const SMBUS_READ_I2C_BLOCK: libc::c_ulong = 0x0400_0000;
+
+    /// Functionality this driver can't work without.
+    const REQUIRED: &[(libc::c_ulong, &str)] = &[
+        (Self::SMBUS_READ_BYTE_DATA, "SMBus read byte data"),
+        (Self::SMBUS_WRITE_BYTE_DATA, "SMBus write byte data"),
+    ];
+
+    pub fn supports(self, flag: libc::c_ulong) -> bool {
+        self.0 & flag == flag
+    }
+
+    /// Returns the names of any required functionality the adapter lacks.
+    pub fn missing_required(self) -> Vec<&'static str> {
+        Self::REQUIRED
+            .iter()
+            .filter(|(flag, _)| !self.supports(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
 pub const REG_COMMAND: u8 = 0x80;
 pub const REG_PRODUCT_ID: u8 = 0x81;
 pub const REG_PROX_RATE: u8 = 0x82; // note: not supported on vcnl4000
@@ -17,6 +56,12 @@ pub const REG_AMBIENT_LIGHT_RESULT_HIGH: u8 = 0x85; // (2 bytes)
 pub const REG_AMBIENT_LIGHT_RESULT_LOW: u8 = 0x86; // (2 bytes)
 pub const REG_PROXIMITY_RESULT_HIGH: u8 = 0x87; // (2 bytes)
 pub const REG_PROXIMITY_RESULT_LOW: u8 = 0x88; // (2 bytes)
+pub const REG_INTERRUPT_CONTROL: u8 = 0x89;
+pub const REG_LOW_THRESHOLD_HIGH: u8 = 0x8A; // (2 bytes)
+pub const REG_LOW_THRESHOLD_LOW: u8 = 0x8B; // (2 bytes)
+pub const REG_HIGH_THRESHOLD_HIGH: u8 = 0x8C; // (2 bytes)
+pub const REG_HIGH_THRESHOLD_LOW: u8 = 0x8D; // (2 bytes)
+pub const REG_INTERRUPT_STATUS: u8 = 0x8E;
 
 #[bitfield(u8)]
 pub struct SensorCommand {
@@ -52,6 +97,133 @@ pub struct SensorCommand {
     pub config_lock: bool,
 }
 
+impl SensorCommand {
+    /// The bits of the command register that can be written; the rest are
+    /// read-only status bits.
+    pub const WRITABLE_MASK: u8 = 0b0001_1111;
+
+    /// Returns this command with the read-only status bits cleared, as
+    /// written by `set_command_register`.
+    pub fn writable(self) -> SensorCommand {
+        SensorCommand::from_bits(self.into_bits() & Self::WRITABLE_MASK)
+    }
+
+    /// Whether the data-ready bits report fresh data on `channel`.
+    pub fn data_ready(self, channel: Channel) -> bool {
+        match channel {
+            Channel::Proximity => self.proximity_data_ready(),
+            Channel::Ambient => self.ambient_light_data_ready(),
+            Channel::Both => self.proximity_data_ready() && self.ambient_light_data_ready(),
+        }
+    }
+}
+
+#[bitfield(u8)]
+pub struct InterruptControl {
+    /// If set, the threshold interrupt applies to ambient light measurements
+    /// rather than proximity.
+    pub threshold_select_ambient: bool,
+
+    /// If set, raises an interrupt when a measurement falls outside the low
+    /// and high thresholds.
+    pub threshold_enabled: bool,
+
+    /// If set, raises an interrupt whenever ambient light data is ready.
+    pub ambient_ready_enabled: bool,
+
+    /// If set, raises an interrupt whenever proximity data is ready.
+    pub proximity_ready_enabled: bool,
+
+    #[bits(1)]
+    __: u8,
+
+    /// Number of consecutive measurements outside the thresholds required to
+    /// raise a threshold interrupt, as a power of two (i.e. 2^n, 1 to 128);
+    /// see `InterruptCountExceed`.
+    #[bits(3)]
+    pub count_exceed: u8,
+}
+
+/// Number of consecutive measurements outside the thresholds required to raise
+/// a threshold interrupt. The field value `n` corresponds to 2^n measurements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptCountExceed {
+    /// 1 measurement (field value 0)
+    C1,
+
+    /// 2 measurements (field value 1)
+    C2,
+
+    /// 4 measurements (field value 2)
+    C4,
+
+    /// 8 measurements (field value 3)
+    C8,
+
+    /// 16 measurements (field value 4)
+    C16,
+
+    /// 32 measurements (field value 5)
+    C32,
+
+    /// 64 measurements (field value 6)
+    C64,
+
+    /// 128 measurements (field value 7)
+    C128
+}
+
+impl InterruptCountExceed {
+    const ALL: [InterruptCountExceed; 8] = [
+        InterruptCountExceed::C1,
+        InterruptCountExceed::C2,
+        InterruptCountExceed::C4,
+        InterruptCountExceed::C8,
+        InterruptCountExceed::C16,
+        InterruptCountExceed::C32,
+        InterruptCountExceed::C64,
+        InterruptCountExceed::C128,
+    ];
+
+    /// Returns the variant for a number of measurements, which must be a power
+    /// of two from 1 to 128.
+    pub fn from_count(count: u8) -> Option<InterruptCountExceed> {
+        InterruptCountExceed::ALL.into_iter().find(|c| c.count() == count)
+    }
+
+    /// Returns the variant for a `count_exceed` field value.
+    pub fn from_value(value: u8) -> Option<InterruptCountExceed> {
+        InterruptCountExceed::ALL.get(value as usize).copied()
+    }
+
+    pub fn value(self) -> u8 {
+        self as u8
+    }
+
+    /// The number of consecutive measurements required.
+    pub fn count(self) -> u8 {
+        1 << self.value()
+    }
+}
+
+#[bitfield(u8)]
+pub struct InterruptStatus {
+    /// A measurement exceeded the high threshold.
+    pub threshold_high: bool,
+
+    /// A measurement fell below the low threshold.
+    pub threshold_low: bool,
+
+    /// Ambient light data is ready.
+    pub ambient_ready: bool,
+
+    /// Proximity data is ready.
+    pub proximity_ready: bool,
+
+    #[bits(4)]
+    __: u8,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum ProximityMeasurementFrequency {
     /// 1.95 samples/sec
@@ -92,6 +264,37 @@ impl ProximityMeasurementFrequency {
             ProximityMeasurementFrequency::M250 => 7,
         }
     }
+
+    pub fn from_value(value: u8) -> Self {
+        match value & 0b111 {
+            0 => ProximityMeasurementFrequency::M1_95,
+            1 => ProximityMeasurementFrequency::M3_90625,
+            2 => ProximityMeasurementFrequency::M7_8125,
+            3 => ProximityMeasurementFrequency::M16_625,
+            4 => ProximityMeasurementFrequency::M31_25,
+            5 => ProximityMeasurementFrequency::M62_5,
+            6 => ProximityMeasurementFrequency::M125,
+            _ => ProximityMeasurementFrequency::M250,
+        }
+    }
+
+    pub fn samples_per_sec(self) -> f32 {
+        match self {
+            ProximityMeasurementFrequency::M1_95 => 1.95,
+            ProximityMeasurementFrequency::M3_90625 => 3.90625,
+            ProximityMeasurementFrequency::M7_8125 => 7.8125,
+            ProximityMeasurementFrequency::M16_625 => 16.625,
+            ProximityMeasurementFrequency::M31_25 => 31.25,
+            ProximityMeasurementFrequency::M62_5 => 62.5,
+            ProximityMeasurementFrequency::M125 => 125.0,
+            ProximityMeasurementFrequency::M250 => 250.0,
+        }
+    }
+
+    /// The nominal time between self-timed measurements at this rate.
+    pub fn period(self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.samples_per_sec())
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -134,6 +337,97 @@ impl AmbientLightMeasurementFrequency {
             AmbientLightMeasurementFrequency::M10 => 7,
         }
     }
+
+    pub fn from_value(value: u8) -> Self {
+        match value & 0b111 {
+            0 => AmbientLightMeasurementFrequency::M1,
+            1 => AmbientLightMeasurementFrequency::M2,
+            2 => AmbientLightMeasurementFrequency::M3,
+            3 => AmbientLightMeasurementFrequency::M4,
+            4 => AmbientLightMeasurementFrequency::M5,
+            5 => AmbientLightMeasurementFrequency::M6,
+            6 => AmbientLightMeasurementFrequency::M8,
+            _ => AmbientLightMeasurementFrequency::M10,
+        }
+    }
+
+    pub fn samples_per_sec(self) -> f32 {
+        match self {
+            AmbientLightMeasurementFrequency::M1 => 1.0,
+            AmbientLightMeasurementFrequency::M2 => 2.0,
+            AmbientLightMeasurementFrequency::M3 => 3.0,
+            AmbientLightMeasurementFrequency::M4 => 4.0,
+            AmbientLightMeasurementFrequency::M5 => 5.0,
+            AmbientLightMeasurementFrequency::M6 => 6.0,
+            AmbientLightMeasurementFrequency::M8 => 8.0,
+            AmbientLightMeasurementFrequency::M10 => 10.0,
+        }
+    }
+
+    /// The nominal time between self-timed measurements at this rate.
+    pub fn period(self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.samples_per_sec())
+    }
+}
+
+/// Results read together by `ProximitySensor::read_measurements_checked`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurements {
+    pub ambient: u16,
+    pub proximity: u16,
+}
+
+/// A measurement channel, or both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Proximity,
+    Ambient,
+    Both,
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Channel::Proximity => "proximity",
+            Channel::Ambient => "ambient light",
+            Channel::Both => "proximity and ambient light",
+        })
+    }
+}
+
+/// Ambient light resolution per the datasheet, in lux per count. The result
+/// registers always hold the average of the configured number of conversions,
+/// and continuous conversion only affects timing, so this holds for every
+/// `AmbientLightParameters` setting.
+pub const AMBIENT_LUX_PER_COUNT: f32 = 0.25;
+
+#[bitfield(u8)]
+pub struct AmbientLightParameters {
+    /// Number of single conversions averaged into each measurement, as a power
+    /// of two (i.e. 2^n conversions, 1 to 128).
+    #[bits(3)]
+    pub averaging: u8,
+
+    /// If set, enables automatic offset compensation.
+    pub auto_offset_compensation: bool,
+
+    /// Self-timed measurement rate; see `AmbientLightMeasurementFrequency`.
+    #[bits(3)]
+    pub rate: u8,
+
+    /// If set, enables continuous conversion mode, which speeds up
+    /// measurements at the cost of power.
+    pub continuous_conversion: bool,
+}
+
+impl AmbientLightParameters {
+    pub fn with_frequency(self, frequency: AmbientLightMeasurementFrequency) -> Self {
+        self.with_rate(frequency.value())
+    }
+
+    pub fn frequency(self) -> AmbientLightMeasurementFrequency {
+        AmbientLightMeasurementFrequency::from_value(self.rate())
+    }
 }
 
 #[bitfield(u8)]
@@ -159,8 +453,69 @@ impl ProductInfo {
     }
 }
 
-pub struct ProximitySensor {
-    device: LinuxI2CDevice,
+/// The type of an I2C multiplexer, which determines its channel select byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuxKind {
+    /// TCA9548A and similar: one enable bit per channel.
+    Tca9548a,
+
+    /// PCA9547 and similar: an enable bit plus a 3-bit channel number.
+    Pca9547,
+}
+
+/// A multiplexer channel the sensor sits behind. The mux is on the same bus
+/// as `--i2c-device`, at `addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2CMux {
+    pub kind: MuxKind,
+    pub addr: u16,
+
+    /// The channel, 0 to 7.
+    pub channel: u8,
+}
+
+impl I2CMux {
+    /// The control byte written to the mux to select only this channel.
+    pub fn control_byte(self) -> u8 {
+        match self.kind {
+            MuxKind::Tca9548a => 1 << (self.channel & 0b111),
+            MuxKind::Pca9547 => 0b1000 | (self.channel & 0b111),
+        }
+    }
+}
+
+/// An I2C device the sensor can be accessed through: a `LinuxI2CDevice`, or
+/// in tests, a `mock::MockDevice`.
+pub trait SensorDevice: I2CDevice<Error = LinuxI2CError> {}
+
+impl<D: I2CDevice<Error = LinuxI2CError>> SensorDevice for D {}
+
+pub struct ProximitySensor<D: SensorDevice = LinuxI2CDevice> {
+    /// The sensor device, or `None` after a failed `reopen`.
+    device: Option<D>,
+
+    /// The mux the sensor is behind, if any, and a device for its address.
+    mux: Option<(I2CMux, D)>,
+
+    /// The I2C device path and whether it's locked, for `reopen`.
+    path: PathBuf,
+    exclusive: bool,
+
+    /// The adapter's functionality, queried when opened.
+    functionality: I2CFunctionality,
+
+    /// Adapter-level retries and timeout, if configured, reapplied on
+    /// `reopen`.
+    bus_retries: Option<u32>,
+    bus_timeout: Option<Duration>,
+
+    /// If set, 16-bit results are assembled low byte first. The datasheet
+    /// order is high byte first; this only exists for misbehaving clones.
+    swap_result_bytes: bool,
+
+    /// If set, 16-bit registers are accessed with SMBus word transfers when
+    /// the adapter supports them.
+    word_access: bool,
 }
 
 #[bitfield(u8)]
@@ -168,8 +523,12 @@ pub struct LEDCurrent {
     #[bits(6)]
     current: u8,
 
+    /// Read-only ID of the fuse program used to set up and calibrate the part
+    /// at the factory. The datasheet describes the bits but doesn't list
+    /// values, so the value to expect has to be taken from a known-genuine
+    /// part; clones often leave these bits zero or fill them inconsistently.
     #[bits(2)]
-    fuse_prog_id: u8
+    pub fuse_prog_id: u8
 }
 
 impl LEDCurrent {
@@ -181,6 +540,20 @@ impl LEDCurrent {
         Ok(self)
     }
 
+    /// Checks the fuse program ID against a value read from a known-genuine
+    /// part, returning `Error::UnexpectedFuseProgId` if it differs. This is
+    /// only a hint, as differing values may also be a newer revision.
+    pub fn verify_fuse_prog_id(self, expected: u8) -> Result<Self> {
+        if self.fuse_prog_id() != expected {
+            return Err(Error::UnexpectedFuseProgId {
+                expected,
+                actual: self.fuse_prog_id(),
+            });
+        }
+
+        Ok(self)
+    }
+
     pub fn with_current_ma(self, ma: u16) -> Self {
         let val = (ma / 10).clamp(0, 20) as u8;
 
@@ -192,59 +565,534 @@ impl LEDCurrent {
     }
 }
 
-impl ProximitySensor {
+fn lock_exclusive(device: &LinuxI2CDevice, path: &Path) -> Result<()> {
+    // SAFETY: flock has no memory safety requirements; the fd is valid for
+    // the lifetime of `device`, and the lock is released when it's closed
+    let ret = unsafe { libc::flock(device.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret == 0 {
+        return Ok(());
+    }
+
+    let source = io::Error::last_os_error();
+    if source.kind() == io::ErrorKind::WouldBlock {
+        Err(Error::DeviceInUse { path: path.to_path_buf() })
+    } else {
+        Err(Error::Lock { path: path.to_path_buf(), source })
+    }
+}
+
+/// Compares the command register from before and after a result read,
+/// returning the channels whose results were stale: either not ready before
+/// the read, or ready again afterwards, meaning a conversion landed mid-read.
+fn stale_channel(before: SensorCommand, after: SensorCommand) -> Option<Channel> {
+    let stale_proximity = !before.proximity_data_ready() || after.proximity_data_ready();
+    let stale_ambient = !before.ambient_light_data_ready() || after.ambient_light_data_ready();
+    match (stale_proximity, stale_ambient) {
+        (true, true) => Some(Channel::Both),
+        (true, false) => Some(Channel::Proximity),
+        (false, true) => Some(Channel::Ambient),
+        (false, false) => None,
+    }
+}
+
+/// Splits an SMBus word read starting at a register pair's high byte into
+/// (high, low) bytes. SMBus words are transferred low byte first, so the byte
+/// from the first register, which the VCNL4010 uses for the high byte, lands
+/// in the word's low byte.
+fn word_to_register_pair(word: u16) -> [u8; 2] {
+    word.to_le_bytes()
+}
+
+/// The inverse of `word_to_register_pair`, for word writes.
+fn register_pair_to_word(bytes: [u8; 2]) -> u16 {
+    u16::from_le_bytes(bytes)
+}
+
+/// Queries the adapter's functionality, failing if it lacks any transfers this
+/// driver requires.
+fn query_functionality(device: &LinuxI2CDevice) -> Result<I2CFunctionality> {
+    let mut funcs: libc::c_ulong = 0;
+
+    // SAFETY: I2C_FUNCS writes a single unsigned long through the pointer
+    let ret = unsafe { libc::ioctl(device.as_raw_fd(), I2C_FUNCS, &mut funcs) };
+    if ret < 0 {
+        return Err(Error::BusConfig { name: "I2C_FUNCS", source: io::Error::last_os_error() });
+    }
+
+    let functionality = I2CFunctionality(funcs);
+    let missing = functionality.missing_required();
+    if !missing.is_empty() {
+        return Err(Error::UnsupportedAdapter { missing });
+    }
+
+    Ok(functionality)
+}
+
+impl ProximitySensor<LinuxI2CDevice> {
+    /// Opens the sensor on the given I2C device, taking an exclusive advisory
+    /// lock (`flock`) on the device node that is held until the sensor is
+    /// dropped. Fails with `Error::DeviceInUse` if another process holds it,
+    /// since concurrent access interleaves register reads.
     pub fn try_new(i2c_device: impl AsRef<Path>) -> Result<Self> {
+        Self::open(i2c_device.as_ref(), true, None)
+    }
+
+    /// Opens the sensor without locking the I2C device, for setups that
+    /// intentionally share it.
+    pub fn try_new_shared(i2c_device: impl AsRef<Path>) -> Result<Self> {
+        Self::open(i2c_device.as_ref(), false, None)
+    }
+
+    /// Opens the sensor behind a channel of an I2C multiplexer, optionally
+    /// locking the I2C device as with `try_new`.
+    ///
+    /// The mux channel is always selected before the sensor is accessed. With
+    /// an exclusive lock nothing else can change the selection, so this is
+    /// done once when opened (and reopened). Otherwise the channel is selected
+    /// before every register access, as another process may have switched it;
+    /// multi-byte results are still read a byte at a time, so a switch between
+    /// the two can't be ruled out entirely. Mux failures are reported as
+    /// `Error::MuxSelect`, separately from sensor errors.
+    pub fn try_new_with_mux(i2c_device: impl AsRef<Path>, mux: I2CMux, exclusive: bool) -> Result<Self> {
+        Self::open(i2c_device.as_ref(), exclusive, Some(mux))
+    }
+
+    fn open(i2c_device: &Path, exclusive: bool, mux: Option<I2CMux>) -> Result<Self> {
         let device = LinuxI2CDevice::new(i2c_device, ADDR)?;
+        if exclusive {
+            lock_exclusive(&device, i2c_device)?;
+        }
+        let functionality = query_functionality(&device)?;
+        let mux = mux
+            .map(|mux| Ok::<_, Error>((mux, LinuxI2CDevice::new(i2c_device, mux.addr)?)))
+            .transpose()?;
 
-        Ok(ProximitySensor {
-            device,
-        })
+        let mut sensor = ProximitySensor {
+            device: Some(device),
+            mux,
+            functionality,
+            path: i2c_device.to_path_buf(),
+            exclusive,
+            swap_result_bytes: false,
+            word_access: true,
+            bus_retries: None,
+            bus_timeout: None,
+        };
+        if exclusive {
+            sensor.select_mux()?;
+        }
+
+        Ok(sensor)
+    }
+
+    /// Closes and reopens the I2C device, e.g. after the sensor is physically
+    /// reconnected. The sensor's configuration is not restored, since it may
+    /// have lost power; callers should reinitialize it.
+    ///
+    /// The old device is closed first, releasing its lock. If reopening fails,
+    /// including if the lock is now held elsewhere, the sensor stays closed and
+    /// accesses fail with `Error::DeviceClosed` until a later `reopen`
+    /// succeeds, so an unlocked device is never used.
+    pub fn reopen(&mut self) -> Result<()> {
+        self.device = None;
+
+        let device = LinuxI2CDevice::new(&self.path, ADDR)?;
+        if self.exclusive {
+            lock_exclusive(&device, &self.path)?;
+        }
+        self.functionality = query_functionality(&device)?;
+        self.device = Some(device);
+        if let Some((mux, _)) = self.mux {
+            self.mux = Some((mux, LinuxI2CDevice::new(&self.path, mux.addr)?));
+            if self.exclusive {
+                self.select_mux()?;
+            }
+        }
+        if let Some(retries) = self.bus_retries {
+            self.set_bus_retries(retries)?;
+        }
+        if let Some(timeout) = self.bus_timeout {
+            self.set_bus_timeout(timeout)?;
+        }
+
+        Ok(())
+    }
+
+    fn bus_ioctl(&mut self, name: &'static str, request: libc::c_ulong, value: libc::c_ulong) -> Result<()> {
+        let device = self.device.as_ref().ok_or(Error::DeviceClosed)?;
+
+        // SAFETY: these i2c-dev ioctls take their argument by value, and the
+        // fd is valid for the lifetime of `device`
+        let ret = unsafe { libc::ioctl(device.as_raw_fd(), request, value) };
+        if ret < 0 {
+            return Err(Error::BusConfig { name, source: io::Error::last_os_error() });
+        }
+
+        Ok(())
+    }
+
+    /// Sets the number of times the I2C adapter retries a transfer when the
+    /// device doesn't acknowledge (`I2C_RETRIES`). This is handled by the bus
+    /// driver, and not all adapters honor it.
+    pub fn set_bus_retries(&mut self, retries: u32) -> Result<()> {
+        self.bus_ioctl("I2C_RETRIES", I2C_RETRIES, retries as libc::c_ulong)?;
+        self.bus_retries = Some(retries);
+
+        Ok(())
+    }
+
+    /// Sets the I2C adapter's transfer timeout (`I2C_TIMEOUT`), which the
+    /// kernel handles in units of 10ms.
+    pub fn set_bus_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let units = timeout.as_millis().div_ceil(10).max(1);
+        self.bus_ioctl("I2C_TIMEOUT", I2C_TIMEOUT, units as libc::c_ulong)?;
+        self.bus_timeout = Some(timeout);
+
+        Ok(())
+    }
+
+}
+
+impl<D: SensorDevice> ProximitySensor<D> {
+    /// Wraps an already open device, such as a `mock::MockDevice`, whose
+    /// adapter supports `functionality`. Locking, muxes, `reopen`, and the
+    /// adapter-level ioctls are only available for a `LinuxI2CDevice`.
+    pub fn from_device(device: D, functionality: I2CFunctionality) -> Self {
+        ProximitySensor {
+            device: Some(device),
+            mux: None,
+            functionality,
+            path: PathBuf::new(),
+            exclusive: true,
+            swap_result_bytes: false,
+            word_access: true,
+            bus_retries: None,
+            bus_timeout: None,
+        }
+    }
+
+    /// Writes the mux control byte to select the sensor's channel, if it's
+    /// behind a mux.
+    fn select_mux(&mut self) -> Result<()> {
+        if let Some((mux, device)) = &mut self.mux {
+            device
+                .smbus_write_byte(mux.control_byte())
+                .map_err(|source| Error::MuxSelect { addr: mux.addr, channel: mux.channel, source })?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the sensor device for a register access, first selecting the
+    /// mux channel if the I2C device is shared.
+    fn dev(&mut self) -> Result<&mut D> {
+        if !self.exclusive {
+            self.select_mux()?;
+        }
+
+        self.device.as_mut().ok_or(Error::DeviceClosed)
+    }
+
+    /// Returns the mux the sensor is behind, if any.
+    pub fn mux(&self) -> Option<I2CMux> {
+        self.mux.as_ref().map(|(mux, _)| *mux)
+    }
+
+    /// Returns the I2C adapter's functionality flags.
+    pub fn functionality(&self) -> I2CFunctionality {
+        self.functionality
+    }
+
+    /// Sets whether 16-bit result registers are assembled low byte first,
+    /// contrary to the datasheet, for clone modules that behave this way.
+    pub fn set_swap_result_bytes(&mut self, swap: bool) {
+        self.swap_result_bytes = swap;
+    }
+
+    /// Sets whether 16-bit registers may be accessed with SMBus word
+    /// transfers, which the adapter must also support. Enabled by default;
+    /// otherwise each byte is transferred separately.
+    pub fn set_word_access(&mut self, enabled: bool) {
+        self.word_access = enabled;
+    }
+
+    fn use_word_access(&self, flag: libc::c_ulong) -> bool {
+        self.word_access && self.functionality.supports(flag)
+    }
+
+    /// Reads a 16-bit register pair starting at its high byte, returning the
+    /// (high, low) bytes.
+    fn read_register_pair(&mut self, high_reg: u8) -> Result<(u8, u8)> {
+        if self.use_word_access(I2CFunctionality::SMBUS_READ_WORD_DATA) {
+            let [high, low] = word_to_register_pair(self.dev()?.smbus_read_word_data(high_reg)?);
+            return Ok((high, low));
+        }
+
+        let high = self.dev()?.smbus_read_byte_data(high_reg)?;
+        let low = self.dev()?.smbus_read_byte_data(high_reg + 1)?;
+
+        Ok((high, low))
+    }
+
+    /// Writes a big-endian 16-bit register pair starting at its high byte.
+    fn write_register_pair(&mut self, high_reg: u8, value: u16) -> Result<()> {
+        let [high, low] = value.to_be_bytes();
+        if self.use_word_access(I2CFunctionality::SMBUS_WRITE_WORD_DATA) {
+            self.dev()?.smbus_write_word_data(high_reg, register_pair_to_word([high, low]))?;
+        } else {
+            self.dev()?.smbus_write_byte_data(high_reg, high)?;
+            self.dev()?.smbus_write_byte_data(high_reg + 1, low)?;
+        }
+
+        Ok(())
+    }
+
+    fn combine_result(&self, high: u8, low: u8) -> u16 {
+        if self.swap_result_bytes {
+            u16::from_le_bytes([high, low])
+        } else {
+            u16::from_be_bytes([high, low])
+        }
+    }
+
+    /// Triggers a single on-demand proximity measurement and waits up to
+    /// `timeout` for it to complete. Self-timed measurements must be disabled,
+    /// as on-demand measurements are ignored while they run.
+    pub fn measure_proximity_on_demand(&mut self, timeout: Duration) -> Result<u16> {
+        self.set_command_register(SensorCommand::new().with_proximity_on_demand(true))?;
+
+        let start = Instant::now();
+        while !self.read_command_register()?.proximity_data_ready() {
+            if start.elapsed() >= timeout {
+                return Err(Error::MeasurementTimeout(timeout));
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.read_proximity()
+    }
+
+    /// Measures the time until the next self-timed conversion completes on
+    /// `channel`, or on both channels for `Channel::Both`. The channel's
+    /// results are read first to clear its data-ready flags, so this times a
+    /// full measurement period, and includes any time the other channel's
+    /// conversions take. Returns `None` if `timeout` elapsed first.
+    pub fn time_conversion(&mut self, channel: Channel, timeout: Duration) -> Result<Option<Duration>> {
+        if channel != Channel::Ambient {
+            self.read_proximity()?;
+        }
+        if channel != Channel::Proximity {
+            self.read_ambient_light()?;
+        }
+
+        let start = Instant::now();
+        loop {
+            if self.read_command_register()?.data_ready(channel) {
+                return Ok(Some(start.elapsed()));
+            }
+
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Waits up to `timeout` for both proximity and ambient light results to
+    /// become ready, e.g. for the first self-timed measurements after enabling
+    /// them, until which the result registers read as zero. Returns false if
+    /// the timeout elapsed first.
+    pub fn wait_for_data_ready(&mut self, timeout: Duration) -> Result<bool> {
+        self.wait_for_channel_ready(Channel::Both, timeout)
+    }
+
+    /// As `wait_for_data_ready`, but only for `channel`, e.g. if the other
+    /// channel's measurements are disabled.
+    pub fn wait_for_channel_ready(&mut self, channel: Channel, timeout: Duration) -> Result<bool> {
+        let start = Instant::now();
+        loop {
+            if self.read_command_register()?.data_ready(channel) {
+                return Ok(true);
+            }
+
+            if start.elapsed() >= timeout {
+                return Ok(false);
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
     }
 
     pub fn read_command_register(&mut self) -> Result<SensorCommand> {
-        let byte = self.device.smbus_read_byte_data(REG_COMMAND)?;
+        let byte = self.dev()?.smbus_read_byte_data(REG_COMMAND)?;
         let parsed = SensorCommand::from_bits(byte);
 
         Ok(parsed)
     }
 
     pub fn set_command_register(&mut self, command: SensorCommand) -> Result<()> {
-        self.device.smbus_write_byte_data(REG_COMMAND, command.into_bits())?;
+        // the datasheet says the status bits are ignored on write, but don't
+        // rely on every clone honoring that, e.g. when writing back a command
+        // that was read from the sensor
+        self.dev()?.smbus_write_byte_data(REG_COMMAND, command.writable().into_bits())?;
 
         Ok(())
     }
 
+    /// Writes the command register and reads it back, returning an error if
+    /// the measurement enable bits don't match what was written. A write that
+    /// silently doesn't take usually indicates a wiring or addressing problem.
+    pub fn set_command_register_verified(&mut self, command: SensorCommand) -> Result<SensorCommand> {
+        self.set_command_register(command)?;
+
+        let actual = self.read_command_register()?;
+        let matches = actual.self_timed_enabled() == command.self_timed_enabled()
+            && actual.proximity_enabled() == command.proximity_enabled()
+            && actual.ambient_light_enabled() == command.ambient_light_enabled();
+
+        if !matches {
+            return Err(Error::CommandMismatch {
+                expected: command,
+                actual,
+            });
+        }
+
+        Ok(actual)
+    }
+
     /// Reads the product ID and revision, returning the result as a tuple of
     /// (id, rev).
     pub fn read_product(&mut self) -> Result<ProductInfo> {
-        let byte = self.device.smbus_read_byte_data(REG_PRODUCT_ID)?;
+        let byte = self.dev()?.smbus_read_byte_data(REG_PRODUCT_ID)?;
         let pi = ProductInfo::from_bits(byte);
 
         Ok(pi)
     }
 
     pub fn read_ambient_light(&mut self) -> Result<u16> {
-        let high = self.device.smbus_read_byte_data(REG_AMBIENT_LIGHT_RESULT_HIGH)?;
-        let low = self.device.smbus_read_byte_data(REG_AMBIENT_LIGHT_RESULT_LOW)?;
+        let (high, low) = self.read_register_pair(REG_AMBIENT_LIGHT_RESULT_HIGH)?;
 
-        let val = ((high as u16) << 8) | (low as u16);
+        Ok(self.combine_result(high, low))
+    }
 
-        Ok(val)
+    /// Reads the latest ambient light value, converted to lux.
+    pub fn read_ambient_light_lux(&mut self) -> Result<f32> {
+        Ok(self.read_ambient_light()? as f32 * AMBIENT_LUX_PER_COUNT)
+    }
+
+    pub fn read_proximity_rate(&mut self) -> Result<ProximityMeasurementFrequency> {
+        let byte = self.dev()?.smbus_read_byte_data(REG_PROX_RATE)?;
+
+        Ok(ProximityMeasurementFrequency::from_value(byte))
+    }
+
+    pub fn read_ambient_light_parameters(&mut self) -> Result<AmbientLightParameters> {
+        let byte = self.dev()?.smbus_read_byte_data(REG_AMBIENT_LIGHT)?;
+        Ok(AmbientLightParameters::from_bits(byte))
+    }
+
+    pub fn set_ambient_light_parameters(&mut self, params: AmbientLightParameters) -> Result<()> {
+        self.dev()?.smbus_write_byte_data(REG_AMBIENT_LIGHT, params.into_bits())?;
+
+        Ok(())
     }
 
     /// Reads the latest proximity value. This is unitless and depends on the
     /// configured LED current, among other factors.
     pub fn read_proximity(&mut self) -> Result<u16> {
-        let high = self.device.smbus_read_byte_data(REG_PROXIMITY_RESULT_HIGH)?;
-        let low = self.device.smbus_read_byte_data(REG_PROXIMITY_RESULT_LOW)?;
+        let (high, low) = self.read_register_pair(REG_PROXIMITY_RESULT_HIGH)?;
+
+        Ok(self.combine_result(high, low))
+    }
+
+    /// Reads both measurement results, checking that each was fresh and not
+    /// updated partway through the read. Data-ready flags are checked before
+    /// the read, and then again afterwards: since reading a result clears its
+    /// flag, a flag that is set again means a new conversion landed during the
+    /// read, which may have mixed bytes from two conversions. Either case is
+    /// reported as `Error::StaleMeasurement`, and the sample should be
+    /// discarded. Self-timed measurements must be running.
+    ///
+    /// All four result bytes are read in a single I2C block read if the
+    /// adapter supports it, narrowing the window for a torn read, and
+    /// otherwise as two words or byte by byte.
+    pub fn read_measurements_checked(&mut self) -> Result<Measurements> {
+        let before = self.read_command_register()?;
+
+        let block = self.functionality.supports(I2CFunctionality::SMBUS_READ_I2C_BLOCK);
+        let word = self.use_word_access(I2CFunctionality::SMBUS_READ_WORD_DATA);
+        let device = self.dev()?;
+        let bytes = if block {
+            device.smbus_read_i2c_block_data(REG_AMBIENT_LIGHT_RESULT_HIGH, 4)?
+        } else if word {
+            let ambient = word_to_register_pair(device.smbus_read_word_data(REG_AMBIENT_LIGHT_RESULT_HIGH)?);
+            let proximity = word_to_register_pair(device.smbus_read_word_data(REG_PROXIMITY_RESULT_HIGH)?);
+            [ambient, proximity].concat()
+        } else {
+            [
+                REG_AMBIENT_LIGHT_RESULT_HIGH,
+                REG_AMBIENT_LIGHT_RESULT_LOW,
+                REG_PROXIMITY_RESULT_HIGH,
+                REG_PROXIMITY_RESULT_LOW,
+            ]
+            .into_iter()
+            .map(|reg| device.smbus_read_byte_data(reg))
+            .collect::<std::result::Result<Vec<u8>, _>>()?
+        };
+        let [ambient_high, ambient_low, proximity_high, proximity_low] = bytes[..] else {
+            return Err(Error::ShortRead { expected: 4, actual: bytes.len() });
+        };
+
+        let after = self.read_command_register()?;
+        if let Some(channel) = stale_channel(before, after) {
+            return Err(Error::StaleMeasurement(channel));
+        }
+
+        Ok(Measurements {
+            ambient: self.combine_result(ambient_high, ambient_low),
+            proximity: self.combine_result(proximity_high, proximity_low),
+        })
+    }
+
+    pub fn read_interrupt_control(&mut self) -> Result<InterruptControl> {
+        let byte = self.dev()?.smbus_read_byte_data(REG_INTERRUPT_CONTROL)?;
 
-        let val = ((high as u16) << 8) | (low as u16);
+        Ok(InterruptControl::from_bits(byte))
+    }
+
+    pub fn set_interrupt_control(&mut self, control: InterruptControl) -> Result<()> {
+        self.dev()?.smbus_write_byte_data(REG_INTERRUPT_CONTROL, control.into_bits())?;
+
+        Ok(())
+    }
+
+    /// Sets the low and high thresholds used by the threshold interrupt.
+    pub fn set_thresholds(&mut self, low: u16, high: u16) -> Result<()> {
+        self.write_register_pair(REG_LOW_THRESHOLD_HIGH, low)?;
+        self.write_register_pair(REG_HIGH_THRESHOLD_HIGH, high)?;
+
+        Ok(())
+    }
+
+    pub fn read_interrupt_status(&mut self) -> Result<InterruptStatus> {
+        let byte = self.dev()?.smbus_read_byte_data(REG_INTERRUPT_STATUS)?;
 
-        Ok(val)
+        Ok(InterruptStatus::from_bits(byte))
+    }
+
+    /// Clears the given interrupt status flags, releasing the INT pin once no
+    /// flags remain set.
+    pub fn clear_interrupt_status(&mut self, status: InterruptStatus) -> Result<()> {
+        // flags are cleared by writing a 1 to them
+        self.dev()?.smbus_write_byte_data(REG_INTERRUPT_STATUS, status.into_bits())?;
+
+        Ok(())
     }
 
     pub fn read_led_current(&mut self) -> Result<LEDCurrent> {
-        let byte = self.device.smbus_read_byte_data(REG_LED_CURRENT)?;
+        let byte = self.dev()?.smbus_read_byte_data(REG_LED_CURRENT)?;
         let c = LEDCurrent::from_bits(byte);
 
         Ok(c)
@@ -253,8 +1101,182 @@ impl ProximitySensor {
     /// Sets current in milliamps. Values are clamped to 0-200mA.
     pub fn set_led_current_ma(&mut self, current_ma: u16) -> Result<()> {
         let c = LEDCurrent::new().with_current_ma(current_ma);
-        self.device.smbus_write_byte_data(REG_LED_CURRENT, c.into_bits())?;
+        self.dev()?.smbus_write_byte_data(REG_LED_CURRENT, c.into_bits())?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::MockDevice;
+
+    const BYTE_TRANSFERS: libc::c_ulong =
+        I2CFunctionality::SMBUS_READ_BYTE_DATA | I2CFunctionality::SMBUS_WRITE_BYTE_DATA;
+    const WORD_TRANSFERS: libc::c_ulong =
+        BYTE_TRANSFERS | I2CFunctionality::SMBUS_READ_WORD_DATA | I2CFunctionality::SMBUS_WRITE_WORD_DATA;
+    const BLOCK_TRANSFERS: libc::c_ulong = WORD_TRANSFERS | I2CFunctionality::SMBUS_READ_I2C_BLOCK;
+
+    /// Returns a sensor on a mock device, and a handle to the device.
+    fn mock_sensor(functionality: libc::c_ulong) -> (ProximitySensor<MockDevice>, MockDevice) {
+        let device = MockDevice::new();
+        let sensor = ProximitySensor::from_device(device.clone(), I2CFunctionality(functionality));
+
+        (sensor, device)
+    }
+
+    #[test]
+    fn interrupt_count_exceed_round_trips() {
+        for (value, count) in [1u8, 2, 4, 8, 16, 32, 64, 128].into_iter().enumerate() {
+            let c = InterruptCountExceed::from_count(count).unwrap();
+            assert_eq!(c.value(), value as u8);
+            assert_eq!(c.count(), count);
+            assert_eq!(InterruptCountExceed::from_value(value as u8), Some(c));
+        }
+
+        assert_eq!(InterruptCountExceed::from_count(3), None);
+        assert_eq!(InterruptCountExceed::from_count(0), None);
+        assert_eq!(InterruptCountExceed::from_value(8), None);
+    }
+
+    #[test]
+    fn interrupt_count_exceed_round_trips_through_device() {
+        let (mut sensor, device) = mock_sensor(BYTE_TRANSFERS);
+        for count in [1u8, 2, 4, 8, 16, 32, 64, 128] {
+            let c = InterruptCountExceed::from_count(count).unwrap();
+            sensor
+                .set_interrupt_control(InterruptControl::new().with_threshold_enabled(true).with_count_exceed(c.value()))
+                .unwrap();
+            assert_eq!(device.register(REG_INTERRUPT_CONTROL) >> 5, c.value(), "count {count}");
+
+            let control = sensor.read_interrupt_control().unwrap();
+            assert!(control.threshold_enabled());
+            assert_eq!(InterruptCountExceed::from_value(control.count_exceed()), Some(c));
+        }
+    }
+
+    #[test]
+    fn detects_stale_and_torn_reads() {
+        let none = SensorCommand::new();
+        let both = none.with_proximity_data_ready(true).with_ambient_light_data_ready(true);
+
+        // both fresh before the read, and cleared by it
+        assert_eq!(stale_channel(both, none), None);
+
+        // an ambient conversion landed mid-read
+        assert_eq!(stale_channel(both, none.with_ambient_light_data_ready(true)), Some(Channel::Ambient));
+
+        // proximity was never ready
+        assert_eq!(stale_channel(none.with_ambient_light_data_ready(true), none), Some(Channel::Proximity));
+        assert_eq!(stale_channel(none, none), Some(Channel::Both));
+        assert_eq!(stale_channel(both, both), Some(Channel::Both));
+    }
+
+    #[test]
+    fn checked_reads_detect_stale_and_torn_results_on_device() {
+        for functionality in [BYTE_TRANSFERS, WORD_TRANSFERS, BLOCK_TRANSFERS] {
+            let (mut sensor, device) = mock_sensor(functionality);
+            device.set_ambient(1234);
+            device.set_proximity(2345);
+            assert_eq!(
+                sensor.read_measurements_checked().unwrap(),
+                Measurements { ambient: 1234, proximity: 2345 },
+                "functionality {functionality:#x}"
+            );
+
+            // the read cleared both flags, and nothing converted since
+            assert!(matches!(sensor.read_measurements_checked(), Err(Error::StaleMeasurement(Channel::Both))));
+
+            device.set_ambient(1234);
+            assert!(matches!(sensor.read_measurements_checked(), Err(Error::StaleMeasurement(Channel::Proximity))));
+        }
+
+        // a conversion landing during the block read may have torn it
+        let (mut sensor, device) = mock_sensor(BLOCK_TRANSFERS);
+        device.set_ambient(1234);
+        device.set_proximity(2345);
+        device.convert_during_next_read(Channel::Proximity);
+        assert!(matches!(sensor.read_measurements_checked(), Err(Error::StaleMeasurement(Channel::Proximity))));
+
+        // with word reads, between the ambient and proximity words
+        let (mut sensor, device) = mock_sensor(WORD_TRANSFERS);
+        device.set_ambient(1234);
+        device.set_proximity(2345);
+        device.convert_during_next_read(Channel::Ambient);
+        assert!(matches!(sensor.read_measurements_checked(), Err(Error::StaleMeasurement(Channel::Ambient))));
+    }
+
+    #[test]
+    fn led_current_converts_milliamps() {
+        assert_eq!(LEDCurrent::new().with_current_ma(0).into_bits(), 0);
+        assert_eq!(LEDCurrent::new().with_current_ma(200).current(), 20);
+        assert_eq!(LEDCurrent::new().with_current_ma(250).to_milliamps(), 200);
+        assert_eq!(LEDCurrent::new().with_current_ma(15).to_milliamps(), 10);
+    }
+
+    #[test]
+    fn data_ready_per_channel() {
+        let none = SensorCommand::new();
+        let proximity = none.with_proximity_data_ready(true);
+        let both = proximity.with_ambient_light_data_ready(true);
+
+        assert!(!none.data_ready(Channel::Proximity));
+        assert!(proximity.data_ready(Channel::Proximity));
+        assert!(!proximity.data_ready(Channel::Ambient));
+        assert!(!proximity.data_ready(Channel::Both));
+        assert!(both.data_ready(Channel::Both));
+    }
+
+    #[test]
+    fn writable_clears_status_bits() {
+        let command = SensorCommand::new()
+            .with_self_timed_enabled(true)
+            .with_proximity_enabled(true)
+            .with_ambient_light_on_demand(true)
+            .with_proximity_data_ready(true)
+            .with_ambient_light_data_ready(true)
+            .with_config_lock(true);
+        let writable = command.writable();
+        assert_eq!(writable.into_bits(), 0b0001_0011);
+        assert!(!writable.proximity_data_ready());
+        assert!(!writable.ambient_light_data_ready());
+        assert!(!writable.config_lock());
+
+        assert_eq!(SensorCommand::from_bits(0xFF).writable().into_bits(), SensorCommand::WRITABLE_MASK);
+    }
+
+    #[test]
+    fn results_are_assembled_in_the_configured_byte_order() {
+        for functionality in [BYTE_TRANSFERS, WORD_TRANSFERS, BLOCK_TRANSFERS] {
+            let (mut sensor, device) = mock_sensor(functionality);
+            device.set_proximity(0x1234);
+            device.set_ambient(0x5678);
+            assert_eq!(sensor.read_proximity().unwrap(), 0x1234, "functionality {functionality:#x}");
+            assert_eq!(sensor.read_ambient_light().unwrap(), 0x5678);
+
+            sensor.set_swap_result_bytes(true);
+            assert_eq!(sensor.read_proximity().unwrap(), 0x3412);
+            assert_eq!(sensor.read_ambient_light().unwrap(), 0x7856);
+
+            device.set_proximity(0x1234);
+            device.set_ambient(0x5678);
+            assert_eq!(
+                sensor.read_measurements_checked().unwrap(),
+                Measurements { ambient: 0x7856, proximity: 0x3412 }
+            );
+        }
+    }
+
+    #[test]
+    fn register_pairs_swap_smbus_word_bytes() {
+        // the high byte register comes first on the bus, so lands in the
+        // word's low byte
+        assert_eq!(word_to_register_pair(0x3412), [0x12, 0x34]);
+        assert_eq!(register_pair_to_word([0x12, 0x34]), 0x3412);
+
+        for value in [0u16, 1, 0x00FF, 0xFF00, 0x1234, u16::MAX] {
+            assert_eq!(register_pair_to_word(word_to_register_pair(value)), value);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! A simulated VCNL4010 for tests. `MockDevice` implements `I2CDevice` over an
+//! in-memory register file, so the driver's real register access paths run
+//! without hardware.
+
+use std::{cell::RefCell, collections::VecDeque, io, rc::Rc};
+
+use i2cdev::{core::I2CDevice, linux::LinuxI2CError};
+
+use crate::{
+    Channel, SensorCommand, REG_AMBIENT_LIGHT_RESULT_HIGH, REG_AMBIENT_LIGHT_RESULT_LOW, REG_COMMAND,
+    REG_INTERRUPT_STATUS, REG_PRODUCT_ID, REG_PROXIMITY_RESULT_HIGH, REG_PROXIMITY_RESULT_LOW,
+};
+
+/// The product ID register of a genuine VCNL4010: product 2, revision 1.
+const PRODUCT_ID: u8 = 0x21;
+
+#[derive(Debug)]
+struct Registers {
+    values: [u8; 256],
+
+    /// The register the next plain read or write starts at.
+    pointer: u8,
+
+    /// Results for upcoming on-demand proximity measurements.
+    on_demand: VecDeque<u16>,
+
+    /// A conversion to land after the next transfer that reads a result.
+    convert_during_read: Option<Channel>,
+
+    /// The number of upcoming transfers that fail.
+    failures: u32,
+}
+
+/// A simulated VCNL4010. Clones share the same registers, so a test can keep
+/// one to feed in readings while the sensor owns another.
+///
+/// Status bits behave as on the chip: command register writes only change its
+/// writable bits, reading either result register of a channel clears its
+/// data-ready flag, and interrupt status flags are cleared by writing 1s.
+/// On-demand proximity measurements complete immediately, with the values
+/// queued by `queue_on_demand`.
+#[derive(Debug, Clone)]
+pub struct MockDevice {
+    registers: Rc<RefCell<Registers>>,
+}
+
+impl Default for MockDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockDevice {
+    /// Creates a device that reports a genuine VCNL4010, with all other
+    /// registers zeroed.
+    pub fn new() -> MockDevice {
+        let mut values = [0; 256];
+        values[REG_PRODUCT_ID as usize] = PRODUCT_ID;
+
+        MockDevice {
+            registers: Rc::new(RefCell::new(Registers {
+                values,
+                pointer: 0,
+                on_demand: VecDeque::new(),
+                convert_during_read: None,
+                failures: 0,
+            })),
+        }
+    }
+
+    pub fn register(&self, reg: u8) -> u8 {
+        self.registers.borrow().values[reg as usize]
+    }
+
+    /// Sets a register directly, bypassing the write behavior of status bits.
+    pub fn set_register(&self, reg: u8, value: u8) {
+        self.registers.borrow_mut().values[reg as usize] = value;
+    }
+
+    /// Completes a proximity conversion, in the datasheet byte order.
+    pub fn set_proximity(&self, value: u16) {
+        self.registers.borrow_mut().convert(Channel::Proximity, Some(value));
+    }
+
+    /// Completes an ambient light conversion, in the datasheet byte order.
+    pub fn set_ambient(&self, value: u16) {
+        self.registers.borrow_mut().convert(Channel::Ambient, Some(value));
+    }
+
+    /// Queues results for upcoming on-demand proximity measurements. Once none
+    /// remain, on-demand measurements never complete.
+    pub fn queue_on_demand(&self, values: impl IntoIterator<Item = u16>) {
+        self.registers.borrow_mut().on_demand.extend(values);
+    }
+
+    /// Lands a conversion on `channel` right after the next transfer that
+    /// reads a result register, as if it completed partway through the read.
+    /// The results are unchanged, but the channel's data-ready flag is set
+    /// again.
+    pub fn convert_during_next_read(&self, channel: Channel) {
+        self.registers.borrow_mut().convert_during_read = Some(channel);
+    }
+
+    /// Fails the next `count` transfers with an I/O error, as if the sensor
+    /// were disconnected.
+    pub fn fail_next(&self, count: u32) {
+        self.registers.borrow_mut().failures = count;
+    }
+}
+
+impl Registers {
+    /// Sets `channel`'s data-ready flag, and its result if given.
+    fn convert(&mut self, channel: Channel, value: Option<u16>) {
+        let mut command = SensorCommand::from_bits(self.values[REG_COMMAND as usize]);
+        if channel != Channel::Ambient {
+            if let Some(value) = value {
+                self.set_result(REG_PROXIMITY_RESULT_HIGH, value);
+            }
+            command.set_proximity_data_ready(true);
+        }
+        if channel != Channel::Proximity {
+            if let Some(value) = value {
+                self.set_result(REG_AMBIENT_LIGHT_RESULT_HIGH, value);
+            }
+            command.set_ambient_light_data_ready(true);
+        }
+        self.values[REG_COMMAND as usize] = command.into_bits();
+    }
+
+    fn set_result(&mut self, high_reg: u8, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.values[high_reg as usize] = high;
+        self.values[high_reg as usize + 1] = low;
+    }
+
+    /// Fails the transfer if a failure was requested with `fail_next`.
+    fn transfer(&mut self) -> Result<(), LinuxI2CError> {
+        if self.failures == 0 {
+            return Ok(());
+        }
+
+        self.failures -= 1;
+        Err(LinuxI2CError::Io(io::Error::other("simulated transfer failure")))
+    }
+
+    /// Reads the register at the pointer and advances it, returning the value
+    /// and whether it was a result register.
+    fn read_next(&mut self) -> (u8, bool) {
+        let reg = self.pointer;
+        self.pointer = self.pointer.wrapping_add(1);
+
+        let value = self.values[reg as usize];
+        let mut command = SensorCommand::from_bits(self.values[REG_COMMAND as usize]);
+        let result = match reg {
+            REG_PROXIMITY_RESULT_HIGH | REG_PROXIMITY_RESULT_LOW => {
+                command.set_proximity_data_ready(false);
+                true
+            },
+            REG_AMBIENT_LIGHT_RESULT_HIGH | REG_AMBIENT_LIGHT_RESULT_LOW => {
+                command.set_ambient_light_data_ready(false);
+                true
+            },
+            _ => false,
+        };
+        self.values[REG_COMMAND as usize] = command.into_bits();
+
+        (value, result)
+    }
+
+    /// Writes the register at the pointer and advances it.
+    fn write_next(&mut self, value: u8) {
+        let reg = self.pointer;
+        self.pointer = self.pointer.wrapping_add(1);
+
+        match reg {
+            REG_COMMAND => {
+                let status = self.values[REG_COMMAND as usize] & !SensorCommand::WRITABLE_MASK;
+                let written = SensorCommand::from_bits(value & SensorCommand::WRITABLE_MASK);
+                self.values[REG_COMMAND as usize] = written.with_proximity_on_demand(false).into_bits() | status;
+                if written.proximity_on_demand() {
+                    if let Some(result) = self.on_demand.pop_front() {
+                        self.convert(Channel::Proximity, Some(result));
+                    }
+                }
+            },
+            REG_PRODUCT_ID => (),
+            REG_INTERRUPT_STATUS => self.values[REG_INTERRUPT_STATUS as usize] &= !value,
+            _ => self.values[reg as usize] = value,
+        }
+    }
+}
+
+fn unsupported(name: &str) -> LinuxI2CError {
+    LinuxI2CError::Io(io::Error::new(io::ErrorKind::Unsupported, format!("{name} is not simulated")))
+}
+
+impl I2CDevice for MockDevice {
+    type Error = LinuxI2CError;
+
+    fn read(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        let mut registers = self.registers.borrow_mut();
+        registers.transfer()?;
+
+        let mut read_result = false;
+        for byte in data.iter_mut() {
+            let (value, result) = registers.read_next();
+            *byte = value;
+            read_result |= result;
+        }
+        if read_result {
+            if let Some(channel) = registers.convert_during_read.take() {
+                registers.convert(channel, None);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let mut registers = self.registers.borrow_mut();
+        registers.transfer()?;
+
+        if let Some((reg, values)) = data.split_first() {
+            registers.pointer = *reg;
+            for value in values {
+                registers.write_next(*value);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn smbus_write_quick(&mut self, _bit: bool) -> Result<(), Self::Error> {
+        self.registers.borrow_mut().transfer()
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> Result<Vec<u8>, Self::Error> {
+        Err(unsupported("SMBus block read"))
+    }
+
+    fn smbus_read_i2c_block_data(&mut self, register: u8, len: u8) -> Result<Vec<u8>, Self::Error> {
+        let mut data = vec![0; len as usize];
+        self.write(&[register])?;
+        self.read(&mut data)?;
+
+        Ok(data)
+    }
+
+    fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> Result<(), Self::Error> {
+        Err(unsupported("SMBus block write"))
+    }
+
+    fn smbus_write_i2c_block_data(&mut self, register: u8, values: &[u8]) -> Result<(), Self::Error> {
+        self.write(&[&[register], values].concat())
+    }
+
+    fn smbus_process_block(&mut self, _register: u8, _values: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        Err(unsupported("SMBus block process call"))
+    }
+}